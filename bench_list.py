@@ -0,0 +1,69 @@
+#!/usr/bin/env python3
+"""
+Time a recursive list() of the whole cloud drive and measure the memory it
+takes.
+
+Build the extension first (`maturin develop --release`), set credentials and
+run:
+    MEGA_EMAIL=user@example.com
+    MEGA_PASSWORD=yourpassword
+    python bench_list.py [rounds]
+
+Reports the fastest of `rounds` (default 5) calls and the growth of the
+process's peak RSS during the first one, which covers the snapshot taken
+under the session lock as well as the MegaNode objects returned. Run it
+against builds before and after a change on the same account to compare.
+No network traffic happens during the timed part. Peak RSS is only
+available on Unix.
+"""
+
+import asyncio
+import gc
+import os
+import resource
+import sys
+import time
+
+import megalib
+
+
+def peak_rss_mb():
+    peak = resource.getrusage(resource.RUSAGE_SELF).ru_maxrss
+    # kilobytes on Linux, bytes on macOS
+    return peak / (1024 * 1024 if sys.platform == "darwin" else 1024)
+
+
+async def main():
+    email = os.environ.get("MEGA_EMAIL")
+    password = os.environ.get("MEGA_PASSWORD")
+    if not email or not password:
+        print("❌ Please set MEGA_EMAIL and MEGA_PASSWORD environment variables")
+        return 1
+    rounds = int(sys.argv[1]) if len(sys.argv) > 1 else 5
+
+    session = await megalib.MegaSession.login(email, password)
+    gc.collect()
+    before = peak_rss_mb()
+    start = time.perf_counter()
+    nodes = await session.list("/Root", recursive=True)
+    times = [time.perf_counter() - start]
+    growth = peak_rss_mb() - before
+    count = len(nodes)
+    del nodes
+
+    for _ in range(rounds - 1):
+        gc.collect()
+        start = time.perf_counter()
+        nodes = await session.list("/Root", recursive=True)
+        times.append(time.perf_counter() - start)
+        del nodes
+
+    best = min(times)
+    print(f"{count} nodes")
+    print(f"list(recursive=True)  {best * 1000:9.1f} ms  ({count / best:,.0f} nodes/s)")
+    print(f"peak RSS growth       {growth:9.1f} MB  ({growth * 1024 * 1024 / max(count, 1):,.0f} bytes/node)")
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(asyncio.run(main()))
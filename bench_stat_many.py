@@ -0,0 +1,69 @@
+#!/usr/bin/env python3
+"""
+Compare stat() in a loop against stat_many() and stat_many_by_handle().
+
+Build the extension first (`maturin develop --release`), set credentials and
+run:
+    MEGA_EMAIL=user@example.com
+    MEGA_PASSWORD=yourpassword
+    python bench_stat_many.py [count]
+
+The paths and handles are taken from the cloud drive and repeated until
+`count` (default 10000) lookups are made. No network traffic happens during
+the timed part.
+"""
+
+import asyncio
+import itertools
+import os
+import sys
+import time
+
+import megalib
+
+
+async def timed(label, count, coro):
+    start = time.perf_counter()
+    result = await coro
+    elapsed = time.perf_counter() - start
+    print(f"{label:<24} {elapsed * 1000:9.1f} ms  ({count / elapsed:,.0f} lookups/s)")
+    return result, elapsed
+
+
+async def stat_loop(session, paths):
+    return [await session.stat(path) for path in paths]
+
+
+async def main():
+    email = os.environ.get("MEGA_EMAIL")
+    password = os.environ.get("MEGA_PASSWORD")
+    if not email or not password:
+        print("❌ Please set MEGA_EMAIL and MEGA_PASSWORD environment variables")
+        return 1
+    count = int(sys.argv[1]) if len(sys.argv) > 1 else 10_000
+
+    session = await megalib.MegaSession.login(email, password)
+    nodes = await session.list("/Root", recursive=True)
+    if not nodes:
+        print("❌ The cloud drive is empty; upload a few files first")
+        return 1
+
+    handles = [node.handle for node in nodes]
+    handles = list(itertools.islice(itertools.cycle(handles), count))
+    # list() returns no paths, so look up the direct children of /Root by name.
+    top_level = await session.list("/Root")
+    paths = [f"/Root/{node.name}" for node in top_level] or ["/Root"]
+    paths = list(itertools.islice(itertools.cycle(paths), count))
+
+    print(f"{count} lookups over {len(nodes)} nodes\n")
+    looped, loop_time = await timed("stat() loop", count, stat_loop(session, paths))
+    batched, many_time = await timed("stat_many()", count, session.stat_many(paths))
+    await timed("stat_many_by_handle()", count, session.stat_many_by_handle(handles))
+
+    assert [n and n.handle for n in looped] == [n and n.handle for n in batched]
+    print(f"\nstat_many() is {loop_time / many_time:.1f}x faster than the loop")
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(asyncio.run(main()))
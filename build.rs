@@ -0,0 +1,54 @@
+//! Records the version of the megalib core crate for `core_version()`, and
+//! compiles in the capability names of `features()` from
+//! `[package.metadata.megalib.features]` in Cargo.toml.
+
+use std::path::Path;
+
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let lock = Path::new(&manifest_dir).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+
+    let version = std::fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| locked_version(&lock, "megalib"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MEGALIB_CORE_VERSION={}", version);
+
+    let manifest = Path::new(&manifest_dir).join("Cargo.toml");
+    println!("cargo:rerun-if-changed={}", manifest.display());
+    let names = feature_names(&std::fs::read_to_string(&manifest).unwrap());
+    let out = Path::new(&std::env::var("OUT_DIR").unwrap()).join("features.rs");
+    std::fs::write(out, format!("&{:?}", names)).unwrap();
+}
+
+/// Version of package `name` in the contents of a Cargo.lock file.
+fn locked_version(lock: &str, name: &str) -> Option<String> {
+    lock.split("[[package]]").find_map(|package| {
+        let field = |key: &str| {
+            package.lines().find_map(|line| {
+                line.strip_prefix(key)
+                    .and_then(|rest| rest.trim().strip_prefix('='))
+                    .map(|value| value.trim().trim_matches('"'))
+            })
+        };
+        match field("name") {
+            Some(n) if n == name => field("version").map(str::to_string),
+            _ => None,
+        }
+    })
+}
+
+/// Sorted capability names from the contents of Cargo.toml. Each must list
+/// at least one symbol, or check_stubs.py couldn't check it.
+fn feature_names(manifest: &str) -> Vec<String> {
+    let manifest: toml::Table = manifest.parse().expect("Cargo.toml doesn't parse");
+    let features = manifest["package"]["metadata"]["megalib"]["features"]
+        .as_table()
+        .expect("[package.metadata.megalib.features] missing");
+    for (name, symbols) in features {
+        let listed = symbols.as_array().map_or(0, |symbols| symbols.len());
+        assert!(listed > 0, "feature {:?} lists no symbols", name);
+    }
+    features.keys().cloned().collect()
+}
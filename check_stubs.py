@@ -0,0 +1,229 @@
+#!/usr/bin/env python3
+"""
+Check that megalib.pyi matches the compiled extension module.
+
+Build the extension first (`maturin develop`), then run:
+    python check_stubs.py
+
+Every public class, function and method exported by the module must appear
+in the stubs with the same parameter names, and the stubs must not declare
+anything the module doesn't export. Return annotations must agree with the
+"Returns:" section of the docstrings: the class named there, whether None
+can be returned, and lists, dicts, bools, ints, strings and bytes. megalib.features() must report exactly
+the capabilities whose symbols are exported. Exits non-zero on any mismatch.
+"""
+
+import ast
+import inspect
+import re
+import sys
+from pathlib import Path
+
+import megalib
+
+STUB_PATH = Path(__file__).with_name("megalib.pyi")
+CARGO_TOML = Path(__file__).with_name("Cargo.toml")
+
+
+def load_feature_symbols():
+    """Symbols providing each capability reported by megalib.features(),
+    from [package.metadata.megalib.features] in Cargo.toml, which build.rs
+    compiles the names from."""
+    try:
+        import tomllib
+    except ImportError:  # Python < 3.11
+        import tomli as tomllib
+    with CARGO_TOML.open("rb") as f:
+        return tomllib.load(f)["package"]["metadata"]["megalib"]["features"]
+
+
+FEATURE_SYMBOLS = load_feature_symbols()
+
+
+def stub_params(func):
+    args = func.args
+    names = [a.arg for a in args.posonlyargs + args.args + args.kwonlyargs]
+    return [n for n in names if n not in ("self", "cls")]
+
+
+def runtime_params(obj):
+    try:
+        sig = inspect.signature(obj)
+    except (TypeError, ValueError):
+        return None
+    return [n for n in sig.parameters if n not in ("self", "cls", "$self", "$cls")]
+
+
+def load_stubs():
+    text = STUB_PATH.read_text(encoding="utf-8")
+    tree = ast.parse(text)
+    functions, classes, returns = {}, {}, {}
+
+    def annotation(func):
+        if func.returns is None:
+            return None
+        return ast.get_source_segment(text, func.returns)
+
+    for item in tree.body:
+        if isinstance(item, ast.FunctionDef):
+            functions[item.name] = stub_params(item)
+            returns[item.name] = annotation(item)
+        elif isinstance(item, ast.ClassDef):
+            methods = {}
+            for member in item.body:
+                if isinstance(member, ast.FunctionDef):
+                    methods[member.name] = stub_params(member)
+                    returns[f"{item.name}.{member.name}"] = annotation(member)
+                elif isinstance(member, ast.AnnAssign):
+                    methods[member.target.id] = None
+            classes[item.name] = methods
+    return functions, classes, returns
+
+
+def returns_section(doc):
+    """The text of the "Returns:" section of a docstring, or None."""
+    lines = inspect.cleandoc(doc or "").splitlines()
+    for i, line in enumerate(lines):
+        if line.strip() != "Returns:":
+            continue
+        indent = len(line) - len(line.lstrip())
+        section = []
+        for line in lines[i + 1 :]:
+            if line.strip() and len(line) - len(line.lstrip()) <= indent:
+                break
+            section.append(line.strip())
+        return " ".join(section).strip()
+    return None
+
+
+# Leading words of a "Returns:" section and what the annotation must contain.
+RETURN_KINDS = [
+    ("List", "List["),
+    ("Dict", "Dict["),
+    ("True if", "bool"),
+    ("Number of", "int"),
+    ("frozenset", "FrozenSet["),
+]
+
+
+def check_returns(name, stub, doc, classes):
+    """Compare the return annotation `stub` of `name` with the "Returns:"
+    section of its runtime docstring. `classes` are the exported class
+    names. Awaitable[...] is looked through: the docstrings describe what
+    the awaitable gives."""
+    section = returns_section(doc)
+    if section is None or stub is None:
+        return []
+    stub = re.sub(r"^Awaitable\[(.*)\]$", r"\1", stub)
+    errors = []
+    first_line = section.split(";")[0]
+    mentioned = [c for c in re.findall(r"\bMega\w+", section) if c in classes]
+    if mentioned and not re.search(rf"\b{mentioned[0]}\b", stub):
+        errors.append(f"{name}: returns {mentioned[0]} per docstring, stub says {stub}")
+    for cls in re.findall(r"\bMega\w+", stub):
+        if cls not in mentioned:
+            errors.append(f"{name}: stub returns {cls}, docstring doesn't mention it")
+    says_none = re.search(r"\bNone\b", section) is not None
+    optional = "Optional[" in stub or stub == "None"
+    if says_none != optional:
+        errors.append(
+            f"{name}: docstring {'mentions' if says_none else 'never mentions'} None, stub says {stub}"
+        )
+    for words, expected in RETURN_KINDS:
+        if section.startswith(words) and expected not in stub:
+            errors.append(f"{name}: returns {words!r}... per docstring, stub says {stub}")
+    if re.search(r"\bstring\b", first_line) and not mentioned and "str" not in stub:
+        errors.append(f"{name}: returns a string per docstring, stub says {stub}")
+    if re.search(r"\bas bytes\b", first_line) and "bytes" not in stub:
+        errors.append(f"{name}: returns bytes per docstring, stub says {stub}")
+    return errors
+
+
+def has_symbol(symbol):
+    path, _, param = symbol.partition("(")
+    obj = megalib
+    for part in path.split("."):
+        obj = getattr(obj, part, None)
+        if obj is None:
+            return False
+    if not param:
+        return True
+    params = runtime_params(obj)
+    return params is not None and param.rstrip(")") in params
+
+
+def check_features():
+    errors = []
+    reported = set(megalib.features())
+    for name in sorted(reported - set(FEATURE_SYMBOLS)):
+        errors.append(f"features(): {name!r} has no entry in FEATURE_SYMBOLS")
+    for name, symbols in sorted(FEATURE_SYMBOLS.items()):
+        present = [s for s in symbols if has_symbol(s)]
+        if name in reported and len(present) < len(symbols):
+            missing = sorted(set(symbols) - set(present))
+            errors.append(f"features(): {name!r} reported but {missing} not exported")
+        elif name not in reported and present:
+            errors.append(f"features(): {name!r} not reported but {present} exported")
+    return errors
+
+
+def public_names(obj):
+    return {n for n in dir(obj) if not n.startswith("_")}
+
+
+def main():
+    functions, classes, returns = load_stubs()
+    errors = []
+
+    exported = public_names(megalib)
+    declared = set(functions) | set(classes)
+    for name in sorted(exported - declared):
+        errors.append(f"missing from stubs: {name}")
+    for name in sorted(declared - exported):
+        errors.append(f"declared in stubs but not exported: {name}")
+
+    for name, params in functions.items():
+        obj = getattr(megalib, name, None)
+        if obj is None:
+            continue
+        actual = runtime_params(obj)
+        if actual is not None and actual != params:
+            errors.append(f"{name}: stub params {params} != runtime {actual}")
+
+    for cls_name, members in classes.items():
+        cls = getattr(megalib, cls_name, None)
+        if cls is None or issubclass(cls, BaseException):
+            continue
+        runtime_members = public_names(cls)
+        for name in sorted(runtime_members - set(members)):
+            errors.append(f"{cls_name}.{name}: missing from stubs")
+        for name, params in members.items():
+            if name not in runtime_members and not name.startswith("_"):
+                errors.append(f"{cls_name}.{name}: declared in stubs but not exported")
+                continue
+            if params is None:
+                continue
+            actual = runtime_params(getattr(cls, name))
+            if actual is not None and actual != params:
+                errors.append(f"{cls_name}.{name}: stub params {params} != runtime {actual}")
+
+    class_names = {n for n in exported if isinstance(getattr(megalib, n), type)}
+    for name, stub in returns.items():
+        obj = megalib
+        for part in name.split("."):
+            obj = getattr(obj, part, None)
+        if obj is not None:
+            errors.extend(check_returns(name, stub, obj.__doc__, class_names))
+
+    errors.extend(check_features())
+
+    for error in errors:
+        print(f"❌ {error}")
+    if errors:
+        return 1
+    print("✅ megalib.pyi and features() are in sync with the extension module")
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,97 @@
+#!/usr/bin/env python3
+"""
+Hammer one MegaSession from several OS threads at once.
+
+Each worker thread runs its own event loop and calls stat(), list() and
+upload() on the shared session. The check fails on any exception, on
+results that differ from a single-threaded baseline, or if the run doesn't
+finish within the timeout (a deadlock).
+
+Build the extension first (`maturin develop`), set credentials and run:
+    MEGA_EMAIL=user@example.com
+    MEGA_PASSWORD=yourpassword
+    python check_threads.py [threads] [rounds]
+
+Uploads go to a scratch folder /Root/megalib-thread-check-<pid>, which is
+removed afterwards.
+"""
+
+import asyncio
+import os
+import sys
+import tempfile
+from concurrent.futures import ThreadPoolExecutor, wait
+
+import megalib
+
+TIMEOUT = 600
+
+
+def worker(session, folder, baseline, index, rounds, local_dir):
+    async def run():
+        for n in range(rounds):
+            node = await session.stat("/Root")
+            assert node is not None and node.handle == baseline["root"], "stat() mismatch"
+            names = sorted(n.name for n in await session.list("/Root"))
+            assert set(baseline["names"]) <= set(names), "list() lost entries"
+
+            path = os.path.join(local_dir, f"t{index}-{n}.txt")
+            with open(path, "w") as f:
+                f.write(f"thread {index} round {n}\n")
+            result = await session.upload(path, folder)
+            assert result.node is not None, "upload() result has no node"
+        return rounds
+
+    return asyncio.run(run())
+
+
+async def setup(email, password):
+    session = await megalib.MegaSession.login(email, password)
+    folder = f"/Root/megalib-thread-check-{os.getpid()}"
+    await session.mkdir(folder, exist_ok=True)
+    root = await session.stat("/Root")
+    names = [n.name for n in await session.list("/Root")]
+    return session, folder, {"root": root.handle, "names": names}
+
+
+async def count_and_clean(session, folder):
+    uploaded = len(await session.list(folder))
+    await session.rm(folder)
+    return uploaded
+
+
+def main():
+    email = os.environ.get("MEGA_EMAIL")
+    password = os.environ.get("MEGA_PASSWORD")
+    if not email or not password:
+        print("❌ Please set MEGA_EMAIL and MEGA_PASSWORD environment variables")
+        return 1
+    threads = int(sys.argv[1]) if len(sys.argv) > 1 else 8
+    rounds = int(sys.argv[2]) if len(sys.argv) > 2 else 5
+
+    session, folder, baseline = asyncio.run(setup(email, password))
+    with tempfile.TemporaryDirectory() as local_dir, ThreadPoolExecutor(threads) as pool:
+        futures = [
+            pool.submit(worker, session, folder, baseline, i, rounds, local_dir)
+            for i in range(threads)
+        ]
+        done, pending = wait(futures, timeout=TIMEOUT)
+        if pending:
+            print(f"❌ {len(pending)} threads still running after {TIMEOUT}s (deadlock?)")
+            os._exit(1)
+        errors = [f.exception() for f in done if f.exception() is not None]
+
+    uploaded = asyncio.run(count_and_clean(session, folder))
+    for error in errors:
+        print(f"❌ {type(error).__name__}: {error}")
+    if errors:
+        return 1
+    if uploaded != threads * rounds:
+        print(f"❌ expected {threads * rounds} uploads, found {uploaded}")
+        return 1
+    print(f"✅ {threads} threads x {rounds} rounds completed without errors")
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -76,7 +76,8 @@ async def main():
     print("\n📌 4. STORAGE QUOTA")
     print("-" * 40)
 
-    total, used = await session.quota()
+    quota = await session.quota()
+    total, used = quota.total, quota.used
     used_mb = used / 1024 / 1024
     total_gb = total / 1024 / 1024 / 1024
     percent = (used / total) * 100 if total > 0 else 0
@@ -1,47 +1,251 @@
-from typing import List, Tuple
+import os
+from typing import Any, AsyncIterable, AsyncIterator, Awaitable, Callable, Dict, FrozenSet, List, Optional, Tuple, Union
+
+# Local filesystem paths: str, bytes or any os.PathLike such as pathlib.Path.
+LocalPath = Union[str, bytes, "os.PathLike[str]", "os.PathLike[bytes]"]
+
+__version__: str
+
+class MegaError(Exception): ...
+class AccessDeniedError(MegaError): ...
+class OverQuotaError(MegaError): ...
+class LinkTakenDownError(MegaError): ...
+class NotRefreshedError(MegaError): ...
+class NotFoundError(MegaError): ...
+class ReadOnlyError(MegaError): ...
+class PartialTreeError(MegaError): ...
+class QueueCorruptError(MegaError): ...
+class BusinessPolicyError(MegaError): ...
+class MissingKeyError(MegaError): ...
+class DiskFullError(MegaError):
+    bytes_written: int
+    local_path: str
 
 class MegaNode:
     name: str
     handle: str
+    path: Optional[str]
+    parent_handle: Optional[str]
     size: int
     timestamp: int
+    created_ts: int
+    modified_ts: int
     is_file: bool
     is_folder: bool
+    is_takendown: bool
+    owner_handle: Optional[str]
+    owner: Optional[str]
+    fav: bool
+    label: Optional[int]
+    is_shared: bool
+    share_count: int
+    is_exported: bool
+    mime_type: Optional[str]
+    def __eq__(self, other: object) -> bool: ...
+    def __ne__(self, other: object) -> bool: ...
+    def __hash__(self) -> int: ...
+
+class MegaStorageUsage:
+    bytes: int
+    files: int
+    folders: int
+
+class MegaShareResult:
+    status: str
+    email: str
+    user_handle: Optional[str]
+
+class MegaUndecryptableNode:
+    handle: str
+    parent_handle: Optional[str]
+    size: int
+
+class MegaContact:
+    handle: str
+    email: str
+    name: Optional[str]
+
+class MegaDownloadResult:
+    local_path: str
+    bytes_transferred: int
+    bytes_reused_from_resume: int
+    bytes_reverified: int
+    bytes_redownloaded: int
+    verified: bool
+    digest: Optional[str]
+    attempts: int
+    def to_dict(self) -> Dict[str, Any]: ...
+
+class MegaUploadResult:
+    node: Optional[MegaNode]
+    bytes: int
+    skipped: bool
+    def to_dict(self) -> Dict[str, Any]: ...
+
+class MegaQuotaInfo:
+    total: int
+    used: int
+    free: int
+    def to_dict(self) -> Dict[str, Any]: ...
+
+class MegaTransferItem:
+    path: str
+    handle: Optional[str]
+    action: str
+    bytes: int
+    detail: Optional[str]
+    error: Optional[str]
+    error_type: Optional[str]
+    duration: float
+    ok: bool
+    def to_dict(self) -> Dict[str, Any]: ...
+    @staticmethod
+    def from_dict(data: Dict[str, Any]) -> MegaTransferItem: ...
+
+class MegaTransferSummary:
+    items: List[MegaTransferItem]
+    bytes: int
+    duration: float
+    def ok(self) -> List[MegaTransferItem]: ...
+    def failed(self) -> List[MegaTransferItem]: ...
+    def to_dict(self) -> Dict[str, Any]: ...
+    @staticmethod
+    def from_dict(data: Dict[str, Any]) -> MegaTransferSummary: ...
+    def __len__(self) -> int: ...
+
+class MegaReadStream:
+    size: int
+    def __aiter__(self) -> AsyncIterator[bytes]: ...
+    def __anext__(self) -> Awaitable[bytes]: ...
+    def close(self) -> None: ...
+    def __aenter__(self) -> Awaitable[MegaReadStream]: ...
+    def __aexit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> Awaitable[bool]: ...
+
+class MegaExposure:
+    kind: str
+    path: str
+    handle: str
+    url: Optional[str]
+    recipient: Optional[str]
+    access_level: Optional[int]
+    def to_dict(self) -> Dict[str, Any]: ...
+
+class MegaExposureReport:
+    entries: List[MegaExposure]
+    def of_kind(self, kind: str) -> List[MegaExposure]: ...
+    def to_dict(self) -> Dict[str, Any]: ...
+    def __len__(self) -> int: ...
+
+class MegaQueueEntry:
+    id: int
+    kind: str
+    remote_path: str
+    local_path: str
+    state: str
+    error: Optional[str]
 
 class MegaSession:
     @staticmethod
-    async def login(email: str, password: str, proxy: str | None = None) -> "MegaSession": ...
-    @staticmethod
-    async def load(path: str) -> "MegaSession | None": ...
-    
-    async def refresh(self) -> None: ...
-    async def save(self, path: str) -> None: ...
-    async def stat(self, path: str) -> MegaNode | None: ...
-    async def list(self, path: str, recursive: bool = False) -> List[MegaNode]: ...
-    async def quota(self) -> Tuple[int, int]: ...
-    async def mkdir(self, path: str) -> None: ...
-    async def rename(self, path: str, new_name: str) -> None: ...
-    async def mv(self, source: str, dest: str) -> None: ...
-    async def rm(self, path: str) -> None: ...
-    async def export(self, path: str) -> str: ...
-    async def upload(self, local_path: str, remote_path: str) -> str: ...
-    async def download(self, remote_path: str, local_path: str) -> str: ...
-    async def get_email(self) -> str: ...
-    async def get_name(self) -> str | None: ...
-    async def get_handle(self) -> str: ...
-    async def set_workers(self, workers: int) -> None: ...
-    async def set_resume(self, enabled: bool) -> None: ...
-    async def enable_previews(self, enabled: bool) -> None: ...
-    async def share_folder(self, path: str, email: str, access_level: int) -> None: ...
-    async def list_contacts(self) -> List[MegaNode]: ...
-    async def change_password(self, new_password: str) -> None: ...
-    async def download_to_file(self, remote_path: str, local_path: str) -> str: ...
-    async def upload_resumable(self, local_path: str, remote_path: str) -> str: ...
+    def login(email: str, password: str, proxy: Optional[str] = None, auto_refresh: bool = True, *, timeout: Optional[float] = None) -> Awaitable[MegaSession]: ...
+    @staticmethod
+    def load(path: LocalPath, auto_refresh: bool = True) -> Awaitable[Optional[MegaSession]]: ...
+    @staticmethod
+    def load_from_string(data: str, auto_refresh: bool = True) -> Awaitable[MegaSession]: ...
+    @staticmethod
+    def create_ephemeral() -> Awaitable[MegaSession]: ...
+
+    def read_only(self) -> MegaSession: ...
+    is_read_only: bool
+    def refresh(self, strict: bool = False, max_nodes: Optional[int] = None, *, timeout: Optional[float] = None) -> Awaitable[None]: ...
+    is_partial_tree: bool
+    def refresh_subtree(self, path_or_handle: str) -> Awaitable[int]: ...
+    def undecryptable_nodes(self) -> Awaitable[List[MegaUndecryptableNode]]: ...
+    def apply_node_update(self, node_json: str) -> Awaitable[MegaNode]: ...
+    def forget_node(self, handle: str) -> Awaitable[bool]: ...
+    def save(self, path: LocalPath) -> Awaitable[None]: ...
+    def save_to_string(self) -> Awaitable[str]: ...
+    def stat(self, path: str) -> Awaitable[Optional[MegaNode]]: ...
+    def canonical_path(self, path_or_handle: str) -> Awaitable[str]: ...
+    def stat_many(self, paths: List[str]) -> Awaitable[List[Optional[MegaNode]]]: ...
+    def stat_many_by_handle(self, handles: List[str]) -> Awaitable[List[Optional[MegaNode]]]: ...
+    def list(self, path: str, recursive: bool = False, only_available: bool = False, only_favorites: bool = False, sort: str = "name") -> Awaitable[List[MegaNode]]: ...
+    def list_folders(self, path: str) -> Awaitable[List[Tuple[MegaNode, int]]]: ...
+    def search(self, query: str, path: str = "/", case_sensitive: bool = False, regex: bool = False) -> Awaitable[List[MegaNode]]: ...
+    def glob(self, pattern: str, case_sensitive: bool = True, files_only: bool = False) -> Awaitable[List[MegaNode]]: ...
+    def quota(self, *, timeout: Optional[float] = None) -> Awaitable[MegaQuotaInfo]: ...
+    def usage_stats(self) -> Dict[str, int]: ...
+    def reset_usage_stats(self) -> Dict[str, int]: ...
+    def remaining_storage(self) -> Awaitable[int]: ...
+    def roots(self) -> Awaitable[Dict[str, MegaNode]]: ...
+    def storage_breakdown(self) -> Awaitable[Dict[str, MegaStorageUsage]]: ...
+    def mkdir(self, path: str, exist_ok: bool = False, *, timeout: Optional[float] = None) -> Awaitable[MegaNode]: ...
+    def create_path(self, path: str) -> Awaitable[List[Tuple[MegaNode, bool]]]: ...
+    def rename(self, path: str, new_name: str, *, timeout: Optional[float] = None) -> Awaitable[None]: ...
+    def mv(self, source: str, dest: str, *, timeout: Optional[float] = None) -> Awaitable[None]: ...
+    def rm(self, path: str, *, permanent: bool = False, timeout: Optional[float] = None) -> Awaitable[None]: ...
+    def export(self, path: str, *, timeout: Optional[float] = None) -> Awaitable[str]: ...
+    def list_trash(self) -> Awaitable[List[MegaNode]]: ...
+    def restore(self, path_or_handle: str, dest: Optional[str] = None) -> Awaitable[MegaNode]: ...
+    def empty_trash(self) -> Awaitable[int]: ...
+    def get_link(self, path_or_handle: str) -> Awaitable[Optional[str]]: ...
+    def unexport(self, path: str) -> Awaitable[bool]: ...
+    def export_protected(self, path: str, password: str) -> Awaitable[str]: ...
+    def set_favorite(self, path_or_handle: str, favorite: bool) -> Awaitable[None]: ...
+    def set_label(self, path_or_handle: str, label: Optional[int]) -> Awaitable[None]: ...
+    def find_by_fingerprint(self, local_path: LocalPath) -> Awaitable[List[MegaNode]]: ...
+    def get_node_attr(self, path_or_handle: str, key: str) -> Awaitable[Optional[str]]: ...
+    def set_node_attr(self, path_or_handle: str, key: str, value: Optional[str]) -> Awaitable[None]: ...
+    def get_raw_attributes(self, path_or_handle: str) -> Awaitable[Dict[str, Any]]: ...
+    def set_raw_attribute(self, path_or_handle: str, key: str, value: Any) -> Awaitable[None]: ...
+    def upload(self, local_path: LocalPath, remote_path: str, precheck_quota: bool = False, atomic: bool = False, trash_existing: bool = False, progress: Optional[Callable[[int, int], None]] = None, *, skip_duplicates: bool = False, timeout: Optional[float] = None) -> Awaitable[MegaUploadResult]: ...
+    def download(self, remote_path: str, local_path: LocalPath, digest: Optional[str] = None, ignore_space_check: bool = False, progress: Optional[Callable[[int, int], None]] = None, *, timeout: Optional[float] = None) -> Awaitable[MegaDownloadResult]: ...
+    def open_read(self, remote_path: str, chunk_size: int = 1048576) -> Awaitable[MegaReadStream]: ...
+    def download_folder(self, remote_path: str, local_dir: LocalPath, progress: Optional[Callable[[int, int, str, int, int], None]] = None) -> Awaitable[MegaTransferSummary]: ...
+    def download_bytes(self, remote_path: str, max_size: Optional[int] = 67108864, *, offset: int = 0, length: Optional[int] = None, timeout: Optional[float] = None) -> Awaitable[bytes]: ...
+    def get_email(self) -> Awaitable[str]: ...
+    def get_name(self) -> Awaitable[Optional[str]]: ...
+    def get_handle(self) -> Awaitable[str]: ...
+    def set_timeout(self, seconds: Optional[float]) -> Awaitable[None]: ...
+    def set_bandwidth_limit(self, bytes_per_second: Optional[int]) -> Awaitable[None]: ...
+    def set_workers(self, workers: int) -> Awaitable[None]: ...
+    def set_resume(self, enabled: bool) -> Awaitable[None]: ...
+    def set_temp_dir(self, path: Optional[LocalPath]) -> Awaitable[None]: ...
+    def set_queue_path(self, path: Optional[LocalPath]) -> Awaitable[None]: ...
+    def queue_download(self, remote_path: str, local_path: LocalPath) -> Awaitable[MegaQueueEntry]: ...
+    def queue_upload(self, local_path: LocalPath, remote_path: str) -> Awaitable[MegaQueueEntry]: ...
+    def queue_status(self) -> Awaitable[List[MegaQueueEntry]]: ...
+    def run_queue(self, retry_failed: bool = False) -> Awaitable[MegaTransferSummary]: ...
+    def enable_previews(self, enabled: bool) -> Awaitable[None]: ...
+    def share_folder(self, path: str, email: str, access_level: int) -> Awaitable[MegaShareResult]: ...
+    def audit_exposure(self) -> Awaitable[MegaExposureReport]: ...
+    def revoke_all(self, kinds: Optional[List[str]] = None, dry_run: bool = True) -> Awaitable[MegaTransferSummary]: ...
+    def list_contacts(self) -> Awaitable[List[MegaNode]]: ...
+    def resolve_user(self, handle: str) -> Awaitable[Optional[MegaContact]]: ...
+    def change_password(self, new_password: str) -> Awaitable[None]: ...
+    def download_to_file(self, remote_path: str, local_path: LocalPath, ignore_space_check: bool = False, resume_verify: bool = False, verify_progress: Optional[Callable[[int, int], None]] = None, progress: Optional[Callable[[int, int], None]] = None, *, digest: Optional[str] = None, timeout: Optional[float] = None) -> Awaitable[MegaDownloadResult]: ...
+    def upload_resumable(self, local_path: LocalPath, remote_path: str, precheck_quota: bool = False, progress: Optional[Callable[[int, int], None]] = None, *, timeout: Optional[float] = None) -> Awaitable[MegaUploadResult]: ...
+    def upload_folder(self, local_dir: LocalPath, remote_dir: str, follow_symlinks: bool = False, progress: Optional[Callable[[int, int, str, int, int], None]] = None, use_ignore_files: bool = False) -> Awaitable[MegaTransferSummary]: ...
+    def upload_bytes(self, data: Any, remote_dir: str, filename: str, *, timeout: Optional[float] = None) -> Awaitable[MegaNode]: ...
+    def upload_stream(self, source: Union[Any, AsyncIterable[bytes]], remote_dir: str, name: str, size: Optional[int] = None) -> Awaitable[MegaNode]: ...
+
+class MegaSessionSync:
+    session: MegaSession
+    def __init__(self, session: MegaSession) -> None: ...
+    @staticmethod
+    def login(email: str, password: str, proxy: Optional[str] = None, auto_refresh: bool = True, *, timeout: Optional[float] = None) -> MegaSessionSync: ...
+    @staticmethod
+    def load(path: LocalPath, auto_refresh: bool = True) -> Optional[MegaSessionSync]: ...
+    @staticmethod
+    def load_from_string(data: str, auto_refresh: bool = True) -> MegaSessionSync: ...
+    @staticmethod
+    def create_ephemeral() -> MegaSessionSync: ...
+    # Every other MegaSession method, returning its result instead of an awaitable.
+    def __getattr__(self, name: str) -> Callable[..., Any]: ...
 
 class MegaRegistrationState:
     def serialize(self) -> str: ...
     @staticmethod
-    def deserialize(s: str) -> "MegaRegistrationState": ...
+    def deserialize(s: str) -> MegaRegistrationState: ...
 
 class MegaPublicFile:
     name: str
@@ -49,12 +253,41 @@ class MegaPublicFile:
     handle: str
 
 class MegaPublicFolder:
-    async def list(self, path: str) -> List[MegaNode]: ...
-    async def download(self, remote_path: str, local_path: str) -> str: ...
+    def link_for(self, path_or_handle: str) -> str: ...
+    def stat(self, path: str) -> Awaitable[Optional[MegaNode]]: ...
+    def list(self, path: str, recursive: bool = False) -> Awaitable[List[MegaNode]]: ...
+    def list_folders(self, path: str) -> Awaitable[List[Tuple[MegaNode, int]]]: ...
+    def export_tree_json(self, local_path: Optional[LocalPath] = None) -> Awaitable[Optional[str]]: ...
+    def download(self, remote_path: str, local_path: LocalPath, digest: Optional[str] = None, ignore_space_check: bool = False, progress: Optional[Callable[[int, int], None]] = None) -> Awaitable[MegaDownloadResult]: ...
+
+def register(email: str, password: str, name: str) -> Awaitable[MegaRegistrationState]: ...
+def verify_registration(state: MegaRegistrationState, signup_key: str) -> Awaitable[None]: ...
+def wait_for_confirmation(
+    state: MegaRegistrationState,
+    fetch_key: Callable[[], Union[Optional[str], Awaitable[Optional[str]]]],
+    *,
+    email: str,
+    password: str,
+    poll_interval_secs: float = 10.0,
+    timeout_secs: float = 600.0,
+) -> Awaitable[MegaSession]: ...
+def upload_anonymous(local_path: LocalPath) -> Awaitable[str]: ...
+
+def get_public_file_info(url: str, key: Optional[str] = None, password: Optional[str] = None) -> Awaitable[MegaPublicFile]: ...
+def download_public_file(url: str, local_path: LocalPath, digest: Optional[str] = None, key: Optional[str] = None, password: Optional[str] = None, ignore_space_check: bool = False) -> Awaitable[MegaDownloadResult]: ...
+def open_folder(url: str, key: Optional[str] = None, password: Optional[str] = None) -> Awaitable[MegaPublicFolder]: ...
+def fetch_public_file(url: str, local_path: LocalPath, *, key: Optional[str] = None, password: Optional[str] = None, retries: int = 5, verify: bool = True, ignore_space_check: bool = False) -> Awaitable[MegaDownloadResult]: ...
+def get_public_file_info_by_handle(handle: str, key: str) -> Awaitable[MegaPublicFile]: ...
+def download_public_file_bytes(url: str, max_size: Optional[int] = 67108864, *, key: Optional[str] = None, password: Optional[str] = None) -> Awaitable[bytes]: ...
+def download_public_file_by_handle(handle: str, key: str, local_path: LocalPath, digest: Optional[str] = None, ignore_space_check: bool = False) -> Awaitable[MegaDownloadResult]: ...
+def open_folder_by_handle(handle: str, key: str) -> Awaitable[MegaPublicFolder]: ...
+def public_folder_manifest(url: str, local_path: Optional[LocalPath] = None, key: Optional[str] = None, password: Optional[str] = None) -> Awaitable[Optional[str]]: ...
 
-async def register(email: str, password: str, name: str) -> MegaRegistrationState: ...
-async def verify_registration(state: MegaRegistrationState, signup_key: str) -> None: ...
+def set_api_rate_limit(requests_per_second: Optional[float], burst: int = 1) -> None: ...
+def set_verify_threads(threads: Optional[int] = None) -> None: ...
+def api_stats() -> Dict[str, int]: ...
+def configure_runtime(worker_threads: Optional[int] = None, max_blocking_threads: Optional[int] = None) -> None: ...
+def cleanup_temp(path: Optional[LocalPath] = None, max_age: float = 86400.0) -> int: ...
 
-async def get_public_file_info(url: str) -> MegaPublicFile: ...
-async def download_public_file(url: str, local_path: str) -> str: ...
-async def open_folder(url: str) -> MegaPublicFolder: ...
+def core_version() -> str: ...
+def features() -> FrozenSet[str]: ...
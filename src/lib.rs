@@ -1,17 +1,450 @@
 use ::megalib::{Node, NodeType, RegistrationState, Session};
+use pyo3::create_exception;
 use pyo3::prelude::*;
+use pyo3::pyclass::CompareOp;
+use pyo3::PyTypeInfo;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+create_exception!(
+    megalib,
+    MegaError,
+    pyo3::exceptions::PyException,
+    "Base class for errors reported by MEGA."
+);
+create_exception!(
+    megalib,
+    AccessDeniedError,
+    MegaError,
+    "The account lacks the access level required for the operation."
+);
+create_exception!(
+    megalib,
+    OverQuotaError,
+    MegaError,
+    "The account doesn't have enough storage quota left."
+);
+create_exception!(
+    megalib,
+    LinkTakenDownError,
+    MegaError,
+    "The node or link was taken down by MEGA and can't be downloaded."
+);
+create_exception!(
+    megalib,
+    BusinessPolicyError,
+    MegaError,
+    "The business account's administrator doesn't allow the operation, or the\n\
+     business account is suspended. Not a bug in the application."
+);
+create_exception!(
+    megalib,
+    MissingKeyError,
+    MegaError,
+    "A public link has no decryption key or needs a password that wasn't given."
+);
+create_exception!(
+    megalib,
+    DiskFullError,
+    MegaError,
+    "The local disk doesn't have room for a download. The exception has\n\
+     bytes_written and local_path attributes."
+);
+create_exception!(
+    megalib,
+    NotFoundError,
+    MegaError,
+    "The node doesn't exist (any more) on MEGA."
+);
+create_exception!(
+    megalib,
+    NotRefreshedError,
+    MegaError,
+    "The file tree hasn't been fetched yet; call await session.refresh() first."
+);
+create_exception!(
+    megalib,
+    PartialTreeError,
+    MegaError,
+    "The operation needs the whole file tree, but refresh() stopped at max_nodes."
+);
+create_exception!(
+    megalib,
+    QueueCorruptError,
+    MegaError,
+    "The transfer queue journal is damaged. It is left untouched for inspection."
+);
+create_exception!(
+    megalib,
+    ReadOnlyError,
+    MegaError,
+    "The session was opened with read_only() and can't change anything."
+);
+
+/// MEGA API error code for "resource does not exist".
+const ENOENT: i32 = -9;
+/// MEGA API error code for "access violation".
+const EACCESS: i32 = -11;
+/// MEGA API error code for "resource already exists".
+const EEXIST: i32 = -12;
+/// MEGA API error code for "resource administratively blocked" (taken down).
+const EBLOCKED: i32 = -16;
+/// MEGA API error code for "over storage quota".
+const EOVERQUOTA: i32 = -17;
+/// MEGA API error code for "only the master user of a business account may
+/// do this".
+const EMASTERONLY: i32 = -27;
+/// MEGA API error code for "business account suspended (payment past due)".
+const EBUSINESSPASTDUE: i32 = -28;
+
+/// Share access levels, as used by `share_folder()`.
+const ACCESS_READ_WRITE: i32 = 1;
+const ACCESS_FULL: i32 = 2;
+
+/// Convert a megalib error into a Python exception.
+///
+/// API errors with a dedicated exception class are mapped to it, everything
+/// else is raised as `E`.
+fn mega_err<E: PyTypeInfo>(e: ::megalib::MegaError) -> PyErr {
+    match &e {
+        ::megalib::MegaError::ApiError { code: EACCESS, .. } => {
+            AccessDeniedError::new_err(e.to_string())
+        }
+        ::megalib::MegaError::ApiError { code: EBLOCKED, .. } => {
+            LinkTakenDownError::new_err(e.to_string())
+        }
+        ::megalib::MegaError::ApiError {
+            code: EOVERQUOTA, ..
+        } => OverQuotaError::new_err(e.to_string()),
+        ::megalib::MegaError::ApiError { code: ENOENT, .. } => {
+            NotFoundError::new_err(e.to_string())
+        }
+        ::megalib::MegaError::ApiError {
+            code: EMASTERONLY, ..
+        } => BusinessPolicyError::new_err(format!(
+            "{}: only the business account's administrator may do this",
+            e
+        )),
+        ::megalib::MegaError::ApiError {
+            code: EBUSINESSPASTDUE,
+            ..
+        } => BusinessPolicyError::new_err(format!(
+            "{}: the business account is suspended; its administrator has to \
+             settle the payment",
+            e
+        )),
+        _ => PyErr::new::<E, _>(e.to_string()),
+    }
+}
+
+/// Path of the cloud drive root; list("/") lists its children.
+const CLOUD_PATH: &str = "/Root";
+
+/// Virtual path of the vault, where MEGA's backup feature stores device
+/// backups. It is the account's inbox root under its current name.
+const VAULT_PATH: &str = "/Vault";
+/// Path of the inbox root in megalib's tree.
+const INBOX_PATH: &str = "/Inbox";
+
+/// Translate virtual root paths ("/Vault/...") into megalib's tree paths.
+fn expand_virtual_path(path: &str) -> std::borrow::Cow<'_, str> {
+    match path.strip_prefix(VAULT_PATH) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            std::borrow::Cow::Owned(format!("{}{}", INBOX_PATH, rest))
+        }
+        _ => std::borrow::Cow::Borrowed(path),
+    }
+}
+
+/// Fail with AccessDeniedError if `path` lies below an incoming share whose
+/// access level is known to be lower than `required`, or inside the vault,
+/// which is read-only to protect backup state.
+///
+/// This only looks at the local tree, so it never touches the network.
+fn ensure_access(session: &Session, path: &str, required: i32) -> PyResult<()> {
+    check_access(path, required, |prefix| {
+        session.stat(prefix).map(|node| node.share_access)
+    })
+}
+
+/// ensure_access() for a node given by path or by handle. A handle has no
+/// path to look at, so the node's ancestors are checked instead.
+fn ensure_node_access(
+    session: &Session,
+    path_or_handle: &str,
+    node: &Node,
+    required: i32,
+) -> PyResult<()> {
+    if path_or_handle.starts_with('/') {
+        return ensure_access(session, path_or_handle, required);
+    }
+    let mut current = Some(node);
+    while let Some(node) = current {
+        if node.node_type == NodeType::Inbox {
+            return Err(vault_read_only());
+        }
+        if let Some(access) = node.share_access {
+            if access < required {
+                return Err(AccessDeniedError::new_err(format!(
+                    "Share {} does not allow this operation",
+                    node.name
+                )));
+            }
+        }
+        current = node
+            .parent
+            .as_deref()
+            .and_then(|parent| session.get_node_by_handle(parent));
+    }
+    Ok(())
+}
+
+/// The error for a change inside the vault.
+fn vault_read_only() -> PyErr {
+    AccessDeniedError::new_err("The vault is read-only; it holds backups managed by MEGA's clients")
+}
+
+/// The check of ensure_access(), with `share_access` giving the access
+/// level of the node at a path: None if there is no node, Some(None) if it
+/// isn't an incoming share.
+fn check_access(
+    path: &str,
+    required: i32,
+    share_access: impl Fn(&str) -> Option<Option<i32>>,
+) -> PyResult<()> {
+    let path = expand_virtual_path(path);
+    if path == INBOX_PATH || path.starts_with(&format!("{}/", INBOX_PATH)) {
+        return Err(vault_read_only());
+    }
+    let mut prefix = String::new();
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        prefix.push('/');
+        prefix.push_str(part);
+        match share_access(&prefix) {
+            Some(Some(access)) if access < required => {
+                return Err(AccessDeniedError::new_err(format!(
+                    "Share {} does not allow this operation",
+                    prefix
+                )));
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Share access needed to delete a node owned by `owner` for good: your
+/// own nodes need read-write access, those of others full access.
+fn rm_access(owner: Option<&str>, me: &str) -> i32 {
+    if owner == Some(me) {
+        ACCESS_READ_WRITE
+    } else {
+        ACCESS_FULL
+    }
+}
+
+/// MEGA API error code for "temporary congestion, retry".
+const EAGAIN: i32 = -3;
+/// MEGA API error code for "too many requests, slow down".
+const ERATELIMIT: i32 = -4;
+
+/// Token bucket state of the process-wide API rate limit.
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: std::time::Instant,
+}
+
+impl Bucket {
+    /// Take a token, or return how long to wait until one is available.
+    fn take(&mut self) -> Option<std::time::Duration> {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(std::time::Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.rate,
+            ))
+        }
+    }
+}
+
+/// Rate limit shared by all sessions in the process; None means unlimited.
+static API_LIMIT: std::sync::Mutex<Option<Bucket>> = std::sync::Mutex::new(None);
+
+/// Counters reported by `api_stats()`.
+static API_REQUESTS: AtomicU64 = AtomicU64::new(0);
+static API_THROTTLED: AtomicU64 = AtomicU64::new(0);
+static API_EAGAIN: AtomicU64 = AtomicU64::new(0);
+
+/// Traffic of one session (and its read-only views), reported by
+/// usage_stats().
+#[derive(Default)]
+struct SessionUsage {
+    bytes_uploaded: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl SessionUsage {
+    /// The counters as a dict, resetting them to zero if `reset` is set.
+    fn snapshot(&self, reset: bool) -> std::collections::HashMap<&'static str, u64> {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        std::collections::HashMap::from([
+            ("bytes_uploaded", read(&self.bytes_uploaded)),
+            ("bytes_downloaded", read(&self.bytes_downloaded)),
+            ("requests", read(&self.requests)),
+        ])
+    }
+}
+
+tokio::task_local! {
+    /// Usage counters of the session whose method is running, if any.
+    static SESSION_USAGE: Arc<SessionUsage>;
+}
+
+/// Add `bytes` to the upload counter of the running session method.
+fn count_uploaded(bytes: u64) {
+    let _ =
+        SESSION_USAGE.try_with(|usage| usage.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed));
+}
+
+/// Add `bytes` to the download counter of the running session method.
+fn count_downloaded(bytes: u64) {
+    let _ =
+        SESSION_USAGE.try_with(|usage| usage.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed));
+}
+
+/// Run a command API call under the process-wide rate limit.
+///
+/// Storage-server transfers are not throttled here, only the API commands
+/// that set them up.
+async fn api<T>(
+    call: impl Future<Output = Result<T, ::megalib::MegaError>>,
+) -> Result<T, ::megalib::MegaError> {
+    let mut throttled = false;
+    loop {
+        let wait = match API_LIMIT.lock().unwrap().as_mut() {
+            Some(bucket) => bucket.take(),
+            None => None,
+        };
+        match wait {
+            Some(wait) => {
+                throttled = true;
+                tokio::time::sleep(wait).await;
+            }
+            None => break,
+        }
+    }
+    if throttled {
+        API_THROTTLED.fetch_add(1, Ordering::Relaxed);
+    }
+    API_REQUESTS.fetch_add(1, Ordering::Relaxed);
+    let _ = SESSION_USAGE.try_with(|usage| usage.requests.fetch_add(1, Ordering::Relaxed));
+
+    let res = call.await;
+    if let Err(::megalib::MegaError::ApiError { code: EAGAIN, .. }) = &res {
+        API_EAGAIN.fetch_add(1, Ordering::Relaxed);
+    }
+    res
+}
+
+/// Run `fut` on the tokio runtime and return a Python awaitable for it.
+///
+/// The awaitable is bound to the event loop running at call time. Nothing is
+/// cached between calls, so uvloop and test runners that create a fresh loop
+/// per test work without any setup.
+fn future_into_py<F, T>(py: Python<'_>, fut: F) -> PyResult<&PyAny>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: IntoPy<PyObject>,
+{
+    let locals = pyo3_asyncio::TaskLocals::with_running_loop(py)
+        .map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "megalib methods must be called from a running asyncio event loop",
+            )
+        })?
+        .copy_context(py)?;
+    *RUNTIME_STARTED.lock().unwrap() = true;
+    pyo3_asyncio::tokio::future_into_py_with_locals(py, locals, fut)
+}
+
+/// Convert a `timeout` argument in seconds. None stays None.
+fn time_limit(timeout: Option<f64>) -> PyResult<Option<std::time::Duration>> {
+    match timeout {
+        None => Ok(None),
+        Some(secs) if secs > 0.0 && secs.is_finite() => {
+            Ok(Some(std::time::Duration::from_secs_f64(secs)))
+        }
+        Some(_) => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "timeout must be a positive number of seconds",
+        )),
+    }
+}
+
+/// Await `fut`, giving up with TimeoutError after `limit`.
+async fn with_timeout<F, T>(limit: Option<std::time::Duration>, fut: F) -> PyResult<T>
+where
+    F: Future<Output = PyResult<T>>,
+{
+    let Some(limit) = limit else {
+        return fut.await;
+    };
+    tokio::time::timeout(limit, fut).await.unwrap_or_else(|_| {
+        Err(PyErr::new::<pyo3::exceptions::PyTimeoutError, _>(format!(
+            "operation timed out after {}s",
+            limit.as_secs_f64()
+        )))
+    })
+}
+
+/// Set once the first async operation has been started, which creates the
+/// tokio runtime; configure_runtime() is refused from then on.
+static RUNTIME_STARTED: std::sync::Mutex<bool> = std::sync::Mutex::new(false);
+
 /// A file or folder node in MEGA.
 ///
 /// Attributes:
 ///     name: File/folder name
 ///     handle: Unique MEGA handle
+///     path: Full remote path (e.g. "/Root/Docs/report.pdf"), None where
+///         there is none or a name on the way contains "/"
+///     parent_handle: Handle of the parent folder, None for the account
+///         roots
 ///     size: Size in bytes (0 for folders)
-///     timestamp: Unix timestamp of last modification
+///     timestamp: Unix timestamp the node was created in MEGA (same as created_ts)
+///     created_ts: Unix timestamp the node was created in MEGA
+///     modified_ts: Unix timestamp of the file content's last modification,
+///         taken from the file fingerprint (falls back to created_ts)
 ///     is_file: True if this is a file
 ///     is_folder: True if this is a folder
+///     is_takendown: True if MEGA has taken the node down; it can't be downloaded
+///     owner_handle: User handle of the node's owner, None if unknown
+///     owner: Same as owner_handle
+///     fav: True if the node is marked as a favorite
+///     label: Color label (1=red, 2=orange, 3=yellow, 4=green, 5=blue,
+///         6=purple, 7=grey), None if unlabeled
+///     is_shared: True if the folder is shared with other users
+///     share_count: Number of users the folder is shared with, including
+///         pending shares (0 for files)
+///     is_exported: True if the node has a public link
+///     mime_type: MIME type guessed from the file extension, None for
+///         folders and unknown extensions
 #[pyclass]
 #[derive(Clone)]
 struct MegaNode {
@@ -19,714 +452,9258 @@ struct MegaNode {
     name: String,
     #[pyo3(get)]
     handle: String,
+    location: NodeLocation,
+    parent_handle: Option<Arc<str>>,
     #[pyo3(get)]
     size: u64,
     #[pyo3(get)]
     timestamp: i64,
     #[pyo3(get)]
+    created_ts: i64,
+    #[pyo3(get)]
+    modified_ts: i64,
+    #[pyo3(get)]
     is_file: bool,
     #[pyo3(get)]
     is_folder: bool,
+    #[pyo3(get)]
+    is_takendown: bool,
+    owner_handle: Option<Arc<str>>,
+    #[pyo3(get)]
+    fav: bool,
+    #[pyo3(get)]
+    label: Option<u8>,
+    #[pyo3(get)]
+    is_shared: bool,
+    #[pyo3(get)]
+    share_count: usize,
+    #[pyo3(get)]
+    is_exported: bool,
+    #[pyo3(get)]
+    mime_type: Option<&'static str>,
+}
+
+/// Where a MegaNode is in the tree, for MegaNode.path.
+#[derive(Clone)]
+enum NodeLocation {
+    /// No path (see node_path()).
+    Unknown,
+    /// The node's full path.
+    At(Arc<str>),
+    /// The path of the node's folder, shared with its siblings; the path
+    /// is built on access.
+    In(Arc<str>),
+}
+
+/// Hands out one shared copy of each string, for values that repeat across
+/// the nodes of a listing such as owner and parent handles.
+#[derive(Default)]
+struct Interner<'a>(std::collections::HashMap<&'a str, Arc<str>>);
+
+impl<'a> Interner<'a> {
+    fn get(&mut self, s: &'a str) -> Arc<str> {
+        self.0.entry(s).or_insert_with(|| Arc::from(s)).clone()
+    }
 }
 
 impl From<&Node> for MegaNode {
     fn from(n: &Node) -> Self {
+        MegaNode::snapshot(n, NodeLocation::Unknown, &mut Interner::default())
+    }
+}
+
+impl MegaNode {
+    /// Convert `n`, sharing repeated strings through `strings`.
+    ///
+    /// Listings convert every node of large trees, so the strings that
+    /// repeat are stored once and the path is kept as the folder's.
+    fn snapshot<'a>(n: &'a Node, location: NodeLocation, strings: &mut Interner<'a>) -> Self {
         MegaNode {
             name: n.name.clone(),
             handle: n.handle.clone(),
+            location,
+            parent_handle: n.parent.as_deref().map(|p| strings.get(p)),
             size: n.size,
             timestamp: n.timestamp,
+            created_ts: n.timestamp,
+            modified_ts: n
+                .fingerprint
+                .as_deref()
+                .and_then(fingerprint_mtime)
+                .unwrap_or(n.timestamp),
             is_file: n.node_type == NodeType::File,
             is_folder: n.node_type.is_container(),
+            is_takendown: n.takendown,
+            owner_handle: n.user_handle.as_deref().map(|u| strings.get(u)),
+            fav: is_favorite(n),
+            label: n
+                .attrs
+                .get(ATTR_LABEL)
+                .and_then(|v| v.as_u64())
+                .and_then(|l| u8::try_from(l).ok())
+                .filter(|l| (LABEL_MIN..=LABEL_MAX).contains(l)),
+            is_shared: !n.outshares.is_empty(),
+            share_count: n.outshares.len(),
+            is_exported: n.public_handle.is_some(),
+            mime_type: if n.node_type == NodeType::File {
+                guess_mime_type(&n.name)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Convert `node` with its path in the session's tree.
+    fn with_path(session: &Session, node: &Node) -> Self {
+        let location = node_path(session, node)
+            .map_or(NodeLocation::Unknown, |path| NodeLocation::At(path.into()));
+        MegaNode::snapshot(node, location, &mut Interner::default())
+    }
+
+    /// The value of MegaNode.path.
+    fn full_path(&self) -> Option<String> {
+        match &self.location {
+            NodeLocation::Unknown => None,
+            NodeLocation::At(path) => Some(path.to_string()),
+            NodeLocation::In(dir) => join_node_path(dir, &self.name),
         }
     }
 }
 
-/// Registration state for two-step account creation.
-///
-/// Use `serialize()` to save state, `deserialize()` to restore it.
-#[pyclass]
-struct MegaRegistrationState {
-    inner: RegistrationState,
+/// True if `node` is marked as a favorite.
+fn is_favorite(node: &Node) -> bool {
+    node.attrs.get(ATTR_FAV).and_then(|v| v.as_u64()) == Some(1)
 }
 
-#[pymethods]
-impl MegaRegistrationState {
-    /// Serialize state to JSON string for storage.
-    fn serialize(&self) -> String {
-        self.inner.serialize()
-    }
+/// Path of `node` for MegaNode.path: its canonical path, or None if it
+/// isn't below an account root or the path would be ambiguous because a
+/// name on the way contains a slash.
+fn node_path(session: &Session, node: &Node) -> Option<String> {
+    let mut names = Vec::new();
+    let mut current = node;
+    let root = loop {
+        if let Some(root) = root_path(current) {
+            break root;
+        }
+        if current.name.contains('/') {
+            return None;
+        }
+        names.push(current.name.as_str());
+        current = session.get_node_by_handle(current.parent.as_deref()?)?;
+    };
+    names.push(&root);
+    names.reverse();
+    Some(names.join("/"))
+}
 
-    /// Restore state from a JSON string.
-    #[staticmethod]
-    fn deserialize(s: String) -> PyResult<Self> {
-        let state = RegistrationState::deserialize(&s)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
-        Ok(MegaRegistrationState { inner: state })
-    }
+/// Path of the child `name` of the folder at `dir`, None if the name
+/// contains a slash (see node_path()).
+fn join_node_path(dir: &str, name: &str) -> Option<String> {
+    (!name.contains('/')).then(|| format!("{}/{}", dir.trim_end_matches('/'), name))
 }
 
-/// Information about a public file link.
+/// Locations of `nodes`, in their order, all taken from `tree`, a
+/// recursive listing of `dir` (whose handle is `dir_handle`).
 ///
-/// Attributes:
-///     name: File name
-///     size: Size in bytes
-///     handle: MEGA handle
-#[pyclass]
-struct MegaPublicFile {
-    #[pyo3(get)]
-    name: String,
-    #[pyo3(get)]
-    size: u64,
-    #[pyo3(get)]
-    handle: String,
+/// Each folder's path is built from its parent's and computed once, so deep
+/// trees with many nodes stay linear, and only the folders above `nodes`
+/// are visited. Siblings share their folder's path. Unknown where
+/// join_node_path() gives None for an ancestor.
+fn descendant_paths(
+    dir: &str,
+    dir_handle: Option<&str>,
+    tree: &[&Node],
+    nodes: &[&Node],
+) -> Vec<NodeLocation> {
+    let by_handle: std::collections::HashMap<&str, &Node> =
+        tree.iter().map(|n| (n.handle.as_str(), *n)).collect();
+    let mut folders: std::collections::HashMap<&str, Option<Arc<str>>> =
+        std::collections::HashMap::new();
+    if let Some(handle) = dir_handle {
+        folders.insert(handle, Some(Arc::from(dir)));
+    }
+    let mut folder_path = |handle: &str| -> Option<Arc<str>> {
+        // Walk up to the first folder with a known path, then fill in the
+        // paths on the way back down.
+        let mut chain = Vec::new();
+        let mut current = handle;
+        let mut path = loop {
+            if let Some(known) = folders.get(current) {
+                break known.clone();
+            }
+            match by_handle.get(current) {
+                Some(&folder) if chain.len() <= tree.len() => {
+                    chain.push(folder);
+                    match folder.parent.as_deref() {
+                        Some(parent) => current = parent,
+                        None => break None,
+                    }
+                }
+                _ => break None,
+            }
+        };
+        for folder in chain.into_iter().rev() {
+            path = path
+                .and_then(|parent| join_node_path(&parent, &folder.name))
+                .map(Arc::from);
+            folders.insert(folder.handle.as_str(), path.clone());
+        }
+        path
+    };
+    nodes
+        .iter()
+        .map(|n| {
+            n.parent
+                .as_deref()
+                .and_then(&mut folder_path)
+                .map_or(NodeLocation::Unknown, NodeLocation::In)
+        })
+        .collect()
 }
 
-/// Authenticated MEGA session for file operations.
-///
-/// Create a session using `login()` or `load()`, then call `refresh()` to
-/// fetch your file tree before performing operations.
+/// Convert `nodes`, all taken from `tree`, a listing of the folder at
+/// `dir` in the session's tree, with their paths.
 ///
-/// Example:
-///     session = await MegaSession.login("user@example.com", "password")
-///     await session.refresh()
-///     files = await session.list("/")
-#[pyclass]
-struct MegaSession {
-    inner: Arc<Mutex<Session>>,
+/// Unlike MegaNode::with_path() on each node, the folders on the way are
+/// looked up once, so this stays linear for recursive listings.
+fn nodes_below(session: &Session, dir: &str, tree: &[&Node], nodes: &[&Node]) -> Vec<MegaNode> {
+    let locations = match session.stat(dir) {
+        Some(dir_node) => match node_path(session, dir_node) {
+            Some(dir_path) => descendant_paths(&dir_path, Some(&dir_node.handle), tree, nodes),
+            None => vec![NodeLocation::Unknown; nodes.len()],
+        },
+        None => vec![NodeLocation::Unknown; nodes.len()],
+    };
+    let mut strings = Interner::default();
+    nodes
+        .iter()
+        .zip(locations)
+        .map(|(node, location)| MegaNode::snapshot(node, location, &mut strings))
+        .collect()
 }
 
 #[pymethods]
-impl MegaSession {
-    /// Login to MEGA with email and password.
-    ///
-    /// Args:
-    ///     email: Your MEGA account email
-    ///     password: Your MEGA account password
-    ///     proxy: Optional HTTP/SOCKS5 proxy URL (e.g., "http://proxy:8080")
-    ///
-    /// Returns:
-    ///     Authenticated MegaSession
-    ///
-    /// Raises:
-    ///     ValueError: If login fails (wrong credentials, etc.)
-    #[staticmethod]
-    fn login(
-        py: Python<'_>,
-        email: String,
-        password: String,
-        proxy: Option<String>,
-    ) -> PyResult<&PyAny> {
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let res = if let Some(p) = proxy {
-                Session::login_with_proxy(&email, &password, &p).await
-            } else {
-                Session::login(&email, &password).await
-            };
+impl MegaNode {
+    #[getter]
+    fn path(&self) -> Option<String> {
+        self.full_path()
+    }
 
-            let session =
-                res.map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
-            Ok(MegaSession {
-                inner: Arc::new(Mutex::new(session)),
-            })
-        })
+    #[getter]
+    fn parent_handle(&self) -> Option<&str> {
+        self.parent_handle.as_deref()
     }
 
-    /// Refresh the file tree from the server.
-    ///
-    /// Must be called after login before using list(), stat(), etc.
-    fn refresh<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .refresh()
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
-        })
+    #[getter]
+    fn owner_handle(&self) -> Option<&str> {
+        self.owner_handle.as_deref()
     }
 
-    /// Get information about a file or folder.
-    ///
-    /// Args:
-    ///     path: Path to the file/folder (e.g., "/Root/Documents")
-    ///
-    /// Returns:
-    ///     MegaNode if found, None otherwise
-    fn stat<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            match session.stat(&path) {
-                Some(node) => Ok(Some(MegaNode::from(node))),
-                None => Ok(None),
-            }
-        })
+    #[getter]
+    fn owner(&self) -> Option<&str> {
+        self.owner_handle.as_deref()
     }
 
-    /// List files in a directory.
-    ///
-    /// Args:
-    ///     path: Path to list (e.g., "/", "/Root/Documents")
-    ///     recursive: If True, list all descendants recursively
-    ///
-    /// Returns:
-    ///     List of MegaNode objects
-    #[pyo3(signature = (path, recursive = false))]
-    fn list<'p>(&self, py: Python<'p>, path: String, recursive: bool) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            let nodes = session
-                .list(&path, recursive)
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            let py_nodes: Vec<MegaNode> = nodes.iter().map(|n| MegaNode::from(*n)).collect();
-            Ok(py_nodes)
-        })
+    /// Nodes are equal if they have the same handle, i.e. are the same
+    /// remote object, even if it was renamed or changed in between.
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyObject {
+        let Ok(other) = other.extract::<PyRef<MegaNode>>() else {
+            return py.NotImplemented();
+        };
+        match op {
+            CompareOp::Eq => (self.handle == other.handle).into_py(py),
+            CompareOp::Ne => (self.handle != other.handle).into_py(py),
+            _ => py.NotImplemented(),
+        }
     }
 
-    /// Get storage quota information.
-    ///
-    /// Returns:
-    ///     Tuple of (total_bytes, used_bytes)
-    fn quota<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            let q = session
-                .quota()
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+    fn __hash__(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
 
-            Ok((q.total, q.used))
-        })
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.handle.hash(&mut hasher);
+        hasher.finish()
     }
 
-    /// Create a new directory.
-    ///
-    /// Args:
-    ///     path: Full path for the new directory (e.g., "/Root/NewFolder")
-    fn mkdir<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .mkdir(&path)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
-        })
+    fn __repr__(&self) -> String {
+        let kind = if self.is_file {
+            "file"
+        } else if self.is_folder {
+            "folder"
+        } else {
+            "other"
+        };
+        format!(
+            "MegaNode({:?}, handle={:?}, size={}, type={:?})",
+            self.name, self.handle, self.size, kind
+        )
     }
+}
 
-    /// Rename a file or folder.
-    ///
-    /// Args:
-    ///     path: Path to the item to rename
-    ///     new_name: New name (not a path, just the filename)
-    fn rename<'p>(&self, py: Python<'p>, path: String, new_name: String) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .rename(&path, &new_name)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
-        })
-    }
+/// File extensions (lowercase) and their MIME types, sorted by extension
+/// for binary search.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("3gp", "video/3gpp"),
+    ("7z", "application/x-7z-compressed"),
+    ("aac", "audio/aac"),
+    ("avi", "video/x-msvideo"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("bz2", "application/x-bzip2"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("epub", "application/epub+zip"),
+    ("flac", "audio/flac"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("m4a", "audio/mp4"),
+    ("m4v", "video/x-m4v"),
+    ("md", "text/markdown"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("opus", "audio/opus"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("psd", "image/vnd.adobe.photoshop"),
+    ("rar", "application/vnd.rar"),
+    ("rtf", "application/rtf"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("ts", "video/mp2t"),
+    ("txt", "text/plain"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("wmv", "video/x-ms-wmv"),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("xml", "application/xml"),
+    ("xz", "application/x-xz"),
+    ("zip", "application/zip"),
+];
 
-    /// Move a file or folder to a new location.
-    ///
-    /// Args:
-    ///     source: Path to the item to move
+/// Guess a file's MIME type from the extension of its name.
+fn guess_mime_type(name: &str) -> Option<&'static str> {
+    let (_, ext) = name.rsplit_once('.')?;
+    let ext = ext.to_ascii_lowercase();
+    MIME_TYPES
+        .binary_search_by(|(e, _)| (*e).cmp(ext.as_str()))
+        .ok()
+        .map(|i| MIME_TYPES[i].1)
+}
+
+/// Extract the modification time from a MEGA file fingerprint.
+///
+/// The fingerprint is base64url(16 byte CRC || serialized mtime), where the
+/// mtime is a length byte followed by that many little-endian bytes.
+fn fingerprint_mtime(fingerprint: &str) -> Option<i64> {
+    use base64::Engine;
+
+    let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(fingerprint)
+        .ok()?;
+    let (&len, rest) = raw.get(16..)?.split_first()?;
+    let bytes = rest.get(..len as usize)?;
+    if bytes.len() > 8 {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0i64, |acc, &b| (acc << 8) | i64::from(b)),
+    )
+}
+
+/// Files up to this size have their CRCs computed over all of their data.
+const FINGERPRINT_MAX_FULL: u64 = 8192;
+/// Size of the blocks sampled from larger files.
+const FINGERPRINT_BLOCK: usize = 64;
+
+/// The CRC part of a MEGA fingerprint of `size` bytes read from `reader`.
+///
+/// Same algorithm as the MEGA SDK and web client: four big-endian CRC32s,
+/// of the data's quarters up to 8 KiB and of 32 evenly spaced 64 byte
+/// blocks per quarter beyond that. Data of up to 16 bytes is used as is,
+/// zero-padded.
+fn fingerprint_crc<R>(reader: &mut R, size: u64) -> std::io::Result<[u8; 16]>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    use std::io::SeekFrom;
+
+    let mut crc = [0u8; 16];
+    if size <= crc.len() as u64 {
+        reader.read_exact(&mut crc[..size as usize])?;
+    } else if size <= FINGERPRINT_MAX_FULL {
+        let mut data = vec![0u8; size as usize];
+        reader.read_exact(&mut data)?;
+        for (i, out) in crc.chunks_mut(4).enumerate() {
+            let begin = (i as u64 * size / 4) as usize;
+            let end = ((i as u64 + 1) * size / 4) as usize;
+            out.copy_from_slice(&crc32fast::hash(&data[begin..end]).to_be_bytes());
+        }
+    } else {
+        let blocks = FINGERPRINT_MAX_FULL / (FINGERPRINT_BLOCK as u64 * 4);
+        let mut block = [0u8; FINGERPRINT_BLOCK];
+        for (i, out) in crc.chunks_mut(4).enumerate() {
+            let mut hasher = crc32fast::Hasher::new();
+            for j in 0..blocks {
+                let n = i as u64 * blocks + j;
+                let offset = (u128::from(size - FINGERPRINT_BLOCK as u64) * u128::from(n)
+                    / u128::from(4 * blocks - 1)) as u64;
+                reader.seek(SeekFrom::Start(offset))?;
+                reader.read_exact(&mut block)?;
+                hasher.update(&block);
+            }
+            out.copy_from_slice(&hasher.finalize().to_be_bytes());
+        }
+    }
+    Ok(crc)
+}
+
+/// Raw fingerprint from its CRC part and the mtime in seconds, serialized
+/// as read by fingerprint_mtime().
+fn serialize_fingerprint(crc: &[u8; 16], mut mtime: u64) -> Vec<u8> {
+    let mut serialized = Vec::new();
+    while mtime != 0 {
+        serialized.push(mtime as u8);
+        mtime >>= 8;
+    }
+    let mut fingerprint = crc.to_vec();
+    fingerprint.push(serialized.len() as u8);
+    fingerprint.extend(serialized);
+    fingerprint
+}
+
+/// Compute the MEGA fingerprint of a local file, as raw bytes, and the
+/// size read.
+fn local_fingerprint(path: &std::path::Path) -> std::io::Result<(u64, Vec<u8>)> {
+    let mut file = std::fs::File::open(path)?;
+    let meta = file.metadata()?;
+    let size = meta.len();
+    let crc = fingerprint_crc(&mut file, size)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok((size, serialize_fingerprint(&crc, mtime)))
+}
+
+/// local_fingerprint() on a blocking thread.
+async fn fingerprint_file(path: std::path::PathBuf) -> PyResult<(u64, Vec<u8>)> {
+    tokio::task::spawn_blocking(move || local_fingerprint(&path))
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
+}
+
+/// True if `node` is a file with the given size and raw fingerprint, i.e.
+/// MEGA's idea of the same content.
+fn has_fingerprint(node: &Node, size: u64, fingerprint: &[u8]) -> bool {
+    use base64::Engine;
+
+    node.node_type == NodeType::File
+        && node.size == size
+        && node.fingerprint.as_deref().is_some_and(|c| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(c.trim_end_matches('='))
+                .is_ok_and(|raw| raw == fingerprint)
+        })
+}
+
+/// Orderings accepted by `list(sort=...)`.
+#[derive(Clone, Copy)]
+enum NodeSort {
+    Name,
+    Size,
+    Timestamp,
+}
+
+impl NodeSort {
+    fn parse(sort: &str) -> PyResult<Self> {
+        match sort {
+            "name" => Ok(NodeSort::Name),
+            "size" => Ok(NodeSort::Size),
+            "timestamp" => Ok(NodeSort::Timestamp),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Unknown sort {:?} (expected name, size or timestamp)",
+                other
+            ))),
+        }
+    }
+
+    /// Sort `nodes`, breaking ties by name and then handle so the order
+    /// never depends on how the tree was fetched.
+    fn apply(self, nodes: &mut [MegaNode]) {
+        nodes.sort_by(|a, b| {
+            let primary = match self {
+                NodeSort::Name => std::cmp::Ordering::Equal,
+                NodeSort::Size => a.size.cmp(&b.size),
+                NodeSort::Timestamp => a.timestamp.cmp(&b.timestamp),
+            };
+            primary
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.handle.cmp(&b.handle))
+        });
+    }
+}
+
+/// Number of nodes turned into Python objects per GIL acquisition.
+const NODE_BATCH: usize = 1024;
+
+/// Convert snapshotted nodes into Python objects.
+///
+/// The GIL is taken once per batch rather than for the whole listing, so
+/// other Python threads keep running while large trees are converted.
+fn nodes_into_py(nodes: Vec<MegaNode>) -> PyResult<Vec<Py<MegaNode>>> {
+    into_py_batched(nodes, |py, node| Py::new(py, node))
+}
+
+/// Like nodes_into_py(), keeping None entries in place.
+fn optional_nodes_into_py(nodes: Vec<Option<MegaNode>>) -> PyResult<Vec<Option<Py<MegaNode>>>> {
+    into_py_batched(nodes, |py, node| node.map(|n| Py::new(py, n)).transpose())
+}
+
+/// Direct child folders among `children` of the folder at `path`, each
+/// with its number of children as given by `count`, sorted by name, then
+/// handle.
+fn child_folders(
+    path: &str,
+    children: Vec<&Node>,
+    count: impl Fn(&str) -> usize,
+) -> Vec<(MegaNode, usize)> {
+    let dir: Arc<str> = Arc::from(path);
+    let mut strings = Interner::default();
+    let mut folders: Vec<_> = children
+        .into_iter()
+        .filter(|node| node.node_type.is_container())
+        .map(|node| {
+            let child_path = format!("{}/{}", path.trim_end_matches('/'), node.name);
+            let location = NodeLocation::In(dir.clone());
+            let folder = MegaNode::snapshot(node, location, &mut strings);
+            (folder, count(&child_path))
+        })
+        .collect();
+    folders.sort_by(|(a, _), (b, _)| (&a.name, &a.handle).cmp(&(&b.name, &b.handle)));
+    folders
+}
+
+/// Convert the result of child_folders() like nodes_into_py().
+fn folders_into_py(folders: Vec<(MegaNode, usize)>) -> PyResult<Vec<(Py<MegaNode>, usize)>> {
+    into_py_batched(folders, |py, (node, count)| Ok((Py::new(py, node)?, count)))
+}
+
+fn into_py_batched<T, U>(
+    items: Vec<T>,
+    convert: impl Fn(Python<'_>, T) -> PyResult<U>,
+) -> PyResult<Vec<U>> {
+    let mut out = Vec::with_capacity(items.len());
+    let mut iter = items.into_iter().peekable();
+    while iter.peek().is_some() {
+        Python::with_gil(|py| {
+            for item in iter.by_ref().take(NODE_BATCH) {
+                out.push(convert(py, item)?);
+            }
+            Ok::<_, PyErr>(())
+        })?;
+    }
+    Ok(out)
+}
+
+/// Node attribute key of the favorite flag.
+const ATTR_FAV: &str = "fav";
+/// Node attribute key of the color label.
+const ATTR_LABEL: &str = "lbl";
+/// Node attribute key of the folder a node in the rubbish bin was removed
+/// from, as the MEGA apps set it.
+const ATTR_RESTORE: &str = "rr";
+/// Prefix of custom node attribute keys, as used by the official SDK's
+/// custom attributes, which keeps them apart from MEGA's own keys.
+const CUSTOM_ATTR_PREFIX: &str = "_";
+/// Range of valid color labels (red through grey).
+const LABEL_MIN: u8 = 1;
+const LABEL_MAX: u8 = 7;
+/// Node attribute keys managed by the bindings (name, fingerprint, favorite,
+/// label); set_raw_attribute() refuses to touch them.
+const RESERVED_ATTRS: &[&str] = &["n", "c", ATTR_FAV, ATTR_LABEL];
+
+/// Attribute key of custom attribute `name`.
+fn custom_attr_key(name: &str) -> PyResult<String> {
+    if name.is_empty() {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "Attribute name must not be empty",
+        ));
+    }
+    Ok(format!("{}{}", CUSTOM_ATTR_PREFIX, name))
+}
+
+/// Convert a JSON value to the equivalent Python object.
+fn json_to_py(py: Python<'_>, value: &serde_json::Value) -> PyResult<PyObject> {
+    let json = py.import("json")?;
+    Ok(json.call_method1("loads", (value.to_string(),))?.into())
+}
+
+/// Convert a JSON-serializable Python object to a JSON value.
+fn py_to_json(value: &PyAny) -> PyResult<serde_json::Value> {
+    let json = value.py().import("json")?;
+    let text: String = json.call_method1("dumps", (value,))?.extract()?;
+    serde_json::from_str(&text)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))
+}
+
+/// Look up a node by absolute path ("/Root/...") or by handle.
+fn resolve_node<'a>(session: &'a Session, path_or_handle: &str) -> PyResult<&'a Node> {
+    let node = if path_or_handle.starts_with('/') {
+        session.stat(&expand_virtual_path(path_or_handle))
+    } else {
+        session.get_node_by_handle(path_or_handle)
+    };
+    node.ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(format!(
+            "{} not found on Mega",
+            path_or_handle
+        ))
+    })
+}
+
+/// Path under which an account root is addressed: "/Root", "/Vault" or
+/// "/" plus the rubbish bin's name. None for nodes that aren't roots.
+fn root_path(node: &Node) -> Option<String> {
+    match node.node_type {
+        NodeType::Inbox => Some(VAULT_PATH.to_string()),
+        NodeType::Root | NodeType::Trash => Some(format!("/{}", node.name)),
+        _ => None,
+    }
+}
+
+/// The rubbish bin's root node.
+fn trash_root(session: &Session) -> PyResult<&Node> {
+    session
+        .list("/", false)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+        .into_iter()
+        .find(|node| node.node_type == NodeType::Trash)
+        .ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>("The tree has no rubbish bin")
+        })
+}
+
+/// The items directly in the rubbish bin.
+fn trash_items(session: &Session) -> PyResult<Vec<&Node>> {
+    let trash = root_path(trash_root(session)?).unwrap_or_default();
+    session
+        .list(&trash, false)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))
+}
+
+/// Canonical path of `node`: the path of its account root followed by the
+/// stored names of the folders down to the node, joined by single slashes.
+fn canonical_path(session: &Session, node: &Node) -> PyResult<String> {
+    let mut parts = Vec::new();
+    let mut current = node;
+    loop {
+        if let Some(root) = root_path(current) {
+            parts.push(root);
+            break;
+        }
+        parts.push(current.name.clone());
+        current = current
+            .parent
+            .as_deref()
+            .and_then(|parent| session.get_node_by_handle(parent))
+            .ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "{} is not below an account root",
+                    node.handle
+                ))
+            })?;
+    }
+    parts.reverse();
+    Ok(parts.join("/"))
+}
+
+/// A change of a node's location: a new name, or a new parent folder.
+enum Relocation<'a> {
+    Rename(&'a str),
+    Into(&'a str),
+}
+
+impl Relocation<'_> {
+    /// Path the node at `path` ends up at.
+    fn target(&self, path: &str) -> String {
+        let (parent, name) = path
+            .trim_end_matches('/')
+            .rsplit_once('/')
+            .unwrap_or(("", path));
+        match self {
+            Relocation::Rename(new_name) => format!("{}/{}", parent, new_name),
+            Relocation::Into(dest) => format!("{}/{}", dest.trim_end_matches('/'), name),
+        }
+    }
+
+    async fn apply(&self, session: &mut Session, path: &str) -> Result<(), ::megalib::MegaError> {
+        match self {
+            Relocation::Rename(new_name) => api(session.rename(path, new_name)).await.map(|_| ()),
+            Relocation::Into(dest) => api(session.mv(path, dest)).await.map(|_| ()),
+        }
+    }
+}
+
+/// Rename or move the node at `path`, reconciling with concurrent changes.
+///
+/// When MEGA reports that the node or the target changed under us, the
+/// tree is re-fetched and compared by handle: if the node already is where
+/// it should end up, that's success; if it is still at `path`, the change is
+/// retried once; otherwise it was moved or deleted and NotFoundError is
+/// raised.
+async fn move_node(session: &mut Session, path: &str, change: Relocation<'_>) -> PyResult<()> {
+    let handle = resolve_node(session, path)?.handle.clone();
+    let target = change.target(path);
+    match change.apply(session, path).await {
+        Ok(()) => return Ok(()),
+        Err(::megalib::MegaError::ApiError {
+            code: EEXIST | ENOENT,
+            ..
+        }) => {}
+        Err(e) => return Err(mega_err::<pyo3::exceptions::PyRuntimeError>(e)),
+    }
+
+    fetch_tree(session, false).await?;
+    let is_node = |node: Option<&Node>| node.is_some_and(|n| n.handle == handle);
+    if is_node(session.stat(&expand_virtual_path(&target))) {
+        return Ok(());
+    }
+    if !is_node(session.stat(&expand_virtual_path(path))) {
+        return Err(NotFoundError::new_err(format!(
+            "{} was moved or deleted by another client",
+            path
+        )));
+    }
+    change
+        .apply(session, path)
+        .await
+        .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)
+}
+
+/// Change some attributes of a node, keeping all others.
+///
+/// megalib re-encrypts the attributes with the node key and updates the
+/// in-memory node, so the change is visible without a refresh.
+async fn update_node_attrs<F>(
+    session: &mut Session,
+    path_or_handle: &str,
+    update: F,
+) -> PyResult<()>
+where
+    F: FnOnce(&mut serde_json::Map<String, serde_json::Value>),
+{
+    let node = resolve_node(session, path_or_handle)?;
+    ensure_node_access(session, path_or_handle, node, ACCESS_READ_WRITE)?;
+    let handle = node.handle.clone();
+    let mut attrs = node.attrs.clone();
+    update(&mut attrs);
+    api(session.set_node_attrs(&handle, attrs))
+        .await
+        .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)
+}
+
+/// A local filesystem path argument: str, bytes or any os.PathLike.
+///
+/// Goes through os.fspath(), so pathlib.Path works, and is kept as a
+/// PathBuf so names that aren't valid UTF-8 survive on Unix.
+struct LocalPath(std::path::PathBuf);
+
+impl<'source> FromPyObject<'source> for LocalPath {
+    fn extract(obj: &'source PyAny) -> PyResult<Self> {
+        let path = obj.py().import("os")?.call_method1("fspath", (obj,))?;
+        if let Ok(bytes) = path.downcast::<pyo3::types::PyBytes>() {
+            #[cfg(unix)]
+            return Ok(LocalPath(
+                <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(bytes.as_bytes())
+                    .into(),
+            ));
+            #[cfg(not(unix))]
+            return std::str::from_utf8(bytes.as_bytes())
+                .map(|path| LocalPath(path.into()))
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()));
+        }
+        Ok(LocalPath(path.extract()?))
+    }
+}
+
+/// Fetch the number of bytes still free in the account's storage quota.
+async fn remaining_storage(session: &mut Session) -> PyResult<u64> {
+    let q = api(session.quota())
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+    Ok(q.total.saturating_sub(q.used))
+}
+
+/// Fail with OverQuotaError if `local_path` doesn't fit in the remaining quota.
+async fn ensure_quota(session: &mut Session, local_path: &std::path::Path) -> PyResult<()> {
+    let size = std::fs::metadata(local_path)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?
+        .len();
+    ensure_fits(session, size).await
+}
+
+/// Fail with OverQuotaError if `size` bytes don't fit in the remaining quota.
+async fn ensure_fits(session: &mut Session, size: u64) -> PyResult<()> {
+    let remaining = remaining_storage(session).await?;
+    if size > remaining {
+        return Err(OverQuotaError::new_err(format!(
+            "Upload needs {} bytes but only {} are left ({} missing)",
+            size,
+            remaining,
+            size - remaining
+        )));
+    }
+    Ok(())
+}
+
+/// Length of a partial download at `local_path` that can be resumed, or 0
+/// if there is none or it can't belong to a file of `size` bytes.
+fn partial_download_len(local_path: &std::path::Path, size: u64) -> u64 {
+    match std::fs::metadata(local_path) {
+        Ok(meta) if meta.is_file() && meta.len() < size => meta.len(),
+        _ => 0,
+    }
+}
+
+/// MEGA computes file MACs over chunks of 128 KiB, 256 KiB, ... 1 MiB and
+/// then 1 MiB each.
+const MAC_CHUNK_UNIT: u64 = 128 * 1024;
+
+/// Sizes of the successive MAC chunks of a file.
+fn mac_chunk_sizes() -> impl Iterator<Item = u64> {
+    (1..=8)
+        .map(|i| i * MAC_CHUNK_UNIT)
+        .chain(std::iter::repeat(8 * MAC_CHUNK_UNIT))
+}
+
+/// Largest MAC chunk boundary at or below `offset`.
+fn mac_chunk_floor(offset: u64) -> u64 {
+    let mut boundary = 0;
+    for size in mac_chunk_sizes() {
+        if boundary + size > offset {
+            return boundary;
+        }
+        boundary += size;
+    }
+    unreachable!("mac_chunk_sizes() is infinite")
+}
+
+/// Threads used by file_mac_matches(), 0 for one per CPU core.
+static VERIFY_THREADS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// MAC of one chunk: CBC-MAC of its data, starting from the nonce in the
+/// node key repeated twice.
+fn chunk_mac(cipher: &aes::Aes128, node_key: &[u8], data: &[u8]) -> [u8; 16] {
+    use aes::cipher::{generic_array::GenericArray, BlockEncrypt};
+
+    let mut mac = [0u8; 16];
+    mac[..8].copy_from_slice(&node_key[16..24]);
+    mac[8..].copy_from_slice(&node_key[16..24]);
+    for block in data.chunks(16) {
+        for (m, b) in mac.iter_mut().zip(block) {
+            *m ^= b;
+        }
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut mac));
+    }
+    mac
+}
+
+/// Check the local file at `local_path` against the MAC in the node key.
+///
+/// Recomputes MEGA's chunk MACs over the whole file and condenses them like
+/// the uploading client did. Only the condensed MAC is stored with the node,
+/// so a mismatch tells that the file is bad, not where.
+///
+/// Chunk MACs don't depend on each other, so the file is split into one
+/// contiguous run of chunks per thread (see set_verify_threads()) and only
+/// the condensing step is sequential. Blocking; calls `progress(done,
+/// total)` from the calling thread after each chunk.
+fn file_mac_matches(
+    local_path: &std::path::Path,
+    node_key: &[u8],
+    progress: Option<&PyObject>,
+) -> PyResult<bool> {
+    use std::io::{Read, Seek};
+
+    let cipher = mac_cipher(node_key)?;
+
+    let io_err = |e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+    let total = std::fs::metadata(local_path).map_err(io_err)?.len();
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    for size in mac_chunk_sizes() {
+        if offset >= total {
+            break;
+        }
+        let len = size.min(total - offset);
+        chunks.push((offset, len));
+        offset += len;
+    }
+
+    let threads = match VERIFY_THREADS.load(Ordering::Relaxed) {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let per_thread = chunks.len().div_ceil(threads).max(1);
+    let mut chunk_macs = vec![[0u8; 16]; chunks.len()];
+    std::thread::scope(|scope| -> PyResult<()> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for (n, run) in chunks.chunks(per_thread).enumerate() {
+            let tx = tx.clone();
+            let cipher = &cipher;
+            scope.spawn(move || {
+                let compute = || -> std::io::Result<()> {
+                    let mut file = std::fs::File::open(local_path)?;
+                    file.seek(std::io::SeekFrom::Start(run[0].0))?;
+                    let mut reader = std::io::BufReader::new(file);
+                    let mut buf = Vec::new();
+                    for (i, &(_, len)) in run.iter().enumerate() {
+                        buf.resize(len as usize, 0);
+                        reader.read_exact(&mut buf)?;
+                        let mac = chunk_mac(cipher, node_key, &buf);
+                        // The receiver is gone if another thread failed.
+                        if tx.send(Ok((n * per_thread + i, mac, len))).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                };
+                if let Err(e) = compute() {
+                    let _ = tx.send(Err(e));
+                }
+            });
+        }
+        drop(tx);
+
+        let mut done = 0;
+        for message in rx {
+            let (i, mac, len) = message.map_err(io_err)?;
+            chunk_macs[i] = mac;
+            done += len;
+            if let Some(progress) = progress {
+                Python::with_gil(|py| progress.call1(py, (done, total)))?;
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(meta_mac_matches(&cipher, node_key, &chunk_macs))
+}
+
+/// AES cipher for the file key in `node_key`.
+fn mac_cipher(node_key: &[u8]) -> PyResult<aes::Aes128> {
+    use aes::cipher::{generic_array::GenericArray, KeyInit};
+
+    if node_key.len() != 32 {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "Node has no file key to verify against",
+        ));
+    }
+    let aes_key: Vec<u8> = (0..16).map(|i| node_key[i] ^ node_key[i + 16]).collect();
+    Ok(aes::Aes128::new(GenericArray::from_slice(&aes_key)))
+}
+
+/// Condense the chunk MACs of a file and compare them with the MAC in the
+/// node key.
+fn meta_mac_matches(cipher: &aes::Aes128, node_key: &[u8], chunk_macs: &[[u8; 16]]) -> bool {
+    use aes::cipher::{generic_array::GenericArray, BlockEncrypt};
+
+    let mut file_mac = [0u8; 16];
+    for chunk in chunk_macs {
+        for (m, c) in file_mac.iter_mut().zip(chunk) {
+            *m ^= c;
+        }
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut file_mac));
+    }
+    let meta_mac: Vec<u8> = (0..4)
+        .map(|i| file_mac[i] ^ file_mac[i + 4])
+        .chain((8..12).map(|i| file_mac[i] ^ file_mac[i + 4]))
+        .collect();
+    meta_mac == node_key[24..32]
+}
+
+/// True if an I/O error means the disk (or the user's disk quota) is full.
+fn is_disk_full(e: &std::io::Error) -> bool {
+    // ENOSPC and EDQUOT on Unix, ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL
+    // on Windows.
+    const CODES: &[i32] = if cfg!(windows) {
+        &[39, 112]
+    } else {
+        &[28, 122]
+    };
+    e.raw_os_error().is_some_and(|code| CODES.contains(&code))
+}
+
+/// DiskFullError for a download to `local_path` that stopped after
+/// `bytes_written` bytes.
+fn disk_full_error(local_path: &std::path::Path, bytes_written: u64, message: String) -> PyErr {
+    Python::with_gil(|py| {
+        let err = DiskFullError::new_err(message);
+        let value = err.value(py);
+        // Setting attributes on a fresh exception instance can't fail.
+        let _ = value.setattr("bytes_written", bytes_written);
+        let _ = value.setattr("local_path", local_path);
+        err
+    })
+}
+
+/// DiskFullError for a download that ran out of space part way through.
+///
+/// The partial file is left in place, so download_to_file() with resume
+/// enabled can continue it once space has been freed.
+fn disk_full_mid_download(local_path: &std::path::Path) -> PyErr {
+    let written = std::fs::metadata(local_path).map_or(0, |m| m.len());
+    disk_full_error(
+        local_path,
+        written,
+        format!(
+            "No space left on device after writing {} bytes to {}",
+            written,
+            local_path.display()
+        ),
+    )
+}
+
+/// Fail with DiskFullError if a file of `size` bytes won't fit at
+/// `local_path`.
+///
+/// An existing file at `local_path` counts as free space, since the
+/// download either overwrites or resumes it.
+fn ensure_disk_space(local_path: &std::path::Path, size: u64) -> PyResult<()> {
+    let existing = std::fs::metadata(local_path).map_or(0, |m| m.len());
+    let dir = local_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    let available = fs2::available_space(dir)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+    let needed = size.saturating_sub(existing);
+    if needed > available {
+        return Err(disk_full_error(
+            local_path,
+            0,
+            format!(
+                "Download needs {} bytes but only {} are free at {} \
+                 (pass ignore_space_check=True to try anyway)",
+                needed,
+                available,
+                local_path.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Fail with LinkTakenDownError before any transfer starts for a taken-down node.
+fn ensure_available(node: &Node) -> PyResult<()> {
+    if node.takendown {
+        return Err(LinkTakenDownError::new_err(format!(
+            "{} has been taken down by MEGA",
+            node.name
+        )));
+    }
+    Ok(())
+}
+
+/// Hash algorithms accepted by the `digest` argument of downloads.
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(name: &str) -> PyResult<Self> {
+        use sha2::Digest;
+
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(Hasher::Sha256(sha2::Sha256::new())),
+            "sha1" => Ok(Hasher::Sha1(sha1::Sha1::new())),
+            "blake3" => Ok(Hasher::Blake3(Box::new(blake3::Hasher::new()))),
+            other => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Unsupported digest {:?} (expected sha256, sha1 or blake3)",
+                other
+            ))),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    fn hexdigest(self) -> String {
+        use sha2::Digest;
+
+        match self {
+            Hasher::Sha256(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha1(h) => format!("{:x}", h.finalize()),
+            Hasher::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+
+    /// Hash the whole file at `path`. Blocks, so run it on a blocking thread.
+    fn hash_file(mut self, path: &std::path::Path) -> std::io::Result<String> {
+        use std::io::Read;
+
+        let mut file = std::fs::File::open(path)?;
+        let mut buf = vec![0u8; 1 << 20];
+        loop {
+            match file.read(&mut buf)? {
+                0 => return Ok(self.hexdigest()),
+                n => self.update(&buf[..n]),
+            }
+        }
+    }
+}
+
+/// Writer that counts and optionally hashes the decrypted plaintext as it
+/// is written out.
+struct DigestWriter<W> {
+    inner: W,
+    hasher: Option<Hasher>,
+    written: u64,
+    /// Set when a write failed because the disk is full.
+    disk_full: bool,
+    /// The file being written, removed if the download is cancelled.
+    partial: PartialFile,
+}
+
+impl<W: std::io::Write> DigestWriter<W> {
+    fn new(inner: W, digest: Option<&str>) -> PyResult<Self> {
+        let hasher = digest.map(Hasher::new).transpose()?;
+        Ok(DigestWriter {
+            inner,
+            hasher,
+            written: 0,
+            disk_full: false,
+            partial: PartialFile(None),
+        })
+    }
+
+    /// Result of a download written through this writer.
+    ///
+    /// Flushes the writer and raises DiskFullError if the download failed
+    /// because the disk filled up.
+    fn finish<T>(
+        mut self,
+        outcome: Result<T, ::megalib::MegaError>,
+        local_path: std::path::PathBuf,
+    ) -> PyResult<MegaDownloadResult> {
+        use std::io::Write;
+
+        self.partial.keep();
+        let flushed = match outcome {
+            Ok(_) => self.flush(),
+            Err(_) => Ok(()),
+        };
+        count_downloaded(self.written);
+        if self.disk_full {
+            return Err(disk_full_mid_download(&local_path));
+        }
+        outcome.map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        flushed.map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+        Ok(MegaDownloadResult {
+            local_path,
+            bytes_transferred: self.written,
+            bytes_reused_from_resume: 0,
+            bytes_reverified: 0,
+            bytes_redownloaded: 0,
+            // megalib doesn't check the MAC of what it writes.
+            verified: false,
+            digest: self.hasher.map(Hasher::hexdigest),
+            attempts: 1,
+        })
+    }
+
+    /// Note whether a failed write or flush was due to a full disk.
+    fn track<T>(&mut self, result: std::io::Result<T>) -> std::io::Result<T> {
+        if let Err(e) = &result {
+            self.disk_full |= is_disk_full(e);
+        }
+        result
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.inner.write(buf);
+        let n = self.track(result)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.inner.flush();
+        self.track(result)
+    }
+}
+
+/// Create (or truncate) `local_path` for a download through a DigestWriter.
+///
+/// The file is removed again if the writer is dropped before finish(),
+/// which happens when the awaiting Python task is cancelled.
+fn download_writer(
+    local_path: &std::path::Path,
+    digest: Option<&str>,
+) -> PyResult<DigestWriter<std::io::BufWriter<std::fs::File>>> {
+    let file = std::fs::File::create(local_path)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+    let mut writer = DigestWriter::new(std::io::BufWriter::new(file), digest)?;
+    writer.partial = PartialFile(Some(local_path.to_path_buf()));
+    Ok(writer)
+}
+
+/// Writer keeping `length` bytes of a download starting at `offset`.
+///
+/// Fails the write once the range is complete, which aborts the rest of
+/// the download.
+struct RangeWriter {
+    data: Vec<u8>,
+    /// Bytes still to skip before the range starts.
+    skip: u64,
+    length: u64,
+    /// Bytes received from the download, including skipped ones.
+    received: u64,
+}
+
+impl RangeWriter {
+    fn new(offset: u64, length: u64) -> Self {
+        RangeWriter {
+            data: Vec::with_capacity(length as usize),
+            skip: offset,
+            length,
+            received: 0,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.data.len() as u64 == self.length
+    }
+}
+
+impl std::io::Write for RangeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.is_complete() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Requested range complete",
+            ));
+        }
+        self.received += buf.len() as u64;
+        let skipped = self.skip.min(buf.len() as u64);
+        self.skip -= skipped;
+        let wanted = (self.length - self.data.len() as u64) as usize;
+        let rest = &buf[skipped as usize..];
+        self.data.extend_from_slice(&rest[..wanted.min(rest.len())]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A download's local file, removed on drop unless keep() was called.
+///
+/// pyo3-asyncio drops a method's future when the awaiting Python task is
+/// cancelled, so this is how cancelled downloads clean up after themselves.
+struct PartialFile(Option<std::path::PathBuf>);
+
+impl PartialFile {
+    /// Leave the file in place when dropped.
+    fn keep(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Default max_size of download_bytes().
+const DOWNLOAD_BYTES_MAX: u64 = 64 * 1024 * 1024;
+
+/// How often with_progress() checks on a running transfer.
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Run `transfer`, reporting progress(done, total) to a Python callback.
+///
+/// `done` is sampled every PROGRESS_INTERVAL with `sample()` and reported
+/// when it changed, and once more when the transfer ends, so the GIL is
+/// taken a few times per second however fast the transfer is. If the
+/// callback raises, the transfer is cancelled and the exception returned.
+async fn with_progress<F: Future>(
+    transfer: F,
+    progress: Option<&PyObject>,
+    total: u64,
+    sample: impl Fn() -> u64,
+) -> PyResult<F::Output> {
+    let Some(progress) = progress else {
+        return Ok(transfer.await);
+    };
+    report_while(transfer, sample, |done| {
+        Python::with_gil(|py| progress.call1(py, (done.min(total), total)).map(|_| ()))
+    })
+    .await
+}
+
+/// The sampling loop of with_progress(): run `transfer`, passing `sample()`
+/// to `report` every PROGRESS_INTERVAL when it changed and once more at
+/// the end. An error from `report` cancels the transfer.
+async fn report_while<F: Future>(
+    transfer: F,
+    sample: impl Fn() -> u64,
+    report: impl Fn(u64) -> PyResult<()>,
+) -> PyResult<F::Output> {
+    tokio::pin!(transfer);
+    let mut ticker = tokio::time::interval_at(
+        tokio::time::Instant::now() + PROGRESS_INTERVAL,
+        PROGRESS_INTERVAL,
+    );
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last = None;
+    loop {
+        tokio::select! {
+            output = &mut transfer => {
+                let done = sample();
+                if last != Some(done) {
+                    report(done)?;
+                }
+                return Ok(output);
+            }
+            _ = ticker.tick() => {
+                let done = sample();
+                if last != Some(done) {
+                    report(done)?;
+                    last = Some(done);
+                }
+            }
+        }
+    }
+}
+
+/// Progress of download_folder() and upload_folder(), reported as
+/// progress(bytes_done, bytes_total, path, files_done, files_total).
+///
+/// The totals are those of the planned files. A file counts as done once
+/// it is over, whether or not it succeeded, so bytes_done ends at
+/// bytes_total unless the transfer stops early.
+struct FolderProgress {
+    callback: Option<PyObject>,
+    bytes_total: u64,
+    files_total: usize,
+    bytes_done: u64,
+    files_done: usize,
+}
+
+impl FolderProgress {
+    fn new(callback: Option<PyObject>, sizes: impl Iterator<Item = u64>) -> Self {
+        let (mut bytes_total, mut files_total) = (0, 0);
+        for size in sizes {
+            bytes_total += size;
+            files_total += 1;
+        }
+        FolderProgress {
+            callback,
+            bytes_total,
+            files_total,
+            bytes_done: 0,
+            files_done: 0,
+        }
+    }
+
+    fn report(&self, bytes_done: u64, path: &str, files_done: usize) -> PyResult<()> {
+        let Some(callback) = &self.callback else {
+            return Ok(());
+        };
+        Python::with_gil(|py| {
+            callback
+                .call1(
+                    py,
+                    (
+                        bytes_done,
+                        self.bytes_total,
+                        path,
+                        files_done,
+                        self.files_total,
+                    ),
+                )
+                .map(|_| ())
+        })
+    }
+
+    /// Run `transfer` of the `size` bytes file at `path`, reporting while
+    /// `sample()` of them have moved and once the file is over.
+    async fn file<F: Future>(
+        &mut self,
+        path: &str,
+        size: u64,
+        transfer: F,
+        sample: impl Fn() -> u64,
+    ) -> PyResult<F::Output> {
+        let output = if self.callback.is_some() {
+            let (bytes_done, files_done) = (self.bytes_done, self.files_done);
+            report_while(transfer, sample, |done| {
+                self.report(bytes_done + done.min(size), path, files_done)
+            })
+            .await?
+        } else {
+            transfer.await
+        };
+        self.skip(path, size)?;
+        Ok(output)
+    }
+
+    /// Count the file at `path` as over without transferring it.
+    fn skip(&mut self, path: &str, size: u64) -> PyResult<()> {
+        self.bytes_done += size;
+        self.files_done += 1;
+        self.report(self.bytes_done, path, self.files_done)
+    }
+}
+
+/// Have megalib report the progress of `session`'s transfers into a
+/// counter for with_progress(). For resumed uploads the first report
+/// already includes the chunks sent before.
+///
+/// The status callback is also where transferred bytes are taken from the
+/// throttle, so it keeps feeding `throttle`.
+fn watch_transfer(session: &mut Session, throttle: &Arc<Throttle>) -> Arc<AtomicU64> {
+    let done = Arc::new(AtomicU64::new(0));
+    let counter = done.clone();
+    let metered = throttle.meter();
+    session.watch_status(Box::new(move |status: &::megalib::TransferProgress| {
+        counter.store(status.done, Ordering::Relaxed);
+        metered(status.done);
+        true
+    }));
+    done
+}
+
+/// Longest single pause of a Paced future, so a raised or removed limit
+/// takes effect on transfers that are already waiting.
+const THROTTLE_RECHECK: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Token bucket limiting the transfer rate of a session and everything
+/// sharing its login (read-only views, open_read() streams).
+///
+/// megalib reports progress after each chunk from its transfer loop, where
+/// the bytes are taken from the bucket. The waiting happens in Paced, which
+/// stops polling the transfer while the bucket is in debt, so the runtime,
+/// timeouts and cancellation keep working. The bucket holds at most one
+/// second's worth of bytes.
+#[derive(Default)]
+struct Throttle {
+    /// Bytes per second, 0 for unlimited.
+    rate: AtomicU64,
+    bucket: std::sync::Mutex<Option<(f64, std::time::Instant)>>,
+    /// Bytes taken from the bucket so far, for Paced to tell whether a
+    /// poll transferred anything.
+    consumed: AtomicU64,
+}
+
+impl Throttle {
+    fn set_rate(&self, rate: u64) {
+        self.rate.store(rate, Ordering::Relaxed);
+        *self.bucket.lock().unwrap() = None;
+    }
+
+    /// Tokens in the bucket after adding those earned since the last call.
+    fn refill(&self, rate: u64) -> std::sync::MutexGuard<'_, Option<(f64, std::time::Instant)>> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = std::time::Instant::now();
+        let tokens = match *bucket {
+            Some((tokens, last)) => {
+                (tokens + now.duration_since(last).as_secs_f64() * rate as f64).min(rate as f64)
+            }
+            None => rate as f64,
+        };
+        *bucket = Some((tokens, now));
+        bucket
+    }
+
+    /// Account for `bytes` transferred. Never blocks; see delay().
+    fn consume(&self, bytes: u64) {
+        let rate = self.rate.load(Ordering::Relaxed);
+        if rate == 0 || bytes == 0 {
+            return;
+        }
+        self.consumed.fetch_add(bytes, Ordering::Relaxed);
+        if let Some((tokens, _)) = self.refill(rate).as_mut() {
+            *tokens -= bytes as f64;
+        }
+    }
+
+    /// How long to pause until the bucket is out of debt, at most
+    /// THROTTLE_RECHECK. None if no pause is needed.
+    fn delay(&self) -> Option<std::time::Duration> {
+        let rate = self.rate.load(Ordering::Relaxed);
+        if rate == 0 {
+            return None;
+        }
+        match *self.refill(rate) {
+            Some((tokens, _)) if tokens < 0.0 => Some(
+                std::time::Duration::from_secs_f64(-tokens / rate as f64).min(THROTTLE_RECHECK),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Callback turning megalib's running `done` totals into consume()
+    /// calls. A total lower than the last one starts a new transfer.
+    fn meter(self: &Arc<Self>) -> impl Fn(u64) + Send + Sync + 'static {
+        let throttle = self.clone();
+        let last = AtomicU64::new(0);
+        move |done: u64| {
+            let last = last.swap(done, Ordering::Relaxed);
+            throttle.consume(if done < last { done } else { done - last });
+        }
+    }
+}
+
+/// Future running `inner` at the rate of `throttle`: after a poll that
+/// transferred data while the bucket is in debt, `inner` isn't polled
+/// again until the debt is paid. Calls that transfer nothing never pause.
+struct Paced<F> {
+    inner: std::pin::Pin<Box<F>>,
+    throttle: Arc<Throttle>,
+    pause: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<F> Paced<F> {
+    fn new(throttle: Arc<Throttle>, inner: F) -> Self {
+        Paced {
+            inner: Box::pin(inner),
+            throttle,
+            pause: None,
+        }
+    }
+}
+
+impl<F: Future> Future for Paced<F> {
+    type Output = F::Output;
+
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<F::Output> {
+        let this = self.get_mut();
+        loop {
+            if let Some(pause) = &mut this.pause {
+                std::task::ready!(pause.as_mut().poll(cx));
+                this.pause = this
+                    .throttle
+                    .delay()
+                    .map(|wait| Box::pin(tokio::time::sleep(wait)));
+                continue;
+            }
+            let before = this.throttle.consumed.load(Ordering::Relaxed);
+            let polled = this.inner.as_mut().poll(cx);
+            if polled.is_pending() && this.throttle.consumed.load(Ordering::Relaxed) != before {
+                this.pause = this
+                    .throttle
+                    .delay()
+                    .map(|wait| Box::pin(tokio::time::sleep(wait)));
+                if this.pause.is_some() {
+                    continue;
+                }
+            }
+            return polled;
+        }
+    }
+}
+
+/// Install the status callback that feeds `throttle` on a new session.
+fn watch_throttle(session: &mut Session, throttle: &Arc<Throttle>) {
+    let metered = throttle.meter();
+    session.watch_status(Box::new(move |status: &::megalib::TransferProgress| {
+        metered(status.done);
+        true
+    }));
+}
+
+/// Size of the file at `path`, 0 if it doesn't exist (yet).
+fn file_len(path: &std::path::Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+/// Bytes requested per `read()` call on a streaming upload source.
+const STREAM_READ_SIZE: usize = 1024 * 1024;
+
+/// Copy any object supporting the buffer protocol into a byte vector.
+fn buffer_to_vec(obj: &PyAny) -> PyResult<Vec<u8>> {
+    let buffer = pyo3::buffer::PyBuffer::<u8>::get(obj)?;
+    buffer.to_vec(obj.py())
+}
+
+/// A Python object producing the bytes of a streaming upload.
+enum StreamSource {
+    /// File-like object with a `read(n)` method.
+    File(PyObject),
+    /// Async iterator yielding bytes-like chunks.
+    AsyncIter(PyObject),
+}
+
+impl StreamSource {
+    fn new(obj: &PyAny) -> PyResult<Self> {
+        if obj.hasattr("read")? {
+            Ok(StreamSource::File(obj.into()))
+        } else if obj.hasattr("__aiter__")? {
+            Ok(StreamSource::AsyncIter(
+                obj.call_method0("__aiter__")?.into(),
+            ))
+        } else {
+            Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(
+                "source must be a file-like object or an async iterable of bytes",
+            ))
+        }
+    }
+
+    /// Read the next chunk, or None once the source is exhausted.
+    ///
+    /// Exceptions raised by the source propagate unchanged.
+    async fn next_chunk(&self) -> PyResult<Option<Vec<u8>>> {
+        match self {
+            StreamSource::File(obj) => {
+                let obj = Python::with_gil(|py| obj.clone_ref(py));
+                let chunk = tokio::task::spawn_blocking(move || {
+                    Python::with_gil(|py| {
+                        let data = obj.call_method1(py, "read", (STREAM_READ_SIZE,))?;
+                        buffer_to_vec(data.as_ref(py))
+                    })
+                })
+                .await
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))??;
+                Ok(if chunk.is_empty() { None } else { Some(chunk) })
+            }
+            StreamSource::AsyncIter(obj) => {
+                let next = Python::with_gil(|py| {
+                    pyo3_asyncio::tokio::into_future(obj.as_ref(py).call_method0("__anext__")?)
+                })?;
+                match next.await {
+                    Ok(chunk) => Python::with_gil(|py| buffer_to_vec(chunk.as_ref(py))).map(Some),
+                    Err(e)
+                        if Python::with_gil(|py| {
+                            e.is_instance_of::<pyo3::exceptions::PyStopAsyncIteration>(py)
+                        }) =>
+                    {
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// Default chunk size of streams returned by open_read().
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Chunks a read stream fetches ahead of its reader.
+const READ_AHEAD: usize = 4;
+
+/// Incremental file_mac_matches() for data that arrives in order.
+struct StreamMac {
+    cipher: aes::Aes128,
+    node_key: Vec<u8>,
+    /// Data of the current MAC chunk so far.
+    chunk: Vec<u8>,
+    chunk_macs: Vec<[u8; 16]>,
+}
+
+impl StreamMac {
+    fn new(node_key: &[u8]) -> PyResult<Self> {
+        Ok(StreamMac {
+            cipher: mac_cipher(node_key)?,
+            node_key: node_key.to_vec(),
+            chunk: Vec::new(),
+            chunk_macs: Vec::new(),
+        })
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Chunk sizes stop growing after the eighth chunk.
+            let chunk_len = mac_chunk_sizes()
+                .nth(self.chunk_macs.len().min(8))
+                .unwrap_or(MAC_CHUNK_UNIT) as usize;
+            let n = (chunk_len - self.chunk.len()).min(data.len());
+            self.chunk.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.chunk.len() == chunk_len {
+                self.finish_chunk();
+            }
+        }
+    }
+
+    fn finish_chunk(&mut self) {
+        let mac = chunk_mac(&self.cipher, &self.node_key, &self.chunk);
+        self.chunk_macs.push(mac);
+        self.chunk.clear();
+    }
+
+    fn matches(mut self) -> bool {
+        if !self.chunk.is_empty() {
+            self.finish_chunk();
+        }
+        meta_mac_matches(&self.cipher, &self.node_key, &self.chunk_macs)
+    }
+}
+
+/// Message from a read stream's download to its reader.
+type ReadChunk = PyResult<Vec<u8>>;
+
+/// Writer cutting a download into chunks for a MegaReadStream.
+///
+/// Runs on a blocking thread: a send waits while the reader is READ_AHEAD
+/// chunks behind, and fails once the reader is gone.
+struct ChunkSender {
+    tx: std::sync::mpsc::SyncSender<ReadChunk>,
+    buf: Vec<u8>,
+    chunk_size: usize,
+    mac: StreamMac,
+    written: u64,
+}
+
+impl ChunkSender {
+    fn send(&self, message: ReadChunk) -> std::io::Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Read stream closed"))
+    }
+
+    /// Check the download against the node and pass on the last chunk, or
+    /// the error.
+    fn finish(mut self, size: u64) {
+        count_downloaded(self.written);
+        let message = if self.written != size {
+            Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                "Stream truncated after {} of {} bytes",
+                self.written, size
+            )))
+        } else if !self.mac.matches() {
+            Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "Streamed data doesn't match the file MAC",
+            ))
+        } else if self.buf.is_empty() {
+            return;
+        } else {
+            Ok(std::mem::take(&mut self.buf))
+        };
+        let _ = self.tx.send(message);
+    }
+}
+
+impl std::io::Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.mac.update(buf);
+        self.written += buf.len() as u64;
+        self.buf.extend_from_slice(buf);
+        while self.buf.len() >= self.chunk_size {
+            let rest = self.buf.split_off(self.chunk_size);
+            let chunk = std::mem::replace(&mut self.buf, rest);
+            self.send(Ok(chunk))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decrypted contents of a remote file, read with `async for`.
+///
+/// Returned by MegaSession.open_read(). Each chunk is a bytes object; the
+/// file MAC is checked once all data is in, and a truncated or corrupted
+/// download raises RuntimeError in place of the last chunk. Use
+/// `async with` or close() to stop reading early.
+///
+/// Attributes:
+///     size: File size in bytes
+#[pyclass]
+struct MegaReadStream {
+    #[pyo3(get)]
+    size: u64,
+    rx: Arc<std::sync::Mutex<Option<std::sync::mpsc::Receiver<ReadChunk>>>>,
+    /// Aborts the download when sent or dropped.
+    cancel: std::sync::Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
+}
+
+impl MegaReadStream {
+    /// Start downloading `node` through `session` on a blocking thread, at
+    /// the rate of `throttle`.
+    ///
+    /// `session` is a detached_session(), so no lock is held while the
+    /// stream is open.
+    fn start(
+        mut session: Session,
+        throttle: Arc<Throttle>,
+        node: Node,
+        chunk_size: usize,
+    ) -> PyResult<Self> {
+        watch_throttle(&mut session, &throttle);
+        let mac = StreamMac::new(&node.key)?;
+        let (tx, rx) = std::sync::mpsc::sync_channel(READ_AHEAD);
+        let (cancel, cancelled) = tokio::sync::oneshot::channel::<()>();
+        let size = node.size;
+        let handle = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let mut writer = ChunkSender {
+                tx,
+                buf: Vec::with_capacity(chunk_size),
+                chunk_size,
+                mac,
+                written: 0,
+            };
+            let outcome = handle.block_on(async {
+                let download = Paced::new(throttle, api(session.download(&node, &mut writer)));
+                tokio::select! {
+                    outcome = download => Some(outcome),
+                    _ = cancelled => None,
+                }
+            });
+            match outcome {
+                Some(Ok(_)) => writer.finish(node.size),
+                Some(Err(e)) => {
+                    count_downloaded(writer.written);
+                    let _ = writer.send(Err(mega_err::<pyo3::exceptions::PyRuntimeError>(e)));
+                }
+                // Closed by the reader, nobody is listening.
+                None => count_downloaded(writer.written),
+            }
+        });
+        Ok(MegaReadStream {
+            size,
+            rx: Arc::new(std::sync::Mutex::new(Some(rx))),
+            cancel: std::sync::Mutex::new(Some(cancel)),
+        })
+    }
+}
+
+#[pymethods]
+impl MegaReadStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'p>(&self, py: Python<'p>) -> PyResult<Option<&'p PyAny>> {
+        let rx = self.rx.clone();
+        let next = future_into_py(py, async move {
+            let message = tokio::task::spawn_blocking(move || {
+                rx.lock().unwrap().as_ref().and_then(|rx| rx.recv().ok())
+            })
+            .await
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+            match message {
+                Some(chunk) => {
+                    let chunk = chunk?;
+                    Ok(Python::with_gil(|py| {
+                        PyObject::from(pyo3::types::PyBytes::new(py, &chunk))
+                    }))
+                }
+                None => Err(PyErr::new::<pyo3::exceptions::PyStopAsyncIteration, _>(())),
+            }
+        })?;
+        Ok(Some(next))
+    }
+
+    /// Stop reading and abort the download.
+    ///
+    /// Iterating afterwards ends right away. Safe to call more than once.
+    fn close(&self) {
+        if let Some(cancel) = self.cancel.lock().unwrap().take() {
+            let _ = cancel.send(());
+        }
+        self.rx.lock().unwrap().take();
+    }
+
+    fn __aenter__<'p>(slf: Py<Self>, py: Python<'p>) -> PyResult<&'p PyAny> {
+        future_into_py(py, async move { Ok(slf) })
+    }
+
+    fn __aexit__<'p>(
+        &self,
+        py: Python<'p>,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<&'p PyAny> {
+        self.close();
+        future_into_py(py, async move { Ok(false) })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("MegaReadStream(size={})", self.size)
+    }
+}
+
+/// A session with the login of `session` but none of its nodes, for a
+/// download that runs on its own, like a read stream.
+///
+/// Goes through save() and load(), which copy the credentials but not the
+/// tree. The lock is released before the copy is loaded.
+async fn detached_session(
+    session: tokio::sync::MutexGuard<'_, Session>,
+    temp_dir: &std::path::Path,
+) -> PyResult<Session> {
+    let io_err = |e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+    let spill = SpillDir::create(temp_dir).map_err(io_err)?;
+    let path = spill.path.join("session");
+    session
+        .save(&path)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+    drop(session);
+    api(Session::load(&path))
+        .await
+        .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?
+        .ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>("Saved session could not be loaded")
+        })
+}
+
+/// Name prefix of scratch directories; cleanup_temp() sweeps by it.
+const SPILL_PREFIX: &str = "megalib-spill-";
+
+/// Scratch directory holding a spill file, removed again on drop.
+///
+/// Dropping covers success, errors and cancellation alike. On Unix the
+/// directory is only accessible to the current user.
+struct SpillDir {
+    path: std::path::PathBuf,
+}
+
+impl SpillDir {
+    /// Create a scratch directory inside `base`.
+    fn create(base: &std::path::Path) -> std::io::Result<Self> {
+        static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = base.join(format!("{}{}-{}", SPILL_PREFIX, std::process::id(), n));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&path)?;
+        Ok(SpillDir { path })
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Upload a spill file into `remote_dir` under its own name, returning the
+/// new node.
+async fn upload_spilled(
+    session: &mut Session,
+    spill_path: &std::path::Path,
+    remote_dir: &str,
+    size: u64,
+) -> PyResult<MegaNode> {
+    ensure_access(session, remote_dir, ACCESS_READ_WRITE)?;
+    api(session.upload(spill_path, remote_dir))
+        .await
+        .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+    count_uploaded(size);
+
+    let name = spill_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let remote_path = format!("{}/{}", remote_dir.trim_end_matches('/'), name);
+    match session.stat(&remote_path) {
+        Some(node) => Ok(MegaNode::with_path(session, node)),
+        None => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "Uploaded node not found in the file tree",
+        )),
+    }
+}
+
+/// Prefix of the hidden name an atomic upload carries until it's complete.
+const ATOMIC_TMP_PREFIX: &str = ".megatmp-";
+
+/// Upload `local_path` into `remote_dir` under a hidden temporary name and
+/// give it its real name only once the node exists with the right size.
+///
+/// With `trash_existing`, a file already at the final path is moved to the
+/// rubbish bin right before the rename. On failure the temporary node is
+/// removed again.
+async fn upload_atomic(
+    session: &mut Session,
+    local_path: &std::path::Path,
+    remote_dir: &str,
+    trash_existing: bool,
+    temp_dir: &std::path::Path,
+) -> PyResult<()> {
+    let io_err = |e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+    let name = local_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>("local_path has no file name")
+        })?;
+    let size = std::fs::metadata(local_path).map_err(io_err)?.len();
+
+    // megalib names the node after the local file, so stage the upload
+    // under the temporary name: a hard link where possible, else a copy.
+    let temp_name = format!("{}{}", ATOMIC_TMP_PREFIX, uuid::Uuid::new_v4().simple());
+    let spill = SpillDir::create(temp_dir).map_err(io_err)?;
+    let staged = spill.path.join(&temp_name);
+    if std::fs::hard_link(local_path, &staged).is_err() {
+        std::fs::copy(local_path, &staged).map_err(io_err)?;
+    }
+
+    let dir = expand_virtual_path(remote_dir)
+        .trim_end_matches('/')
+        .to_string();
+    let temp_path = format!("{}/{}", dir, temp_name);
+    let final_path = format!("{}/{}", dir, name);
+    let result = async {
+        api(session.upload(&staged, remote_dir))
+            .await
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        if session.stat(&temp_path).map(|n| n.size) != Some(size) {
+            return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "Uploaded node is missing or has the wrong size",
+            ));
+        }
+        if trash_existing && session.stat(&final_path).is_some() {
+            let rubbish = root_path(trash_root(session)?).unwrap_or_default();
+            api(session.mv(&final_path, &rubbish))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        }
+        api(session.rename(&temp_path, name))
+            .await
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        Ok(())
+    }
+    .await;
+
+    if result.is_err() && session.stat(&temp_path).is_some() {
+        // Best effort; the original error is what the caller needs to see.
+        let _ = api(session.rm(&temp_path)).await;
+    }
+    result
+}
+
+/// Spool `source` into `path`, returning the number of bytes written.
+async fn spool_stream(source: &StreamSource, path: &std::path::Path) -> PyResult<u64> {
+    use tokio::io::AsyncWriteExt;
+
+    let io_err = |e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await.map_err(io_err)?;
+    let mut written = 0u64;
+    while let Some(chunk) = source.next_chunk().await? {
+        file.write_all(&chunk).await.map_err(io_err)?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await.map_err(io_err)?;
+    Ok(written)
+}
+
+/// Registration state for two-step account creation.
+///
+/// Use `serialize()` to save state, `deserialize()` to restore it.
+#[pyclass]
+struct MegaRegistrationState {
+    inner: RegistrationState,
+}
+
+#[pymethods]
+impl MegaRegistrationState {
+    /// Serialize state to JSON string for storage.
+    fn serialize(&self) -> String {
+        self.inner.serialize()
+    }
+
+    /// Restore state from a JSON string.
+    #[staticmethod]
+    fn deserialize(s: String) -> PyResult<Self> {
+        let state = RegistrationState::deserialize(&s)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        Ok(MegaRegistrationState { inner: state })
+    }
+}
+
+/// Information about a public file link.
+///
+/// Attributes:
+///     name: File name
+///     size: Size in bytes
+///     handle: MEGA handle
+#[pyclass]
+struct MegaPublicFile {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    size: u64,
+    #[pyo3(get)]
+    handle: String,
+}
+
+/// Storage used below one account root.
+///
+/// Attributes:
+///     bytes: Total size of all files in bytes
+///     files: Number of files
+///     folders: Number of folders (not counting the root itself)
+#[pyclass]
+#[derive(Clone, Default)]
+struct MegaStorageUsage {
+    #[pyo3(get)]
+    bytes: u64,
+    #[pyo3(get)]
+    files: u64,
+    #[pyo3(get)]
+    folders: u64,
+}
+
+impl MegaStorageUsage {
+    /// Sum up everything below `path` in the local tree.
+    fn of_path(session: &Session, path: &str) -> PyResult<Self> {
+        let nodes = session
+            .list(path, true)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+        let mut usage = MegaStorageUsage::default();
+        for node in nodes {
+            if node.node_type == NodeType::File {
+                usage.bytes += node.size;
+                usage.files += 1;
+            } else {
+                usage.folders += 1;
+            }
+        }
+        Ok(usage)
+    }
+}
+
+/// Share states reported by `share_folder()`.
+const SHARE_ACTIVE: &str = "active";
+const SHARE_PENDING: &str = "pending";
+const SHARE_INVITED: &str = "invited";
+
+/// Outcome of sharing a folder.
+///
+/// Attributes:
+///     status: "active" if the recipient is a contact and can access the
+///         folder now, "pending" if they have a MEGA account but must accept
+///         a contact request first, "invited" if they have no MEGA account
+///         and were sent an invitation
+///     email: Recipient's email address
+///     user_handle: Recipient's MEGA user handle, None if they have no account
+#[pyclass]
+struct MegaShareResult {
+    #[pyo3(get)]
+    status: String,
+    #[pyo3(get)]
+    email: String,
+    #[pyo3(get)]
+    user_handle: Option<String>,
+}
+
+/// Kinds of exposure reported by audit_exposure().
+const EXPOSURE_LINK: &str = "link";
+const EXPOSURE_SHARE: &str = "share";
+const EXPOSURE_PENDING: &str = "pending_share";
+const EXPOSURE_KINDS: &[&str] = &[EXPOSURE_LINK, EXPOSURE_SHARE, EXPOSURE_PENDING];
+
+/// One way a node is reachable by someone other than the account owner.
+///
+/// Attributes:
+///     kind: "link" (public link), "share" (outgoing share) or
+///         "pending_share" (share awaiting the recipient's acceptance or
+///         signup)
+///     path: Path of the node
+///     handle: Handle of the node
+///     url: Public URL for links, None otherwise
+///     recipient: Email of the share recipient, None for links
+///     access_level: Share access level (0=read, 1=write, 2=full), None for
+///         links
+#[pyclass]
+#[derive(Clone)]
+struct MegaExposure {
+    #[pyo3(get)]
+    kind: String,
+    #[pyo3(get)]
+    path: String,
+    #[pyo3(get)]
+    handle: String,
+    #[pyo3(get)]
+    url: Option<String>,
+    #[pyo3(get)]
+    recipient: Option<String>,
+    #[pyo3(get)]
+    access_level: Option<i32>,
+}
+
+#[pymethods]
+impl MegaExposure {
+    /// Convert to a JSON-compatible dict.
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("kind", &self.kind)?;
+        dict.set_item("path", &self.path)?;
+        dict.set_item("handle", &self.handle)?;
+        dict.set_item("url", &self.url)?;
+        dict.set_item("recipient", &self.recipient)?;
+        dict.set_item("access_level", self.access_level)?;
+        Ok(dict)
+    }
+
+    fn __repr__(&self) -> String {
+        match &self.recipient {
+            Some(recipient) => format!(
+                "MegaExposure({:?}, {:?}, recipient={:?})",
+                self.kind, self.path, recipient
+            ),
+            None => format!("MegaExposure({:?}, {:?})", self.kind, self.path),
+        }
+    }
+}
+
+/// Everything in an account that is reachable by someone else.
+///
+/// Attributes:
+///     entries: List of MegaExposure, ordered by path
+#[pyclass]
+struct MegaExposureReport {
+    #[pyo3(get)]
+    entries: Vec<MegaExposure>,
+}
+
+#[pymethods]
+impl MegaExposureReport {
+    /// Entries of one kind ("link", "share" or "pending_share").
+    fn of_kind(&self, kind: &str) -> Vec<MegaExposure> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .cloned()
+            .collect()
+    }
+
+    /// Convert to a JSON-compatible dict: {"entries": [entry dicts]}.
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| entry.to_dict(py))
+            .collect::<PyResult<Vec<_>>>()?;
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("entries", entries)?;
+        Ok(dict)
+    }
+
+    fn __len__(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn __repr__(&self) -> String {
+        let count = |kind| self.entries.iter().filter(|e| e.kind == kind).count();
+        format!(
+            "MegaExposureReport(links={}, shares={}, pending_shares={})",
+            count(EXPOSURE_LINK),
+            count(EXPOSURE_SHARE),
+            count(EXPOSURE_PENDING)
+        )
+    }
+}
+
+/// Node name test of search().
+enum NameMatcher {
+    /// Substring, lowercased unless the search is case-sensitive.
+    Substring {
+        needle: String,
+        case_sensitive: bool,
+    },
+    Regex(regex::Regex),
+}
+
+impl NameMatcher {
+    fn new(query: &str, case_sensitive: bool, regex: bool) -> PyResult<Self> {
+        if regex {
+            regex::RegexBuilder::new(query)
+                .case_insensitive(!case_sensitive)
+                .build()
+                .map(NameMatcher::Regex)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))
+        } else {
+            Ok(NameMatcher::Substring {
+                needle: if case_sensitive {
+                    query.to_string()
+                } else {
+                    query.to_lowercase()
+                },
+                case_sensitive,
+            })
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NameMatcher::Substring {
+                needle,
+                case_sensitive: true,
+            } => name.contains(needle.as_str()),
+            NameMatcher::Substring { needle, .. } => name.to_lowercase().contains(needle.as_str()),
+            NameMatcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// One "/"-separated component of a glob() pattern.
+enum GlobPart {
+    /// "**": any number of folders, including none.
+    Recurse,
+    /// Anything else, matched against a whole node name.
+    Name(regex::Regex),
+}
+
+impl GlobPart {
+    /// Compile the components of an absolute glob pattern.
+    fn parse(pattern: &str, case_sensitive: bool) -> PyResult<Vec<GlobPart>> {
+        if !pattern.starts_with('/') {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Glob patterns must be absolute, e.g. \"/Root/**/*.mp4\"",
+            ));
+        }
+        pattern
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                if part == "**" {
+                    return Ok(GlobPart::Recurse);
+                }
+                regex::RegexBuilder::new(&glob_to_regex(part))
+                    .case_insensitive(!case_sensitive)
+                    .build()
+                    .map(GlobPart::Name)
+                    .map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                            "Invalid glob component {:?}: {}",
+                            part, e
+                        ))
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Translate one glob component to an anchored regular expression.
+///
+/// `*` matches any run of characters, `?` one character, and `[...]` a
+/// character class (negated with a leading `!` or `^`). A `[` without a
+/// closing `]` stands for itself.
+fn glob_to_regex(part: &str) -> String {
+    let chars: Vec<char> = part.chars().collect();
+    let mut out = String::from("(?s)^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            '[' => {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '!' || chars[j] == '^') {
+                    j += 1;
+                }
+                // A "]" right after the opening bracket is a member.
+                if j < chars.len() && chars[j] == ']' {
+                    j += 1;
+                }
+                while j < chars.len() && chars[j] != ']' {
+                    j += 1;
+                }
+                if j >= chars.len() {
+                    out.push_str("\\[");
+                } else {
+                    let mut members = &chars[i + 1..j];
+                    out.push('[');
+                    if let Some('!' | '^') = members.first() {
+                        out.push('^');
+                        members = &members[1..];
+                    }
+                    for &c in members {
+                        if matches!(c, '\\' | '[' | ']' | '^') {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                    out.push(']');
+                    i = j;
+                }
+            }
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    out.push('$');
+    out
+}
+
+/// Collect the nodes below `node` (the account roots if None) at `path`
+/// that match the glob components `parts`, with their paths for
+/// MegaNode.path. `exact` is false once a name on the way contains a
+/// slash, which leaves the paths ambiguous (see node_path()).
+fn glob_walk<'a>(
+    session: &'a Session,
+    path: &str,
+    exact: bool,
+    node: Option<&'a Node>,
+    parts: &[GlobPart],
+    out: &mut Vec<(Option<String>, &'a Node)>,
+) -> PyResult<()> {
+    let Some((part, rest)) = parts.split_first() else {
+        out.extend(node.map(|node| (exact.then(|| path.to_string()), node)));
+        return Ok(());
+    };
+    let children: Vec<(String, &Node)> = match node {
+        None => session
+            .list("/", false)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+            .into_iter()
+            .filter_map(|root| root_path(root).map(|path| (path, root)))
+            .collect(),
+        Some(node) if node.node_type.is_container() => session
+            .list(&expand_virtual_path(path), false)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+            .into_iter()
+            .map(|child| (format!("{}/{}", path, child.name), child))
+            .collect(),
+        Some(_) => Vec::new(),
+    };
+    match part {
+        GlobPart::Recurse => {
+            glob_walk(session, path, exact, node, rest, out)?;
+            for (child_path, child) in children {
+                if child.node_type.is_container() {
+                    let exact = exact && !(node.is_some() && child.name.contains('/'));
+                    glob_walk(session, &child_path, exact, Some(child), parts, out)?;
+                }
+            }
+        }
+        GlobPart::Name(regex) => {
+            for (child_path, child) in children {
+                let name = child_path.rsplit('/').next().unwrap_or_default();
+                if regex.is_match(name) {
+                    let exact = exact && !(node.is_some() && child.name.contains('/'));
+                    glob_walk(session, &child_path, exact, Some(child), rest, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every node below the account roots, paired with its path.
+fn walk_tree(session: &Session) -> PyResult<Vec<(String, &Node)>> {
+    let list = |path: &str| {
+        session
+            .list(&expand_virtual_path(path), false)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))
+    };
+    let mut folders: Vec<String> = list("/")?.into_iter().filter_map(root_path).collect();
+
+    let mut nodes = Vec::new();
+    while let Some(folder) = folders.pop() {
+        for node in list(&folder)? {
+            let path = format!("{}/{}", folder, node.name);
+            if node.node_type.is_container() {
+                folders.push(path.clone());
+            }
+            nodes.push((path, node));
+        }
+    }
+    Ok(nodes)
+}
+
+/// Collect the public links and outgoing shares of all nodes in the tree.
+fn exposures(session: &Session) -> PyResult<Vec<MegaExposure>> {
+    let mut entries = Vec::new();
+    for (path, node) in walk_tree(session)? {
+        if node.public_handle.is_some() {
+            entries.push(MegaExposure {
+                kind: EXPOSURE_LINK.to_string(),
+                path: path.clone(),
+                handle: node.handle.clone(),
+                url: session.public_link(node),
+                recipient: None,
+                access_level: None,
+            });
+        }
+        for share in &node.outshares {
+            entries.push(MegaExposure {
+                kind: if share.pending {
+                    EXPOSURE_PENDING
+                } else {
+                    EXPOSURE_SHARE
+                }
+                .to_string(),
+                path: path.clone(),
+                handle: node.handle.clone(),
+                url: None,
+                recipient: Some(share.user.clone()),
+                access_level: Some(share.access_level),
+            });
+        }
+    }
+    entries.sort_by(|a, b| (&a.path, &a.kind).cmp(&(&b.path, &b.kind)));
+    Ok(entries)
+}
+
+/// Name of the Python exception class of `err`, for MegaTransferItem.
+fn exception_name(err: &PyErr) -> Option<String> {
+    Python::with_gil(|py| err.get_type(py).name().ok().map(str::to_string))
+}
+
+/// A node left out of the tree because its key couldn't be decrypted.
+///
+/// Attributes:
+///     handle: Node handle
+///     parent_handle: Handle of the parent folder, None if unknown
+///     size: Size in bytes (0 for folders)
+#[pyclass]
+struct MegaUndecryptableNode {
+    #[pyo3(get)]
+    handle: String,
+    #[pyo3(get)]
+    parent_handle: Option<String>,
+    #[pyo3(get)]
+    size: u64,
+}
+
+/// A MEGA user known to this session.
+///
+/// Attributes:
+///     handle: User handle (matches MegaNode.owner_handle)
+///     email: User's email address
+///     name: Display name, None if unknown
+#[pyclass]
+struct MegaContact {
+    #[pyo3(get)]
+    handle: String,
+    #[pyo3(get)]
+    email: String,
+    #[pyo3(get)]
+    name: Option<String>,
+}
+
+/// Result of a completed download.
+///
+/// Attributes:
+///     local_path: Path of the downloaded file
+///     bytes_transferred: Bytes fetched from MEGA by this call
+///     bytes_reused_from_resume: Bytes of an earlier partial download that
+///         were kept instead of being fetched again
+///     bytes_reverified: Bytes of the earlier partial download that were
+///         checked against the file MAC (resume_verify=True only)
+///     bytes_redownloaded: Bytes of the earlier partial download that were
+///         dropped and fetched again because they couldn't be verified
+///     verified: True if the file's MAC was checked against the node key
+///         and matched; only fetch_public_file() and a resumed
+///         download_to_file(resume_verify=True) check it
+///     digest: Hex digest if one was requested, otherwise None
+///     attempts: Number of times the transfer was started (more than 1 only
+///         for fetch_public_file() retries)
+#[pyclass]
+struct MegaDownloadResult {
+    #[pyo3(get)]
+    local_path: std::path::PathBuf,
+    #[pyo3(get)]
+    bytes_transferred: u64,
+    #[pyo3(get)]
+    bytes_reused_from_resume: u64,
+    #[pyo3(get)]
+    bytes_reverified: u64,
+    #[pyo3(get)]
+    bytes_redownloaded: u64,
+    #[pyo3(get)]
+    verified: bool,
+    #[pyo3(get)]
+    digest: Option<String>,
+    #[pyo3(get)]
+    attempts: u32,
+}
+
+impl MegaDownloadResult {
+    /// The string downloads used to return.
+    fn legacy(&self) -> &str {
+        self.digest.as_deref().unwrap_or("Download complete")
+    }
+}
+
+#[pymethods]
+impl MegaDownloadResult {
+    /// Convert to a JSON-compatible dict.
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("local_path", &self.local_path)?;
+        dict.set_item("bytes_transferred", self.bytes_transferred)?;
+        dict.set_item("bytes_reused_from_resume", self.bytes_reused_from_resume)?;
+        dict.set_item("bytes_reverified", self.bytes_reverified)?;
+        dict.set_item("bytes_redownloaded", self.bytes_redownloaded)?;
+        dict.set_item("verified", self.verified)?;
+        dict.set_item("digest", &self.digest)?;
+        dict.set_item("attempts", self.attempts)?;
+        Ok(dict)
+    }
+
+    /// Deprecated: equal to the string downloads used to return, the hex
+    /// digest if one was requested and "Download complete" otherwise.
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        legacy_compare(py, self.legacy(), other, op, "MegaDownloadResult")
+    }
+
+    /// The hash of the legacy string, since the result compares equal to it.
+    fn __hash__(&self, py: Python<'_>) -> PyResult<isize> {
+        self.legacy().to_object(py).as_ref(py).hash()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MegaDownloadResult({:?}, bytes_transferred={})",
+            self.local_path, self.bytes_transferred
+        )
+    }
+}
+
+/// Result of a completed upload.
+///
+/// Attributes:
+///     node: MegaNode of the uploaded file, None if it isn't in the local
+///         tree yet
+///     bytes: Size of the uploaded file in bytes
+///     skipped: True if nothing was uploaded because an identical file was
+///         already in the folder (see upload(skip_duplicates=True))
+#[pyclass]
+struct MegaUploadResult {
+    #[pyo3(get)]
+    node: Option<MegaNode>,
+    #[pyo3(get)]
+    bytes: u64,
+    #[pyo3(get)]
+    skipped: bool,
+}
+
+impl MegaUploadResult {
+    /// Describe the upload of `local_path` into `remote_dir`.
+    fn new(session: &Session, local_path: &std::path::Path, remote_dir: &str) -> PyResult<Self> {
+        let bytes = std::fs::metadata(local_path)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?
+            .len();
+        let node = local_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|name| {
+                session.stat(&format!(
+                    "{}/{}",
+                    expand_virtual_path(remote_dir).trim_end_matches('/'),
+                    name
+                ))
+            });
+        Ok(MegaUploadResult {
+            node: node.map(|node| MegaNode::with_path(session, node)),
+            bytes,
+            skipped: false,
+        })
+    }
+}
+
+#[pymethods]
+impl MegaUploadResult {
+    /// Convert to a JSON-compatible dict; node is its handle or None.
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("handle", self.node.as_ref().map(|node| &node.handle))?;
+        dict.set_item("bytes", self.bytes)?;
+        dict.set_item("skipped", self.skipped)?;
+        Ok(dict)
+    }
+
+    /// Deprecated: equal to "Upload complete", which uploads used to return.
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        legacy_compare(py, "Upload complete", other, op, "MegaUploadResult")
+    }
+
+    /// The hash of "Upload complete", since the result compares equal to it.
+    fn __hash__(&self, py: Python<'_>) -> PyResult<isize> {
+        "Upload complete".to_object(py).as_ref(py).hash()
+    }
+
+    fn __repr__(&self) -> String {
+        match &self.node {
+            Some(node) => format!("MegaUploadResult({:?}, bytes={})", node.name, self.bytes),
+            None => format!("MegaUploadResult(bytes={})", self.bytes),
+        }
+    }
+}
+
+/// Storage quota of an account.
+///
+/// Attributes:
+///     total: Storage quota in bytes
+///     used: Bytes in use
+///     free: Bytes still available (0 when over quota)
+#[pyclass]
+struct MegaQuotaInfo {
+    #[pyo3(get)]
+    total: u64,
+    #[pyo3(get)]
+    used: u64,
+}
+
+#[pymethods]
+impl MegaQuotaInfo {
+    #[getter]
+    fn free(&self) -> u64 {
+        self.total.saturating_sub(self.used)
+    }
+
+    /// Convert to a JSON-compatible dict.
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("total", self.total)?;
+        dict.set_item("used", self.used)?;
+        dict.set_item("free", self.free())?;
+        Ok(dict)
+    }
+
+    /// Deprecated: unpacks like the (total, used) tuple quota() used to
+    /// return.
+    fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        warn_legacy_result(py, "MegaQuotaInfo", "the total and used attributes")?;
+        let items = pyo3::types::PyTuple::new(py, [self.total, self.used]);
+        Ok(items.call_method0("__iter__")?.into())
+    }
+
+    /// Deprecated: indexes like the (total, used) tuple quota() used to
+    /// return.
+    fn __getitem__(&self, py: Python<'_>, index: isize) -> PyResult<u64> {
+        warn_legacy_result(py, "MegaQuotaInfo", "the total and used attributes")?;
+        match index {
+            0 | -2 => Ok(self.total),
+            1 | -1 => Ok(self.used),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyIndexError, _>(
+                "MegaQuotaInfo index out of range",
+            )),
+        }
+    }
+
+    /// Deprecated: equal to the (total, used) tuple quota() used to return.
+    fn __richcmp__(&self, py: Python<'_>, other: &PyAny, op: CompareOp) -> PyResult<PyObject> {
+        let legacy = pyo3::types::PyTuple::new(py, [self.total, self.used]);
+        legacy_compare(py, legacy, other, op, "MegaQuotaInfo")
+    }
+
+    /// The hash of the (total, used) tuple, since the result compares equal
+    /// to it.
+    fn __hash__(&self, py: Python<'_>) -> PyResult<isize> {
+        pyo3::types::PyTuple::new(py, [self.total, self.used]).hash()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("MegaQuotaInfo(total={}, used={})", self.total, self.used)
+    }
+}
+
+/// Emit the DeprecationWarning for code using a result object like the
+/// plain value the method returned before.
+fn warn_legacy_result(py: Python<'_>, class: &str, instead: &str) -> PyResult<()> {
+    PyErr::warn(
+        py,
+        py.get_type::<pyo3::exceptions::PyDeprecationWarning>(),
+        &format!(
+            "Using a {} like the plain value returned by earlier versions is \
+             deprecated and will stop working in the next release; use {}",
+            class, instead
+        ),
+        2,
+    )
+}
+
+/// Compare a result object with the value its method used to return.
+///
+/// Comparing with a value of that type warns and compares with `legacy`;
+/// everything else is compared by identity as before.
+fn legacy_compare(
+    py: Python<'_>,
+    legacy: impl ToPyObject,
+    other: &PyAny,
+    op: CompareOp,
+    class: &str,
+) -> PyResult<PyObject> {
+    let legacy = legacy.to_object(py);
+    let legacy = legacy.as_ref(py);
+    if !matches!(op, CompareOp::Eq | CompareOp::Ne) || !other.get_type().is(legacy.get_type()) {
+        return Ok(py.NotImplemented());
+    }
+    warn_legacy_result(py, class, "its attributes")?;
+    Ok(legacy.rich_compare(other, op)?.into())
+}
+
+/// One entry of a MegaTransferSummary.
+///
+/// Attributes:
+///     path: Local or remote path of the item
+///     handle: Node handle, None if no node was involved or created
+///     action: What was done, e.g. "uploaded", "downloaded", "skipped" or
+///         "failed"
+///     bytes: Bytes transferred for this item
+///     detail: Extra information about the item (e.g. a share recipient),
+///         None if there is none
+///     error: Error message if the item failed, otherwise None
+///     error_type: Name of the exception class the error was raised as
+///         (e.g. "OverQuotaError"), None if the item succeeded
+///     duration: Seconds spent on this item
+#[pyclass]
+#[derive(Clone)]
+struct MegaTransferItem {
+    #[pyo3(get)]
+    path: String,
+    #[pyo3(get)]
+    handle: Option<String>,
+    #[pyo3(get)]
+    action: String,
+    #[pyo3(get)]
+    bytes: u64,
+    #[pyo3(get)]
+    detail: Option<String>,
+    #[pyo3(get)]
+    error: Option<String>,
+    #[pyo3(get)]
+    error_type: Option<String>,
+    #[pyo3(get)]
+    duration: f64,
+}
+
+#[pymethods]
+impl MegaTransferItem {
+    /// True if the item didn't fail.
+    #[getter]
+    fn ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Convert to a JSON-compatible dict (see MegaTransferSummary.to_dict).
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("path", &self.path)?;
+        dict.set_item("handle", &self.handle)?;
+        dict.set_item("action", &self.action)?;
+        dict.set_item("bytes", self.bytes)?;
+        dict.set_item("detail", &self.detail)?;
+        dict.set_item("error", &self.error)?;
+        dict.set_item("error_type", &self.error_type)?;
+        dict.set_item("duration", self.duration)?;
+        Ok(dict)
+    }
+
+    /// Rebuild an item from the output of to_dict().
+    #[staticmethod]
+    fn from_dict(data: &pyo3::types::PyDict) -> PyResult<Self> {
+        fn field<'a, T: FromPyObject<'a>>(data: &'a pyo3::types::PyDict, key: &str) -> PyResult<T> {
+            match data.get_item(key)? {
+                Some(value) => value.extract(),
+                None => Err(PyErr::new::<pyo3::exceptions::PyKeyError, _>(
+                    key.to_string(),
+                )),
+            }
+        }
+        Ok(MegaTransferItem {
+            path: field(data, "path")?,
+            handle: field(data, "handle")?,
+            action: field(data, "action")?,
+            bytes: field(data, "bytes")?,
+            detail: field(data, "detail")?,
+            error: field(data, "error")?,
+            error_type: field(data, "error_type")?,
+            duration: field(data, "duration")?,
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        match &self.error_type {
+            Some(error_type) => format!(
+                "MegaTransferItem({:?}, action={:?}, error_type={:?})",
+                self.path, self.action, error_type
+            ),
+            None => format!(
+                "MegaTransferItem({:?}, action={:?}, bytes={})",
+                self.path, self.action, self.bytes
+            ),
+        }
+    }
+}
+
+/// Per-item report of a batch operation.
+///
+/// The common result type for operations that transfer or change many
+/// items, so logging and metrics code only has to handle one shape.
+/// to_dict() output is JSON-serializable and from_dict() restores it.
+///
+/// Attributes:
+///     items: List of MegaTransferItem, in processing order
+///     bytes: Total bytes transferred
+///     duration: Total seconds spent on the items
+#[pyclass]
+struct MegaTransferSummary {
+    #[pyo3(get)]
+    items: Vec<MegaTransferItem>,
+}
+
+#[pymethods]
+impl MegaTransferSummary {
+    #[getter]
+    fn bytes(&self) -> u64 {
+        self.items.iter().map(|item| item.bytes).sum()
+    }
+
+    #[getter]
+    fn duration(&self) -> f64 {
+        self.items.iter().map(|item| item.duration).sum()
+    }
+
+    /// Items that succeeded.
+    fn ok(&self) -> Vec<MegaTransferItem> {
+        self.items
+            .iter()
+            .filter(|item| item.ok())
+            .cloned()
+            .collect()
+    }
+
+    /// Items that failed.
+    fn failed(&self) -> Vec<MegaTransferItem> {
+        self.items
+            .iter()
+            .filter(|item| !item.ok())
+            .cloned()
+            .collect()
+    }
+
+    /// Convert to a JSON-compatible dict: {"items": [item dicts]}.
+    fn to_dict<'p>(&self, py: Python<'p>) -> PyResult<&'p pyo3::types::PyDict> {
+        let items = self
+            .items
+            .iter()
+            .map(|item| item.to_dict(py))
+            .collect::<PyResult<Vec<_>>>()?;
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("items", items)?;
+        Ok(dict)
+    }
+
+    /// Rebuild a summary from the output of to_dict().
+    #[staticmethod]
+    fn from_dict(data: &pyo3::types::PyDict) -> PyResult<Self> {
+        let items = data
+            .get_item("items")?
+            .ok_or_else(|| PyErr::new::<pyo3::exceptions::PyKeyError, _>("items"))?;
+        let items = items
+            .iter()?
+            .map(|item| MegaTransferItem::from_dict(item?.downcast()?))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(MegaTransferSummary { items })
+    }
+
+    fn __len__(&self) -> usize {
+        self.items.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MegaTransferSummary(ok={}, failed={}, bytes={})",
+            self.ok().len(),
+            self.failed().len(),
+            self.bytes()
+        )
+    }
+}
+
+/// Items of a composite transfer so far, shared with the awaitable
+/// returned to Python so a cancelled call can still report them (see
+/// with_partial_summary()).
+#[derive(Clone, Default)]
+struct PartialSummary(Arc<std::sync::Mutex<Vec<MegaTransferItem>>>);
+
+impl PartialSummary {
+    fn push(&self, item: MegaTransferItem) {
+        self.0.lock().unwrap().push(item);
+    }
+
+    fn extend(&self, items: impl IntoIterator<Item = MegaTransferItem>) {
+        self.0.lock().unwrap().extend(items);
+    }
+
+    fn snapshot(&self) -> MegaTransferSummary {
+        MegaTransferSummary {
+            items: self.0.lock().unwrap().clone(),
+        }
+    }
+
+    fn finish(&self) -> MegaTransferSummary {
+        MegaTransferSummary {
+            items: std::mem::take(&mut *self.0.lock().unwrap()),
+        }
+    }
+}
+
+/// Wraps the awaitable of a composite transfer in a task that sets
+/// partial_summary on the CancelledError raised when it is cancelled.
+const PARTIAL_SUMMARY: &str = r#"
+import asyncio
+
+
+async def _attach(awaitable, summary):
+    try:
+        return await awaitable
+    except asyncio.CancelledError as e:
+        e.partial_summary = summary()
+        raise
+
+
+def attach(awaitable, summary):
+    return asyncio.ensure_future(_attach(awaitable, summary))
+"#;
+
+static PARTIAL_ATTACH: pyo3::sync::GILOnceCell<PyObject> = pyo3::sync::GILOnceCell::new();
+
+/// Return `awaitable` as a task whose CancelledError carries the items of
+/// `summary` finished before the cancellation, as a MegaTransferSummary in
+/// its partial_summary attribute. Cancelling the task cancels the transfer,
+/// aborting the file in flight.
+fn with_partial_summary<'p>(
+    py: Python<'p>,
+    awaitable: &'p PyAny,
+    summary: PartialSummary,
+) -> PyResult<&'p PyAny> {
+    let attach = PARTIAL_ATTACH.get_or_try_init(py, || -> PyResult<PyObject> {
+        Ok(
+            PyModule::from_code(py, PARTIAL_SUMMARY, "megalib_partial.py", "megalib_partial")?
+                .getattr("attach")?
+                .into(),
+        )
+    })?;
+    let snapshot = pyo3::types::PyCFunction::new_closure(
+        py,
+        None,
+        None,
+        move |_args: &pyo3::types::PyTuple, _kwargs: Option<&pyo3::types::PyDict>| {
+            summary.snapshot()
+        },
+    )?;
+    Ok(attach.call1(py, (awaitable, snapshot))?.into_ref(py))
+}
+
+/// States of a MegaQueueEntry.
+const QUEUE_PENDING: &str = "pending";
+const QUEUE_DONE: &str = "done";
+const QUEUE_FAILED: &str = "failed";
+
+/// A transfer in the persistent queue (see MegaSession.set_queue_path()).
+///
+/// Attributes:
+///     id: Position in the queue, starting at 1
+///     kind: "download" or "upload"
+///     remote_path: File on MEGA (download) or destination folder (upload)
+///     local_path: Destination file (download) or source file (upload)
+///     state: "pending", "done" or "failed"
+///     error: Error message of the last failed attempt, otherwise None
+#[pyclass]
+#[derive(Clone)]
+struct MegaQueueEntry {
+    #[pyo3(get)]
+    id: u64,
+    #[pyo3(get)]
+    kind: String,
+    #[pyo3(get)]
+    remote_path: String,
+    #[pyo3(get)]
+    local_path: String,
+    #[pyo3(get)]
+    state: String,
+    #[pyo3(get)]
+    error: Option<String>,
+}
+
+#[pymethods]
+impl MegaQueueEntry {
+    fn __repr__(&self) -> String {
+        format!(
+            "MegaQueueEntry({}, {:?}, {:?}, {:?}, state={:?})",
+            self.id, self.kind, self.remote_path, self.local_path, self.state
+        )
+    }
+}
+
+/// One journal line: the JSON record, a tab and the first 16 hex digits of
+/// the record's SHA-256.
+///
+/// The checksum catches lines torn by a crash or edited by hand, so a
+/// damaged journal is reported instead of silently losing entries.
+fn queue_line(record: &serde_json::Value) -> String {
+    let json = record.to_string();
+    format!("{}\t{}\n", json, queue_checksum(&json))
+}
+
+fn queue_checksum(json: &str) -> String {
+    use sha2::Digest;
+    let digest = sha2::Sha256::digest(json.as_bytes());
+    format!("{:x}", digest)[..16].to_string()
+}
+
+fn queue_corrupt(path: &std::path::Path, line: usize, why: &str) -> PyErr {
+    QueueCorruptError::new_err(format!("{}, line {}: {}", path.display(), line, why))
+}
+
+/// Replay the journal at `path` into the queue's entries, in id order.
+///
+/// "add" records create entries, "done" and "failed" records set the
+/// outcome of the latest attempt. A missing journal is an empty queue.
+fn read_queue(path: &std::path::Path) -> PyResult<Vec<MegaQueueEntry>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string())),
+    };
+    let mut entries: Vec<MegaQueueEntry> = Vec::new();
+    for (n, line) in data.split(|&b| b == b'\n').enumerate() {
+        let n = n + 1;
+        if line.is_empty() {
+            continue;
+        }
+        let line = std::str::from_utf8(line).map_err(|_| queue_corrupt(path, n, "not UTF-8"))?;
+        let (json, sum) = line
+            .rsplit_once('\t')
+            .ok_or_else(|| queue_corrupt(path, n, "missing checksum"))?;
+        if sum != queue_checksum(json) {
+            return Err(queue_corrupt(path, n, "checksum mismatch"));
+        }
+        let record: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| queue_corrupt(path, n, &e.to_string()))?;
+        let text = |key: &str| record.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        let id = record
+            .get("id")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| queue_corrupt(path, n, "record has no id"))?;
+        match text("op").as_deref() {
+            Some("add") => {
+                let (Some(kind), Some(remote_path), Some(local_path)) =
+                    (text("kind"), text("remote"), text("local"))
+                else {
+                    return Err(queue_corrupt(path, n, "incomplete add record"));
+                };
+                if id != entries.len() as u64 + 1 {
+                    return Err(queue_corrupt(path, n, "entry id out of sequence"));
+                }
+                entries.push(MegaQueueEntry {
+                    id,
+                    kind,
+                    remote_path,
+                    local_path,
+                    state: QUEUE_PENDING.to_string(),
+                    error: None,
+                });
+            }
+            Some(op @ (QUEUE_DONE | QUEUE_FAILED)) => {
+                let entry = (id as usize)
+                    .checked_sub(1)
+                    .and_then(|i| entries.get_mut(i))
+                    .ok_or_else(|| queue_corrupt(path, n, "record for an unknown entry"))?;
+                entry.state = op.to_string();
+                entry.error = text("error");
+            }
+            _ => return Err(queue_corrupt(path, n, "unknown record type")),
+        }
+    }
+    Ok(entries)
+}
+
+/// Append one record to the journal and flush it to disk.
+fn append_queue(path: &std::path::Path, record: &serde_json::Value) -> PyResult<()> {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| {
+            file.write_all(queue_line(record).as_bytes())?;
+            file.sync_data()
+        })
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
+}
+
+/// Add a transfer to the journal at `path`, returning the new entry.
+fn enqueue(
+    path: &std::path::Path,
+    kind: &str,
+    remote_path: String,
+    local_path: &std::path::Path,
+) -> PyResult<MegaQueueEntry> {
+    let local_path = local_path.to_str().ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>("Queued local paths must be valid UTF-8")
+    })?;
+    let entry = MegaQueueEntry {
+        id: read_queue(path)?.len() as u64 + 1,
+        kind: kind.to_string(),
+        remote_path,
+        local_path: local_path.to_string(),
+        state: QUEUE_PENDING.to_string(),
+        error: None,
+    };
+    append_queue(
+        path,
+        &serde_json::json!({
+            "op": "add",
+            "id": entry.id,
+            "kind": entry.kind,
+            "remote": entry.remote_path,
+            "local": entry.local_path,
+        }),
+    )?;
+    Ok(entry)
+}
+
+/// Run one queued transfer, returning the handle of the node involved and
+/// the bytes transferred.
+async fn run_queued(
+    session: &mut Session,
+    entry: &MegaQueueEntry,
+    resume: bool,
+) -> PyResult<(Option<String>, u64)> {
+    let local_path = std::path::Path::new(&entry.local_path);
+    if entry.kind == "upload" {
+        ensure_access(session, &entry.remote_path, ACCESS_READ_WRITE)?;
+        api(session.upload_resumable(local_path, &entry.remote_path))
+            .await
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        let result = MegaUploadResult::new(session, local_path, &entry.remote_path)?;
+        count_uploaded(result.bytes);
+        return Ok((result.node.map(|node| node.handle), result.bytes));
+    }
+    let node = session
+        .stat(&expand_virtual_path(&entry.remote_path))
+        .cloned()
+        .ok_or_else(|| NotFoundError::new_err(format!("{} not found", entry.remote_path)))?;
+    let bytes = download_node(session, &node, local_path, resume).await?;
+    Ok((Some(node.handle), bytes))
+}
+
+/// One step of download_folder(): create a local directory, or download
+/// a file.
+enum FolderStep {
+    Dir {
+        path: String,
+        handle: String,
+        local_path: std::path::PathBuf,
+    },
+    File {
+        path: String,
+        node: Node,
+        local_path: std::path::PathBuf,
+    },
+}
+
+/// Plan the download of the folder at `root` into `local_dir`: the steps in
+/// order, folders before their contents, and the items skipped because
+/// their names can't be used locally.
+fn plan_folder_download(
+    session: &Session,
+    root: &str,
+    local_dir: &std::path::Path,
+) -> PyResult<(Vec<FolderStep>, Vec<MegaTransferItem>)> {
+    let mut steps = Vec::new();
+    let mut skipped = Vec::new();
+    let mut folders = vec![(root.to_string(), local_dir.to_path_buf())];
+    while let Some((folder, dir)) = folders.pop() {
+        let children = session
+            .list(&folder, false)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+        for node in children {
+            let path = format!("{}/{}", folder.trim_end_matches('/'), node.name);
+            if !is_safe_local_name(&node.name) {
+                skipped.push(MegaTransferItem {
+                    path,
+                    handle: Some(node.handle.clone()),
+                    action: "skipped".to_string(),
+                    bytes: 0,
+                    detail: Some("name can't be used locally".to_string()),
+                    error: None,
+                    error_type: None,
+                    duration: 0.0,
+                });
+                continue;
+            }
+            let local_path = dir.join(&node.name);
+            if node.node_type.is_container() {
+                steps.push(FolderStep::Dir {
+                    path: path.clone(),
+                    handle: node.handle.clone(),
+                    local_path: local_path.clone(),
+                });
+                folders.push((path, local_path));
+            } else {
+                steps.push(FolderStep::File {
+                    path,
+                    node: node.clone(),
+                    local_path,
+                });
+            }
+        }
+    }
+    Ok((steps, skipped))
+}
+
+/// Name of the ignore files read by upload_folder(use_ignore_files=True).
+const IGNORE_FILE: &str = ".megaignore";
+
+/// One pattern of an ignore file.
+struct IgnoreRule {
+    /// "<ignore file>:<line>", for errors and skipped items.
+    source: String,
+    pattern: String,
+    /// "!pattern": re-include what an earlier pattern excluded.
+    negated: bool,
+    /// "pattern/": only matches directories.
+    dir_only: bool,
+    /// A slash other than a trailing one anchors the pattern to the ignore
+    /// file's directory; otherwise it matches names at any depth.
+    anchored: bool,
+    parts: Vec<GlobPart>,
+}
+
+/// The rules of one ignore file, which apply below `dir`.
+struct IgnoreFile {
+    dir: std::path::PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreFile {
+    /// Read the ignore file of `dir`, if it has one.
+    fn load(dir: &std::path::Path) -> PyResult<Option<IgnoreFile>> {
+        let path = dir.join(IGNORE_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                    "{}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        let rules = IgnoreRule::parse_all(&path.to_string_lossy(), &text)?;
+        Ok(Some(IgnoreFile {
+            dir: dir.to_path_buf(),
+            rules,
+        }))
+    }
+}
+
+impl IgnoreRule {
+    /// Parse the text of the ignore file `name`, in gitignore syntax: one
+    /// pattern per line, blank lines and lines starting with "#" ignored,
+    /// "!" negates, a trailing "/" matches directories only, "**" spans
+    /// folders, and "\#" or "\!" start a pattern with those characters.
+    fn parse_all(name: &str, text: &str) -> PyResult<Vec<IgnoreRule>> {
+        let mut rules = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let source = format!("{}:{}", name, index + 1);
+            let invalid = |reason: &str| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "{}: invalid pattern {:?}: {}",
+                    source, line, reason
+                ))
+            };
+            let mut pattern = line.trim_end_matches([' ', '\t']);
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            if pattern.ends_with('\\') {
+                return Err(invalid("ends with a backslash"));
+            }
+            let negated = pattern.starts_with('!');
+            if negated {
+                pattern = &pattern[1..];
+            }
+            if pattern.starts_with("\\#") || pattern.starts_with("\\!") {
+                pattern = &pattern[1..];
+            }
+            let dir_only = pattern.ends_with('/');
+            let body = pattern.trim_end_matches('/');
+            if body.is_empty() {
+                return Err(invalid("empty pattern"));
+            }
+            let anchored = body.contains('/');
+            let parts = body
+                .split('/')
+                .filter(|part| !part.is_empty())
+                .map(|part| match part {
+                    "**" => Ok(GlobPart::Recurse),
+                    _ => regex::Regex::new(&glob_to_regex(part))
+                        .map(GlobPart::Name)
+                        .map_err(|e| invalid(&e.to_string())),
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            rules.push(IgnoreRule {
+                source,
+                pattern: line.trim_end_matches([' ', '\t']).to_string(),
+                negated,
+                dir_only,
+                anchored,
+                parts,
+            });
+        }
+        Ok(rules)
+    }
+
+    /// Whether the rule matches the entry at `names`, its path relative to
+    /// the ignore file's directory.
+    fn matches(&self, names: &[&str], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            glob_parts_match(&self.parts, names)
+        } else {
+            names
+                .last()
+                .is_some_and(|name| glob_parts_match(&self.parts, &[name]))
+        }
+    }
+}
+
+/// Whether the glob components `parts` match the path `names` exactly.
+fn glob_parts_match(parts: &[GlobPart], names: &[&str]) -> bool {
+    match parts.split_first() {
+        None => names.is_empty(),
+        // A trailing "**" matches what is inside, not the folder itself.
+        Some((GlobPart::Recurse, [])) => !names.is_empty(),
+        Some((GlobPart::Recurse, rest)) => {
+            (0..=names.len()).any(|skip| glob_parts_match(rest, &names[skip..]))
+        }
+        Some((GlobPart::Name(regex), rest)) => names
+            .split_first()
+            .is_some_and(|(name, names)| regex.is_match(name) && glob_parts_match(rest, names)),
+    }
+}
+
+/// The rule excluding `path`, given the ignore files that apply to it from
+/// the outermost in. Later rules take precedence over earlier ones, and
+/// those of nested files over their parents', as in git.
+fn ignored_by<'a>(
+    files: &'a [Arc<IgnoreFile>],
+    path: &std::path::Path,
+    is_dir: bool,
+) -> Option<&'a IgnoreRule> {
+    let mut matched = None;
+    for file in files {
+        let Ok(relative) = path.strip_prefix(&file.dir) else {
+            continue;
+        };
+        let names: Vec<&str> = relative.iter().filter_map(|name| name.to_str()).collect();
+        for rule in &file.rules {
+            if rule.matches(&names, is_dir) {
+                matched = Some(rule);
+            }
+        }
+    }
+    matched.filter(|rule| !rule.negated)
+}
+
+/// One step of upload_folder(): create a folder named after `local_path`
+/// in `remote_dir`, or upload a file into it.
+enum UploadStep {
+    Dir {
+        local_path: std::path::PathBuf,
+        remote_dir: String,
+        name: String,
+    },
+    File {
+        local_path: std::path::PathBuf,
+        remote_dir: String,
+        size: u64,
+    },
+}
+
+/// Plan the upload of the local directory `root` into `remote_dir`: the
+/// steps in order, folders before their contents, and the entries skipped
+/// or failed while walking it. With `use_ignore_files`, entries matched by
+/// the IGNORE_FILE of their directory or one above are skipped.
+fn plan_folder_upload(
+    root: &std::path::Path,
+    remote_dir: &str,
+    follow_symlinks: bool,
+    use_ignore_files: bool,
+) -> PyResult<(Vec<UploadStep>, Vec<MegaTransferItem>)> {
+    let io_err = |e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+    let root = std::fs::canonicalize(root).map_err(io_err)?;
+    let mut steps = Vec::new();
+    let mut items = Vec::new();
+    // Each folder carries the canonical paths of the directories above
+    // it, to catch symlinks pointing back up, and the ignore files that
+    // apply to it.
+    let mut folders = vec![(root.clone(), remote_dir.to_string(), vec![root], Vec::new())];
+    while let Some((dir, remote, ancestors, mut ignores)) = folders.pop() {
+        if use_ignore_files {
+            if let Some(file) = IgnoreFile::load(&dir)? {
+                ignores.push(Arc::new(file));
+            }
+        }
+        let mut entries = std::fs::read_dir(&dir)
+            .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+            .map_err(io_err)?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let local_path = entry.path();
+            let item = |action: &str, detail: &str, error: Option<PyErr>| MegaTransferItem {
+                path: local_path.to_string_lossy().into_owned(),
+                handle: None,
+                action: action.to_string(),
+                bytes: 0,
+                detail: Some(detail.to_string()),
+                error: error.as_ref().map(|e| e.to_string()),
+                error_type: error.as_ref().and_then(exception_name),
+                duration: 0.0,
+            };
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            if is_symlink && !follow_symlinks {
+                items.push(item("skipped", "symlink", None));
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                items.push(item("skipped", "name isn't valid UTF-8", None));
+                continue;
+            };
+            let meta = std::fs::metadata(&local_path);
+            if let Some(rule) = meta
+                .as_ref()
+                .ok()
+                .and_then(|meta| ignored_by(&ignores, &local_path, meta.is_dir()))
+            {
+                let reason = format!("ignored by {}: {}", rule.source, rule.pattern);
+                items.push(item("skipped", &reason, None));
+                continue;
+            }
+            match meta {
+                Ok(meta) if meta.is_dir() => match std::fs::canonicalize(&local_path) {
+                    Ok(target) if ancestors.contains(&target) => {
+                        items.push(item("skipped", "symlink loop", None));
+                    }
+                    Ok(target) => {
+                        let mut ancestors = ancestors.clone();
+                        ancestors.push(target);
+                        folders.push((
+                            local_path.clone(),
+                            format!("{}/{}", remote, name),
+                            ancestors,
+                            ignores.clone(),
+                        ));
+                        steps.push(UploadStep::Dir {
+                            local_path,
+                            remote_dir: remote.clone(),
+                            name,
+                        });
+                    }
+                    Err(e) => items.push(item("failed", &remote, Some(io_err(e)))),
+                },
+                Ok(meta) => steps.push(UploadStep::File {
+                    local_path,
+                    remote_dir: remote.clone(),
+                    size: meta.len(),
+                }),
+                Err(e) => items.push(item("failed", &remote, Some(io_err(e)))),
+            }
+        }
+    }
+    Ok((steps, items))
+}
+
+/// Download the file `node` to `local_path` like download_to_file(),
+/// returning the number of bytes transferred.
+async fn download_node(
+    session: &mut Session,
+    node: &Node,
+    local_path: &std::path::Path,
+    resume: bool,
+) -> PyResult<u64> {
+    ensure_available(node)?;
+    let reused = if resume {
+        partial_download_len(local_path, node.size)
+    } else {
+        0
+    };
+    ensure_disk_space(local_path, node.size)?;
+    // Without resume a cancelled download's file is useless.
+    let mut partial = PartialFile((!resume).then(|| local_path.to_path_buf()));
+    let outcome = api(session.download_to_file(node, local_path)).await;
+    partial.keep();
+    outcome.map_err(|e| match &e {
+        ::megalib::MegaError::Io(io) if is_disk_full(io) => disk_full_mid_download(local_path),
+        _ => mega_err::<pyo3::exceptions::PyRuntimeError>(e),
+    })?;
+    count_downloaded(node.size - reused);
+    Ok(node.size - reused)
+}
+
+/// True if a node name can be used as a single local path component
+/// without leaving the directory it's joined to.
+fn is_safe_local_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+        && !(cfg!(windows) && name.contains(':'))
+}
+
+/// Create the folder `path` unless something exists there already.
+///
+/// The parent must exist. Returns whether the folder was created.
+async fn ensure_folder(session: &mut Session, path: &str) -> PyResult<bool> {
+    if session.stat(path).is_some() {
+        return Ok(false);
+    }
+    match api(session.mkdir(path)).await {
+        Ok(_) => Ok(true),
+        Err(::megalib::MegaError::ApiError { code: EEXIST, .. }) => {
+            // Lost a race with another client: pick up its folder.
+            fetch_tree(session, false).await?;
+            Ok(false)
+        }
+        Err(e) => Err(mega_err::<pyo3::exceptions::PyRuntimeError>(e)),
+    }
+}
+
+/// Journal path set with set_queue_path(), or an error if there is none.
+fn queue_path(path: &Option<std::path::PathBuf>) -> PyResult<&std::path::Path> {
+    path.as_deref().ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "No transfer queue; call set_queue_path() first",
+        )
+    })
+}
+
+/// Authenticated MEGA session for file operations.
+///
+/// Create a session using `login()` or `load()`, which fetch your file tree
+/// unless called with auto_refresh=False.
+///
+/// A session may be shared between threads, each running its own event
+/// loop. Every call binds to the loop running in the calling thread, and
+/// calls on one session are serialized by its lock, so they never
+/// interleave. The GIL is not held while waiting for that lock.
+///
+/// Example:
+///     session = await MegaSession.login("user@example.com", "password")
+///     files = await session.list("/")
+#[pyclass]
+struct MegaSession {
+    inner: Arc<Mutex<Session>>,
+    /// Mirrors the resume setting passed to megalib via set_resume().
+    resume: Arc<AtomicBool>,
+    /// Set once the file tree has been fetched.
+    refreshed: Arc<AtomicBool>,
+    /// Set while the tree is incomplete because refresh() hit max_nodes.
+    partial: Arc<AtomicBool>,
+    /// Where scratch files go, None for the system temp directory.
+    temp_dir: Arc<std::sync::Mutex<Option<std::path::PathBuf>>>,
+    /// Journal of the persistent transfer queue, None until
+    /// set_queue_path() is called. The lock serializes journal writes.
+    queue: Arc<Mutex<Option<std::path::PathBuf>>>,
+    /// Set while run_queue() is working through the queue.
+    queue_running: Arc<AtomicBool>,
+    /// Held exclusively while refresh() fetches a new tree, and shared by
+    /// methods that need the session mutably (see run_mut()).
+    refresh_gate: Arc<tokio::sync::RwLock<()>>,
+    /// Set on views returned by read_only().
+    read_only: bool,
+    /// Counters for usage_stats(), shared with read-only views.
+    usage: Arc<SessionUsage>,
+    /// Default time limit of calls, set by set_timeout().
+    timeout: Arc<std::sync::Mutex<Option<std::time::Duration>>>,
+    /// Transfer rate limit, set by set_bandwidth_limit().
+    throttle: Arc<Throttle>,
+}
+
+impl MegaSession {
+    fn new(mut session: Session, refreshed: bool) -> Self {
+        let throttle = Arc::<Throttle>::default();
+        watch_throttle(&mut session, &throttle);
+        MegaSession {
+            inner: Arc::new(Mutex::new(session)),
+            resume: Arc::new(AtomicBool::new(false)),
+            refreshed: Arc::new(AtomicBool::new(refreshed)),
+            partial: Arc::new(AtomicBool::new(false)),
+            temp_dir: Arc::new(std::sync::Mutex::new(None)),
+            queue: Arc::default(),
+            queue_running: Arc::new(AtomicBool::new(false)),
+            refresh_gate: Arc::default(),
+            read_only: false,
+            usage: Arc::default(),
+            timeout: Arc::default(),
+            throttle,
+        }
+    }
+
+    /// Run a method's future with this session's usage counters in scope.
+    fn run<'p, F, T>(&self, py: Python<'p>, fut: F) -> PyResult<&'p PyAny>
+    where
+        F: Future<Output = PyResult<T>> + Send + 'static,
+        T: IntoPy<PyObject>,
+    {
+        self.run_within(py, None, fut)
+    }
+
+    /// Like run(), abandoning the future after `timeout` seconds, or the
+    /// set_timeout() default if None. Dropping the future releases the
+    /// session just like cancelling the awaiting task.
+    fn run_within<'p, F, T>(
+        &self,
+        py: Python<'p>,
+        timeout: Option<f64>,
+        fut: F,
+    ) -> PyResult<&'p PyAny>
+    where
+        F: Future<Output = PyResult<T>> + Send + 'static,
+        T: IntoPy<PyObject>,
+    {
+        let limit = match time_limit(timeout)? {
+            Some(limit) => Some(limit),
+            None => *self.timeout.lock().unwrap(),
+        };
+        let fut = Paced::new(self.throttle.clone(), fut);
+        future_into_py(
+            py,
+            SESSION_USAGE.scope(self.usage.clone(), with_timeout(limit, fut)),
+        )
+    }
+
+    /// Like run(), for methods that need the session mutably.
+    ///
+    /// refresh() fetches a new tree on the side and swaps it in at the end,
+    /// so changes made to the tree meanwhile would be lost.
+    /// These methods wait for a running refresh() to finish instead, and
+    /// hold off new ones until they are done; methods that only read the
+    /// tree keep using the current one.
+    fn run_mut<'p, F, T>(&self, py: Python<'p>, fut: F) -> PyResult<&'p PyAny>
+    where
+        F: Future<Output = PyResult<T>> + Send + 'static,
+        T: IntoPy<PyObject>,
+    {
+        self.run_mut_within(py, None, fut)
+    }
+
+    /// run_mut() with a time limit, see run_within(). Waiting for a running
+    /// refresh() counts towards it.
+    fn run_mut_within<'p, F, T>(
+        &self,
+        py: Python<'p>,
+        timeout: Option<f64>,
+        fut: F,
+    ) -> PyResult<&'p PyAny>
+    where
+        F: Future<Output = PyResult<T>> + Send + 'static,
+        T: IntoPy<PyObject>,
+    {
+        let gate = self.refresh_gate.clone();
+        self.run_within(py, timeout, async move {
+            let _gate = gate.read().await;
+            fut.await
+        })
+    }
+
+    /// Fail with ReadOnlyError before anything is changed on a read-only
+    /// view. Every method that modifies the account or the tree calls this
+    /// first.
+    fn writable(&self) -> PyResult<()> {
+        if self.read_only {
+            return Err(ReadOnlyError::new_err("This session is read-only"));
+        }
+        Ok(())
+    }
+
+    /// Directory to create scratch files in.
+    fn temp_dir(&self) -> std::path::PathBuf {
+        self.temp_dir
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// The session, for methods that need the file tree. Raises
+    /// NotRefreshedError if it hasn't been fetched yet.
+    fn tree(&self) -> PyResult<Arc<Mutex<Session>>> {
+        if !self.refreshed.load(Ordering::Relaxed) {
+            return Err(NotRefreshedError::new_err(
+                "call await session.refresh() first",
+            ));
+        }
+        Ok(self.inner.clone())
+    }
+
+    /// Like tree(), but raise PartialTreeError if refresh() stopped at
+    /// max_nodes. For operations whose results would be silently wrong on
+    /// an incomplete tree.
+    fn complete_tree(&self, operation: &str) -> PyResult<Arc<Mutex<Session>>> {
+        let inner = self.tree()?;
+        if self.partial.load(Ordering::Relaxed) {
+            return Err(PartialTreeError::new_err(format!(
+                "{} needs the whole file tree; call refresh() without max_nodes first",
+                operation
+            )));
+        }
+        Ok(inner)
+    }
+}
+
+/// Fetch the file tree.
+///
+/// Unless `strict` is set, nodes whose keys can't be decrypted are left out
+/// of the tree and listed by Session::undecryptable_nodes() instead of
+/// failing the whole refresh.
+async fn fetch_tree(session: &mut Session, strict: bool) -> PyResult<()> {
+    let res = if strict {
+        api(session.refresh()).await
+    } else {
+        api(session.refresh_lenient()).await
+    };
+    res.map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+    Ok(())
+}
+
+/// Check raw node JSON for apply_node_update() against the local tree.
+///
+/// Returns the node's handle. Rejects updates that would leave the tree
+/// inconsistent instead of letting them corrupt it.
+fn validate_node_update(session: &Session, node: &serde_json::Value) -> PyResult<String> {
+    let invalid = |msg: String| PyErr::new::<pyo3::exceptions::PyValueError, _>(msg);
+    let field = |name: &str| {
+        node.get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid(format!("node_json needs a string {:?} field", name)))
+    };
+    let handle = field("h")?;
+    let parent = field("p")?;
+    let is_folder = match node.get("t").and_then(|t| t.as_u64()) {
+        Some(0) => false,
+        Some(1) => true,
+        _ => {
+            return Err(invalid(
+                "node_json \"t\" must be 0 (file) or 1 (folder)".into(),
+            ))
+        }
+    };
+
+    let parent_node = session
+        .get_node_by_handle(parent)
+        .ok_or_else(|| invalid(format!("Parent {} is not in the tree", parent)))?;
+    if !parent_node.node_type.is_container() {
+        return Err(invalid(format!("Parent {} is not a folder", parent)));
+    }
+    if let Some(existing) = session.get_node_by_handle(handle) {
+        if existing.node_type.is_container() != is_folder {
+            return Err(invalid(format!(
+                "Node {} can't change between file and folder",
+                handle
+            )));
+        }
+    }
+    let mut ancestor = Some(parent_node);
+    while let Some(node) = ancestor {
+        if node.handle == handle {
+            return Err(invalid(format!(
+                "Node {} can't be moved below itself",
+                handle
+            )));
+        }
+        ancestor = node
+            .parent
+            .as_deref()
+            .and_then(|p| session.get_node_by_handle(p));
+    }
+    Ok(handle.to_string())
+}
+
+// Sharing a session between threads relies on all of its state being
+// Send + Sync; fail the build rather than the contract if that changes.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<MegaSession>();
+};
+
+#[pymethods]
+impl MegaSession {
+    /// Login to MEGA with email and password.
+    ///
+    /// Args:
+    ///     email: Your MEGA account email
+    ///     password: Your MEGA account password
+    ///     proxy: Optional HTTP/SOCKS5 proxy URL (e.g., "http://proxy:8080")
+    ///     auto_refresh: Fetch the file tree as part of login, so refresh()
+    ///         isn't needed before list(), stat(), etc.
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     Authenticated MegaSession
+    ///
+    /// Raises:
+    ///     ValueError: If login fails (wrong credentials, etc.)
+    #[staticmethod]
+    #[pyo3(signature = (email, password, proxy = None, auto_refresh = true, *, timeout = None))]
+    fn login(
+        py: Python<'_>,
+        email: String,
+        password: String,
+        proxy: Option<String>,
+        auto_refresh: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<&PyAny> {
+        future_into_py(
+            py,
+            with_timeout(time_limit(timeout)?, async move {
+                let res = if let Some(p) = proxy {
+                    api(Session::login_with_proxy(&email, &password, &p)).await
+                } else {
+                    api(Session::login(&email, &password)).await
+                };
+
+                let mut session = res
+                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+                if auto_refresh {
+                    fetch_tree(&mut session, false).await?;
+                }
+                Ok(MegaSession::new(session, auto_refresh))
+            }),
+        )
+    }
+
+    /// Get a read-only view of this session.
+    ///
+    /// The view shares the login, file tree and settings with this session,
+    /// but every method that would modify the account (uploads, rm, mv,
+    /// rename, mkdir, export, sharing, attribute changes, password changes)
+    /// or patch the local tree raises ReadOnlyError before sending anything.
+    /// Views of a view are read-only too.
+    ///
+    /// Returns:
+    ///     Read-only MegaSession
+    fn read_only(&self) -> MegaSession {
+        MegaSession {
+            inner: self.inner.clone(),
+            resume: self.resume.clone(),
+            refreshed: self.refreshed.clone(),
+            partial: self.partial.clone(),
+            temp_dir: self.temp_dir.clone(),
+            queue: self.queue.clone(),
+            queue_running: self.queue_running.clone(),
+            refresh_gate: self.refresh_gate.clone(),
+            read_only: true,
+            usage: self.usage.clone(),
+            timeout: self.timeout.clone(),
+            throttle: self.throttle.clone(),
+        }
+    }
+
+    /// Traffic caused by this session since it was created or last reset.
+    ///
+    /// Counts file payload (not protocol overhead) and API commands of all
+    /// methods of this session and its read-only views. Bytes of downloads
+    /// that failed part way are included, since they were transferred.
+    ///
+    /// Returns:
+    ///     Dict with "bytes_uploaded", "bytes_downloaded" and "requests"
+    fn usage_stats(&self) -> std::collections::HashMap<&'static str, u64> {
+        self.usage.snapshot(false)
+    }
+
+    /// Reset the counters of usage_stats() to zero.
+    ///
+    /// Each counter is read and reset in one atomic step, so transfers
+    /// running concurrently are counted either here or in the next period.
+    ///
+    /// Returns:
+    ///     The counters as they were before the reset
+    fn reset_usage_stats(&self) -> std::collections::HashMap<&'static str, u64> {
+        self.usage.snapshot(true)
+    }
+
+    /// True for views returned by read_only().
+    #[getter]
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Refresh the file tree from the server.
+    ///
+    /// Must be called before using list(), stat(), etc. unless the session
+    /// was created with auto_refresh=True; those methods raise
+    /// NotRefreshedError until it has been.
+    ///
+    /// The new tree is fetched into a structure of its own, outside the
+    /// session lock, and swapped in once complete, so stat(), list() and
+    /// other lookups keep answering from the previous tree while the fetch
+    /// runs and never see a half-built one. The previous tree isn't copied,
+    /// so memory peaks at the old and the new tree. Methods that change the
+    /// session (uploads, mkdir, settings, ...) wait until the swap.
+    ///
+    /// Nodes whose keys can't be decrypted (e.g. after a password reset)
+    /// are left out of the tree; undecryptable_nodes() lists them.
+    ///
+    /// With max_nodes, loading stops once that many nodes are in the tree
+    /// and is_partial_tree becomes True, so accounts flooded with nodes
+    /// don't exhaust memory. Lookups and transfers work on the nodes that
+    /// were loaded, and refresh_subtree() loads more on demand. Operations
+    /// that need the whole tree (storage_breakdown(), audit_exposure(),
+    /// revoke_all(), create_path(), mkdir(exist_ok=True), download_folder()
+    /// and upload_folder()) raise PartialTreeError instead of giving wrong
+    /// answers.
+    ///
+    /// Args:
+    ///     strict: If True, fail on the first undecryptable node instead
+    ///     max_nodes: Maximum number of nodes to load, None for no limit.
+    ///         Can't be combined with strict.
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    #[pyo3(signature = (strict = false, max_nodes = None, *, timeout = None))]
+    fn refresh<'p>(
+        &self,
+        py: Python<'p>,
+        strict: bool,
+        max_nodes: Option<usize>,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        if strict && max_nodes.is_some() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "strict and max_nodes can't be combined",
+            ));
+        }
+        let inner = self.inner.clone();
+        let refreshed = self.refreshed.clone();
+        let partial = self.partial.clone();
+        let gate = self.refresh_gate.clone();
+        self.run_within(py, timeout, async move {
+            let _gate = gate.write().await;
+            // Only what's needed to fetch is taken from the session, not
+            // its tree.
+            let fetcher = inner.lock().await.node_fetcher();
+            let (tree, truncated) = match max_nodes {
+                Some(max_nodes) => api(fetcher.fetch_limited(max_nodes)).await,
+                None if strict => api(fetcher.fetch()).await.map(|tree| (tree, false)),
+                None => api(fetcher.fetch_lenient()).await.map(|tree| (tree, false)),
+            }
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            inner.lock().await.install_nodes(tree);
+            partial.store(truncated, Ordering::Relaxed);
+            refreshed.store(true, Ordering::Relaxed);
+            Ok(())
+        })
+    }
+
+    /// True if the last refresh() stopped at max_nodes.
+    #[getter]
+    fn is_partial_tree(&self) -> bool {
+        self.partial.load(Ordering::Relaxed)
+    }
+
+    /// Load the whole subtree of one folder into a partial tree.
+    ///
+    /// Use after refresh(max_nodes=...) to fill in the folders you need.
+    /// The folder itself must already be loaded. is_partial_tree stays True,
+    /// since other folders may still be incomplete.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of the folder
+    ///
+    /// Returns:
+    ///     Number of nodes added to the tree
+    fn refresh_subtree<'p>(&self, py: Python<'p>, path_or_handle: String) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            let node = resolve_node(&session, &path_or_handle)?;
+            if !node.node_type.is_container() {
+                return Err(PyErr::new::<pyo3::exceptions::PyNotADirectoryError, _>(
+                    format!("{} is not a folder", path_or_handle),
+                ));
+            }
+            let handle = node.handle.clone();
+            api(session.refresh_subtree(&handle))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)
+        })
+    }
+
+    /// Get information about a file or folder.
+    ///
+    /// Args:
+    ///     path: Path to the file/folder (e.g., "/Root/Documents")
+    ///
+    /// Returns:
+    ///     MegaNode if found, None otherwise
+    fn stat<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            match session.stat(&expand_virtual_path(&path)) {
+                Some(node) => Ok(Some(MegaNode::with_path(&session, node))),
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// Get the canonical path of a node.
+    ///
+    /// The canonical path is the path of the node's account root ("/Root",
+    /// "/Vault" or the rubbish bin's path, see roots()) followed by the
+    /// exact stored names of the folders down to the node, joined by single
+    /// slashes, without a trailing slash. "/" becomes "/Root". Paths the
+    /// library returns, e.g. from audit_exposure(), are already canonical,
+    /// and canonical_path() of a canonical path returns it unchanged.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of the node
+    ///
+    /// Returns:
+    ///     The canonical path string
+    ///
+    /// Raises:
+    ///     FileNotFoundError: If the node isn't in the tree
+    ///     ValueError: If the node isn't below an account root (e.g. it is
+    ///         in an incoming share)
+    fn canonical_path<'p>(&self, py: Python<'p>, path_or_handle: String) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let path_or_handle = if path_or_handle == "/" {
+                CLOUD_PATH.to_string()
+            } else {
+                path_or_handle
+            };
+            let session = inner.lock().await;
+            let node = resolve_node(&session, &path_or_handle)?;
+            canonical_path(&session, node)
+        })
+    }
+
+    /// Look up many paths at once.
+    ///
+    /// Resolves everything from the local tree under a single lock, without
+    /// any network traffic. Much cheaper than awaiting stat() in a loop.
+    ///
+    /// Args:
+    ///     paths: Paths to look up (e.g., ["/Root/a.txt", "/Root/b"])
+    ///
+    /// Returns:
+    ///     List with a MegaNode or None for each path, in the same order
+    fn stat_many<'p>(&self, py: Python<'p>, paths: Vec<String>) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let nodes = {
+                let session = inner.lock().await;
+                paths
+                    .iter()
+                    .map(|path| {
+                        session
+                            .stat(&expand_virtual_path(path))
+                            .map(|node| MegaNode::with_path(&session, node))
+                    })
+                    .collect()
+            };
+            optional_nodes_into_py(nodes)
+        })
+    }
+
+    /// Look up many nodes by handle at once.
+    ///
+    /// Works like stat_many() for node handles.
+    ///
+    /// Args:
+    ///     handles: Node handles to look up
+    ///
+    /// Returns:
+    ///     List with a MegaNode or None for each handle, in the same order
+    fn stat_many_by_handle<'p>(&self, py: Python<'p>, handles: Vec<String>) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let nodes = {
+                let session = inner.lock().await;
+                handles
+                    .iter()
+                    .map(|handle| {
+                        session
+                            .get_node_by_handle(handle)
+                            .map(|node| MegaNode::with_path(&session, node))
+                    })
+                    .collect()
+            };
+            optional_nodes_into_py(nodes)
+        })
+    }
+
+    /// List nodes skipped by the last refresh because their keys couldn't
+    /// be decrypted.
+    ///
+    /// These nodes hold data that exists but can't be read with the
+    /// account's current keys; list() and stat() never return them.
+    ///
+    /// Returns:
+    ///     List of MegaUndecryptableNode
+    fn undecryptable_nodes<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            Ok(session
+                .undecryptable_nodes()
+                .iter()
+                .map(|node| MegaUndecryptableNode {
+                    handle: node.handle.clone(),
+                    parent_handle: node.parent.clone(),
+                    size: node.size,
+                })
+                .collect::<Vec<_>>())
+        })
+    }
+
+    /// Add or update one node in the local tree from its raw API JSON.
+    ///
+    /// Advanced: for applications that learn about changes from another
+    /// source and want the tree to reflect them without a refresh(). The
+    /// node is decrypted the same way refresh() does it. Nothing is sent to
+    /// MEGA, and the next refresh() replaces the tree with the server's.
+    ///
+    /// Args:
+    ///     node_json: The node object as returned by MEGA's API, as a JSON
+    ///         string (at least "h", "p", "t", "a" and "k")
+    ///
+    /// Returns:
+    ///     MegaNode for the node as it is now in the tree
+    ///
+    /// Raises:
+    ///     ValueError: If the JSON is malformed or inconsistent with the tree:
+    ///         unknown or non-folder parent, a file turning into a folder (or
+    ///         back), or a folder moved below itself
+    ///     MissingKeyError: If the node's key can't be decrypted
+    fn apply_node_update<'p>(&self, py: Python<'p>, node_json: String) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let node: serde_json::Value = serde_json::from_str(&node_json)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            let handle = validate_node_update(&session, &node)?;
+            session
+                .apply_node(&node)
+                .map_err(|e| MissingKeyError::new_err(e.to_string()))?;
+            session
+                .get_node_by_handle(&handle)
+                .map(|node| MegaNode::with_path(&session, node))
+                .ok_or_else(|| {
+                    MissingKeyError::new_err(format!("Node {} could not be decrypted", handle))
+                })
+        })
+    }
+
+    /// Remove a node, and everything below it, from the local tree.
+    ///
+    /// Advanced: the counterpart of apply_node_update() for nodes deleted
+    /// elsewhere. Nothing is sent to MEGA.
+    ///
+    /// Args:
+    ///     handle: Handle of the node to forget
+    ///
+    /// Returns:
+    ///     True if the node was in the tree, False if it wasn't
+    ///
+    /// Raises:
+    ///     ValueError: If the handle is one of the account roots
+    fn forget_node<'p>(&self, py: Python<'p>, handle: String) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            match session.get_node_by_handle(&handle) {
+                None => return Ok(false),
+                Some(node) if node.parent.is_none() => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "{} is an account root and can't be forgotten",
+                        handle
+                    )));
+                }
+                Some(_) => {}
+            }
+            session.remove_node(&handle);
+            Ok(true)
+        })
+    }
+
+    /// Report everything in the account that others can reach.
+    ///
+    /// Combines public links and outgoing shares, including pending shares
+    /// and invitations, from the fetched tree without any request.
+    ///
+    /// Returns:
+    ///     MegaExposureReport; report.to_dict() is JSON-serializable
+    fn audit_exposure<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.complete_tree("audit_exposure()")?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            Ok(MegaExposureReport {
+                entries: exposures(&session)?,
+            })
+        })
+    }
+
+    /// Revoke public links and shares in bulk.
+    ///
+    /// Defaults to a dry run that only reports what would be revoked.
+    ///
+    /// Args:
+    ///     kinds: Kinds to revoke ("link", "share", "pending_share"), None
+    ///         for all of them
+    ///     dry_run: If True (the default), change nothing
+    ///
+    /// Returns:
+    ///     MegaTransferSummary with one item per exposure; action is
+    ///     "would_revoke", "revoked" or "failed" and detail holds the kind,
+    ///     plus the recipient for shares
+    #[pyo3(signature = (kinds = None, dry_run = true))]
+    fn revoke_all<'p>(
+        &self,
+        py: Python<'p>,
+        kinds: Option<Vec<String>>,
+        dry_run: bool,
+    ) -> PyResult<&'p PyAny> {
+        let kinds = match kinds {
+            Some(kinds) => {
+                if let Some(bad) = kinds.iter().find(|k| !EXPOSURE_KINDS.contains(&k.as_str())) {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Unknown exposure kind {:?} (expected one of {:?})",
+                        bad, EXPOSURE_KINDS
+                    )));
+                }
+                kinds
+            }
+            None => EXPOSURE_KINDS.iter().map(|k| k.to_string()).collect(),
+        };
+        if !dry_run {
+            self.writable()?;
+        }
+        let inner = self.complete_tree("revoke_all()")?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            let entries = exposures(&session)?;
+            let mut items = Vec::new();
+            for entry in entries.into_iter().filter(|e| kinds.contains(&e.kind)) {
+                let started = std::time::Instant::now();
+                let outcome = if dry_run {
+                    Ok(())
+                } else {
+                    match &entry.recipient {
+                        None => api(session.unexport(&entry.path)).await.map(|_| ()),
+                        Some(recipient) => api(session.unshare_folder(&entry.path, recipient))
+                            .await
+                            .map(|_| ()),
+                    }
+                    .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)
+                };
+                let (action, error, error_type) = match outcome {
+                    Ok(()) if dry_run => ("would_revoke", None, None),
+                    Ok(()) => ("revoked", None, None),
+                    Err(e) => ("failed", Some(e.to_string()), exception_name(&e)),
+                };
+                let detail = match &entry.recipient {
+                    Some(recipient) => format!("{}:{}", entry.kind, recipient),
+                    None => entry.kind.clone(),
+                };
+                items.push(MegaTransferItem {
+                    path: entry.path,
+                    handle: Some(entry.handle),
+                    action: action.to_string(),
+                    bytes: 0,
+                    detail: Some(detail),
+                    error,
+                    error_type,
+                    duration: started.elapsed().as_secs_f64(),
+                });
+            }
+            Ok(MegaTransferSummary { items })
+        })
+    }
+
+    /// List files in a directory.
+    ///
+    /// "/" lists the children of the cloud drive ("/Root"). Other roots
+    /// are only reachable through their own paths (see roots()).
+    ///
+    /// Args:
+    ///     path: Path to list (e.g., "/", "/Root/Documents")
+    ///     recursive: If True, list all descendants recursively
+    ///     only_available: If True, leave out nodes taken down by MEGA
+    ///     only_favorites: If True, only return nodes marked as favorites
+    ///     sort: Order of the results: "name" (default), "size" or
+    ///         "timestamp"; ties are broken by name, then handle
+    ///
+    /// Returns:
+    ///     List of MegaNode objects
+    #[pyo3(signature = (
+        path,
+        recursive = false,
+        only_available = false,
+        only_favorites = false,
+        sort = "name"
+    ))]
+    fn list<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        recursive: bool,
+        only_available: bool,
+        only_favorites: bool,
+        sort: &str,
+    ) -> PyResult<&'p PyAny> {
+        let sort = NodeSort::parse(sort)?;
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let path = if path == "/" {
+                CLOUD_PATH.to_string()
+            } else {
+                expand_virtual_path(&path).into_owned()
+            };
+            // Snapshot under the lock, convert to Python objects after releasing it.
+            let mut py_nodes: Vec<MegaNode> = {
+                let session = inner.lock().await;
+                let tree = session.list(&path, recursive).map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                })?;
+                let nodes: Vec<&Node> = tree
+                    .iter()
+                    .copied()
+                    .filter(|n| !(only_available && n.takendown))
+                    .filter(|n| !only_favorites || is_favorite(n))
+                    .collect();
+                nodes_below(&session, &path, &tree, &nodes)
+            };
+            sort.apply(&mut py_nodes);
+            nodes_into_py(py_nodes)
+        })
+    }
+
+    /// Find files and folders by name below a folder.
+    ///
+    /// Only looks at the tree loaded by refresh(); nothing is sent over the
+    /// network.
+    ///
+    /// Args:
+    ///     query: Text to look for in node names, or a regular expression
+    ///         with regex=True (matched anywhere in the name unless
+    ///         anchored with ^ and $)
+    ///     path: Folder to search; "/" means "/Root" as in list()
+    ///     case_sensitive: If True, letter case must match
+    ///     regex: If True, query is a regular expression
+    ///
+    /// Returns:
+    ///     List of matching MegaNode objects with their path set, sorted
+    ///     by path
+    ///
+    /// Raises:
+    ///     ValueError: If regex=True and query isn't a valid expression
+    #[pyo3(signature = (query, path = "/".to_string(), case_sensitive = false, regex = false))]
+    fn search<'p>(
+        &self,
+        py: Python<'p>,
+        query: String,
+        path: String,
+        case_sensitive: bool,
+        regex: bool,
+    ) -> PyResult<&'p PyAny> {
+        let matcher = NameMatcher::new(&query, case_sensitive, regex)?;
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let path = if path == "/" {
+                CLOUD_PATH.to_string()
+            } else {
+                expand_virtual_path(&path).into_owned()
+            };
+            let mut py_nodes: Vec<MegaNode> = {
+                let session = inner.lock().await;
+                let tree = session.list(&path, true).map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                })?;
+                let nodes: Vec<&Node> = tree
+                    .iter()
+                    .copied()
+                    .filter(|n| matcher.matches(&n.name))
+                    .collect();
+                nodes_below(&session, &path, &tree, &nodes)
+            };
+            py_nodes.sort_by_cached_key(|n| (n.full_path(), n.handle.clone()));
+            nodes_into_py(py_nodes)
+        })
+    }
+
+    /// Find files and folders whose path matches a shell-style pattern.
+    ///
+    /// Components of the pattern are matched against node names: `*`
+    /// matches any run of characters (leading dots included), `?` one
+    /// character and `[...]` one character of a class, negated with `!`
+    /// or `^`. A `**` component matches any number of folders, including
+    /// none. Only looks at the tree loaded by refresh(); nothing is sent
+    /// over the network.
+    ///
+    /// Args:
+    ///     pattern: Absolute pattern, e.g. "/Root/**/*.mp4"
+    ///     case_sensitive: If False, letter case is ignored
+    ///     files_only: If True, leave out folders
+    ///
+    /// Returns:
+    ///     List of matching MegaNode objects with their path set, sorted
+    ///     by path
+    ///
+    /// Raises:
+    ///     ValueError: If the pattern isn't absolute or has an invalid
+    ///         character class
+    #[pyo3(signature = (pattern, case_sensitive = true, files_only = false))]
+    fn glob<'p>(
+        &self,
+        py: Python<'p>,
+        pattern: String,
+        case_sensitive: bool,
+        files_only: bool,
+    ) -> PyResult<&'p PyAny> {
+        let parts = GlobPart::parse(&pattern, case_sensitive)?;
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let mut py_nodes: Vec<MegaNode> = {
+                let session = inner.lock().await;
+                let mut matches = Vec::new();
+                glob_walk(&session, "", true, None, &parts, &mut matches)?;
+                // Several "**" can reach the same node more than once.
+                let mut seen = std::collections::HashSet::new();
+                matches
+                    .into_iter()
+                    .filter(|(_, n)| !(files_only && n.node_type != NodeType::File))
+                    .filter(|(_, n)| seen.insert(n.handle.clone()))
+                    .map(|(path, n)| {
+                        let location =
+                            path.map_or(NodeLocation::Unknown, |p| NodeLocation::At(p.into()));
+                        MegaNode::snapshot(n, location, &mut Interner::default())
+                    })
+                    .collect()
+            };
+            py_nodes.sort_by_cached_key(|n| (n.full_path(), n.handle.clone()));
+            nodes_into_py(py_nodes)
+        })
+    }
+
+    /// List only the direct child folders of a folder.
+    ///
+    /// Cheaper than list() on folders with many files, for tree views that
+    /// expand one level at a time.
+    ///
+    /// Args:
+    ///     path: Folder to list; "/" means "/Root" as in list()
+    ///
+    /// Returns:
+    ///     List of (MegaNode, child_count) tuples sorted by name, where
+    ///     child_count is the number of files and folders directly inside
+    fn list_folders<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let path = if path == "/" {
+                CLOUD_PATH.to_string()
+            } else {
+                expand_virtual_path(&path).into_owned()
+            };
+            let folders = {
+                let session = inner.lock().await;
+                let children = session.list(&path, false).map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                })?;
+                child_folders(&path, children, |child| {
+                    session.list(child, false).map_or(0, |nodes| nodes.len())
+                })
+            };
+            folders_into_py(folders)
+        })
+    }
+
+    /// Get storage quota information.
+    ///
+    /// Args:
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     MegaQuotaInfo with total, used and free bytes
+    #[pyo3(signature = (*, timeout = None))]
+    fn quota<'p>(&self, py: Python<'p>, timeout: Option<f64>) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            let q = api(session.quota())
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+
+            Ok(MegaQuotaInfo {
+                total: q.total,
+                used: q.used,
+            })
+        })
+    }
+
+    /// Get the number of bytes still free in the storage quota.
+    ///
+    /// Always fetches fresh quota information from the server.
+    fn remaining_storage<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            remaining_storage(&mut session).await
+        })
+    }
+
+    /// List the items in the rubbish bin.
+    ///
+    /// Only the items directly in the bin are returned, with the names they
+    /// had before they were removed; list() on their paths shows what's
+    /// inside removed folders.
+    ///
+    /// Returns:
+    ///     List of MegaNode objects with their path set
+    fn list_trash<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let mut py_nodes: Vec<MegaNode> = {
+                let session = inner.lock().await;
+                trash_items(&session)?
+                    .into_iter()
+                    .map(|node| MegaNode::with_path(&session, node))
+                    .collect()
+            };
+            NodeSort::Name.apply(&mut py_nodes);
+            nodes_into_py(py_nodes)
+        })
+    }
+
+    /// Move an item out of the rubbish bin.
+    ///
+    /// Without `dest` the item goes back to the folder it was removed from,
+    /// which the MEGA apps and rm() record with the item.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of an item in the rubbish bin
+    ///     dest: Folder to move the item to, None for where it came from
+    ///
+    /// Returns:
+    ///     MegaNode of the restored item
+    ///
+    /// Raises:
+    ///     ValueError: If the item isn't in the rubbish bin, or dest is None
+    ///         and its previous folder is unknown or gone
+    #[pyo3(signature = (path_or_handle, dest = None))]
+    fn restore<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+        dest: Option<String>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            let trash = root_path(trash_root(&session)?).unwrap_or_default();
+            let node = resolve_node(&session, &path_or_handle)?;
+            let handle = node.handle.clone();
+            let path = canonical_path(&session, node)?;
+            if !path.starts_with(&format!("{}/", trash)) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "{} is not in the rubbish bin",
+                    path_or_handle
+                )));
+            }
+            let previous = node.attrs.get(ATTR_RESTORE).and_then(|v| v.as_str());
+            let dest = match dest {
+                Some(dest) => dest,
+                None => previous
+                    .and_then(|parent| session.get_node_by_handle(parent))
+                    .filter(|parent| parent.node_type.is_container())
+                    .and_then(|parent| canonical_path(&session, parent).ok())
+                    .filter(|parent| {
+                        *parent != trash && !parent.starts_with(&format!("{}/", trash))
+                    })
+                    .ok_or_else(|| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                            "The folder {} was removed from is unknown or gone; pass dest=",
+                            path_or_handle
+                        ))
+                    })?,
+            };
+            let had_restore_attr = previous.is_some();
+            ensure_access(&session, &dest, ACCESS_READ_WRITE)?;
+            move_node(&mut session, &path, Relocation::Into(&dest)).await?;
+            if had_restore_attr {
+                update_node_attrs(&mut session, &handle, |attrs| {
+                    attrs.remove(ATTR_RESTORE);
+                })
+                .await?;
+            }
+            session
+                .get_node_by_handle(&handle)
+                .map(|node| MegaNode::with_path(&session, node))
+                .ok_or_else(|| {
+                    NotFoundError::new_err(format!(
+                        "{} was deleted by another client",
+                        path_or_handle
+                    ))
+                })
+        })
+    }
+
+    /// Permanently delete everything in the rubbish bin.
+    ///
+    /// Returns:
+    ///     Number of items deleted (folders count as one)
+    fn empty_trash<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            let paths: Vec<String> = trash_items(&session)?
+                .into_iter()
+                .map(|node| canonical_path(&session, node))
+                .collect::<PyResult<_>>()?;
+            for path in &paths {
+                api(session.rm(path))
+                    .await
+                    .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            }
+            Ok(paths.len())
+        })
+    }
+
+    /// Get the account's root folders.
+    ///
+    /// Returns:
+    ///     Dict mapping the path to use for each root ("/Root" for the cloud
+    ///     drive, "/Vault" for backups, the rubbish bin's path) to its MegaNode
+    fn roots<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            let roots = session
+                .list("/", false)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+            let mut named = std::collections::HashMap::new();
+            for root in roots {
+                if let Some(path) = root_path(root) {
+                    named.insert(path, MegaNode::with_path(&session, root));
+                }
+            }
+            Ok(named)
+        })
+    }
+
+    /// Get storage usage split by account root.
+    ///
+    /// Computed from the refreshed file tree, so no request is sent.
+    ///
+    /// Returns:
+    ///     Dict mapping "cloud", "inbox", "rubbish" and one
+    ///     "inshare:<owner email>/<folder>" entry per incoming share to
+    ///     MegaStorageUsage
+    fn storage_breakdown<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.complete_tree("storage_breakdown()")?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            let mut breakdown = std::collections::HashMap::new();
+
+            let roots = session
+                .list("/", false)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+            for root in roots {
+                let key = match root.node_type {
+                    NodeType::Root => "cloud",
+                    NodeType::Inbox => "inbox",
+                    NodeType::Trash => "rubbish",
+                    _ => continue,
+                };
+                let usage = MegaStorageUsage::of_path(&session, &format!("/{}", root.name))?;
+                breakdown.insert(key.to_string(), usage);
+            }
+
+            for contact in session.list_contacts() {
+                let contact_path = format!("/Contacts/{}", contact.name);
+                let shares = session.list(&contact_path, false).map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                })?;
+                for share in shares {
+                    let share_path = format!("{}/{}", contact_path, share.name);
+                    let mut usage = MegaStorageUsage::of_path(&session, &share_path)?;
+                    usage.folders += 1;
+                    breakdown.insert(format!("inshare:{}/{}", contact.name, share.name), usage);
+                }
+            }
+
+            Ok(breakdown)
+        })
+    }
+
+    /// Create a new directory.
+    ///
+    /// If another client creates the same folder concurrently, the tree is
+    /// refreshed to pick it up.
+    ///
+    /// Args:
+    ///     path: Full path for the new directory (e.g., "/Root/NewFolder")
+    ///     exist_ok: If True, return an existing folder at `path` instead of
+    ///         raising FileExistsError
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     MegaNode of the created (or existing) folder
+    #[pyo3(signature = (path, exist_ok = false, *, timeout = None))]
+    fn mkdir<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        exist_ok: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = if exist_ok {
+            self.complete_tree("mkdir(exist_ok=True)")?
+        } else {
+            self.tree()?
+        };
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+            let existing = || {
+                PyErr::new::<pyo3::exceptions::PyFileExistsError, _>(format!(
+                    "{} already exists",
+                    path
+                ))
+            };
+
+            let found = exist_ok && session.stat(&path).is_some();
+            if !found {
+                match api(session.mkdir(&path)).await {
+                    Ok(_) => {}
+                    Err(::megalib::MegaError::ApiError { code: EEXIST, .. }) => {
+                        // Lost a race with another client: pick up its folder.
+                        fetch_tree(&mut session, false).await?;
+                        if !exist_ok {
+                            return Err(existing());
+                        }
+                    }
+                    Err(e) => return Err(mega_err::<pyo3::exceptions::PyRuntimeError>(e)),
+                }
+            }
+
+            match session.stat(&path) {
+                Some(node) if node.node_type.is_container() => {
+                    Ok(MegaNode::with_path(&session, node))
+                }
+                Some(_) => Err(existing()),
+                None => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                    "Folder {} not found after creating it",
+                    path
+                ))),
+            }
+        })
+    }
+
+    /// Create a folder path, including any missing parents.
+    ///
+    /// Folders that already exist are reused. If another client creates one
+    /// of the folders concurrently, the tree is refreshed and the existing
+    /// folder is used instead of creating a duplicate sibling.
+    ///
+    /// Args:
+    ///     path: Full folder path (e.g., "/Root/Clients/ACME/2025")
+    ///
+    /// Returns:
+    ///     List of (MegaNode, created) tuples, one per path segment, where
+    ///     created is True if this call created the folder
+    fn create_path<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.complete_tree("create_path()")?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+
+            let mut segments = Vec::new();
+            let mut prefix = String::new();
+            for part in path.split('/').filter(|p| !p.is_empty()) {
+                prefix.push('/');
+                prefix.push_str(part);
+
+                let created = ensure_folder(&mut session, &prefix).await?;
+                let node = session.stat(&prefix).ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                        "Folder {} not found after creating it",
+                        prefix
+                    ))
+                })?;
+                segments.push((MegaNode::with_path(&session, node), created));
+            }
+            Ok(segments)
+        })
+    }
+
+    /// Rename a file or folder.
+    ///
+    /// Safe to retry when another client changes the node concurrently: see
+    /// mv().
+    ///
+    /// Args:
+    ///     path: Path to the item to rename
+    ///     new_name: New name (not a path, just the filename)
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    #[pyo3(signature = (path, new_name, *, timeout = None))]
+    fn rename<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        new_name: String,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+            move_node(&mut session, &path, Relocation::Rename(&new_name)).await
+        })
+    }
+
+    /// Move a file or folder to a new location.
+    ///
+    /// If MEGA rejects the move because the node or the destination changed
+    /// concurrently, the tree is refreshed. The call succeeds if the node
+    /// already ended up at the destination, is retried once if it is still
+    /// at `source`, and raises NotFoundError otherwise.
+    ///
+    /// Args:
+    ///     source: Path to the item to move
     ///     dest: Path to the destination folder
-    fn mv<'p>(&self, py: Python<'p>, source: String, dest: String) -> PyResult<&'p PyAny> {
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    #[pyo3(signature = (source, dest, *, timeout = None))]
+    fn mv<'p>(
+        &self,
+        py: Python<'p>,
+        source: String,
+        dest: String,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &source, ACCESS_READ_WRITE)?;
+            ensure_access(&session, &dest, ACCESS_READ_WRITE)?;
+            move_node(&mut session, &source, Relocation::Into(&dest)).await
+        })
+    }
+
+    /// Delete a file or folder.
+    ///
+    /// By default the item is moved to the rubbish bin like the MEGA apps
+    /// do, remembering its folder for restore(). Items already in the bin
+    /// are left alone. With permanent=True the item is deleted for good.
+    ///
+    /// Incoming shares have no rubbish bin of their own, and MEGA offers no
+    /// way to move a node from one into the sharer's, so there only
+    /// permanent=True is accepted. Deleting a node you own needs read-write
+    /// access to the share; deleting one owned by someone else (see
+    /// MegaNode.owner_handle) needs full access.
+    ///
+    /// Args:
+    ///     path: Path to the item to delete
+    ///     permanent: If True, delete instead of moving to the rubbish bin
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Raises:
+    ///     FileNotFoundError: If there is no item at `path`
+    ///     ValueError: If the item is in an incoming share and permanent
+    ///         is False
+    ///     AccessDeniedError: If the share's access level doesn't allow it
+    #[pyo3(signature = (path, *, permanent = false, timeout = None))]
+    fn rm<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        permanent: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            let path = expand_virtual_path(&path).into_owned();
+            if !permanent {
+                let node = resolve_node(&session, &path)?;
+                // Nodes in incoming shares have no canonical path.
+                let canonical = canonical_path(&session, node).map_err(|_| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "{} is in an incoming share, which has no rubbish bin; \
+                         pass permanent=True to delete it",
+                        path
+                    ))
+                })?;
+                let handle = node.handle.clone();
+                let parent = node.parent.clone();
+                let trash = root_path(trash_root(&session)?).unwrap_or_default();
+                if canonical == trash || canonical.starts_with(&format!("{}/", trash)) {
+                    return Ok(());
+                }
+                ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+                move_node(&mut session, &canonical, Relocation::Into(&trash)).await?;
+                // Only once the move succeeded, so a failed one leaves no
+                // stale restore target behind.
+                if let Some(parent) = parent {
+                    update_node_attrs(&mut session, &handle, |attrs| {
+                        attrs.insert(ATTR_RESTORE.to_string(), parent.into());
+                    })
+                    .await?;
+                }
+                return Ok(());
+            }
+            let required = match session.stat(&path) {
+                Some(node) => rm_access(node.user_handle.as_deref(), &session.user_handle),
+                None => ACCESS_READ_WRITE,
+            };
+            ensure_access(&session, &path, required)?;
+            api(session.rm(&path))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            Ok(())
+        })
+    }
+
+    /// Create a public download link for a file.
+    ///
+    /// Args:
+    ///     path: Path to the file to export
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     Public URL string
+    #[pyo3(signature = (path, *, timeout = None))]
+    fn export<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+            let url = api(session.export(&path))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            Ok(url)
+        })
+    }
+
+    /// Get the existing public link of a file or folder.
+    ///
+    /// Built from the public handle and key in the local tree; nothing is
+    /// sent to MEGA and no link is created.
+    ///
+    /// Args:
+    ///     path_or_handle: Path (e.g., "/Root/file.txt") or handle of the node
+    ///
+    /// Returns:
+    ///     Public URL string, or None if the node isn't exported
+    ///
+    /// Raises:
+    ///     FileNotFoundError: If the node isn't in the tree
+    fn get_link<'p>(&self, py: Python<'p>, path_or_handle: String) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            let node = resolve_node(&session, &path_or_handle)?;
+            Ok(session.public_link(node))
+        })
+    }
+
+    /// Remove the public link of a file or folder.
+    ///
+    /// Links made with export() and export_protected() stop working. The
+    /// local tree is updated in place, so is_exported is False on nodes
+    /// returned afterwards without a refresh().
+    ///
+    /// Args:
+    ///     path: Path to the exported file or folder
+    ///
+    /// Returns:
+    ///     True if a link was removed, False if the node had none
+    ///
+    /// Raises:
+    ///     FileNotFoundError: If there is no node at path
+    fn unexport<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            let node = session.stat(&expand_virtual_path(&path)).ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(format!(
+                    "{} not found",
+                    path
+                ))
+            })?;
+            if node.public_handle.is_none() {
+                return Ok(false);
+            }
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+            api(session.unexport(&expand_virtual_path(&path)))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            Ok(true)
+        })
+    }
+
+    /// Create a password-protected public link for a file.
+    ///
+    /// Exports the file like export() and encrypts the link with the
+    /// password the way the official clients do, giving a
+    /// "https://mega.nz/#P!..." link. Opening it needs the password, e.g.
+    /// download_public_file(url, password=...) or the MEGA apps.
+    ///
+    /// Args:
+    ///     path: Path to the file to export
+    ///     password: Password for the link
+    ///
+    /// Returns:
+    ///     Protected public URL string
+    ///
+    /// Raises:
+    ///     ValueError: If password is empty or path is a folder
+    fn export_protected<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        password: String,
+    ) -> PyResult<&'p PyAny> {
+        if password.is_empty() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "password must not be empty",
+            ));
+        }
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+            if let Some(node) = session.stat(&expand_virtual_path(&path)) {
+                if node.node_type != NodeType::File {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                        "Only files can be exported with a password",
+                    ));
+                }
+            }
+            let url = api(session.export(&expand_virtual_path(&path)))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            // PBKDF2 with 100,000 rounds takes a moment; keep it off the
+            // runtime's worker threads.
+            tokio::task::spawn_blocking(move || protect_file_link(&url, &password))
+                .await
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+        })
+    }
+
+    /// Mark or unmark a file or folder as a favorite.
+    ///
+    /// Args:
+    ///     path_or_handle: Path (e.g., "/Root/file.txt") or handle of the node
+    ///     favorite: True to mark, False to unmark
+    fn set_favorite<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+        favorite: bool,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            update_node_attrs(&mut session, &path_or_handle, |attrs| {
+                if favorite {
+                    attrs.insert(ATTR_FAV.to_string(), 1.into());
+                } else {
+                    attrs.remove(ATTR_FAV);
+                }
+            })
+            .await
+        })
+    }
+
+    /// Set or clear the color label of a file or folder.
+    ///
+    /// Args:
+    ///     path_or_handle: Path (e.g., "/Root/file.txt") or handle of the node
+    ///     label: 1=red, 2=orange, 3=yellow, 4=green, 5=blue, 6=purple,
+    ///         7=grey, or None to clear
+    ///
+    /// Raises:
+    ///     ValueError: If label is out of range
+    fn set_label<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+        label: Option<u8>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        if let Some(l) = label {
+            if !(LABEL_MIN..=LABEL_MAX).contains(&l) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "label must be between {} and {}, got {}",
+                    LABEL_MIN, LABEL_MAX, l
+                )));
+            }
+        }
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            update_node_attrs(&mut session, &path_or_handle, |attrs| match label {
+                Some(l) => {
+                    attrs.insert(ATTR_LABEL.to_string(), l.into());
+                }
+                None => {
+                    attrs.remove(ATTR_LABEL);
+                }
+            })
+            .await
+        })
+    }
+
+    /// Get the full decrypted attribute object of a node.
+    ///
+    /// Includes keys set by other clients that MegaNode doesn't model.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of the node
+    ///
+    /// Returns:
+    ///     Dict of attribute keys to JSON values
+    fn get_raw_attributes<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+    ) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            let attrs =
+                serde_json::Value::Object(resolve_node(&session, &path_or_handle)?.attrs.clone());
+            Python::with_gil(|py| json_to_py(py, &attrs))
+        })
+    }
+
+    /// Set or remove a single raw node attribute.
+    ///
+    /// Keys managed by the bindings ("n", "c", "fav", "lbl") can't be set
+    /// this way; use rename(), set_favorite() and set_label() instead.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of the node
+    ///     key: Attribute key
+    ///     value: Any JSON-serializable value, or None to remove the key
+    ///
+    /// Raises:
+    ///     ValueError: If key is reserved or value isn't JSON-serializable
+    fn set_raw_attribute<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+        key: String,
+        value: &PyAny,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        if RESERVED_ATTRS.contains(&key.as_str()) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Attribute {:?} is reserved",
+                key
+            )));
+        }
+        let value = if value.is_none() {
+            None
+        } else {
+            Some(py_to_json(value)?)
+        };
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            update_node_attrs(&mut session, &path_or_handle, |attrs| match value {
+                Some(value) => {
+                    attrs.insert(key, value);
+                }
+                None => {
+                    attrs.remove(&key);
+                }
+            })
+            .await
+        })
+    }
+
+    /// Find files in the account with the same content as a local file.
+    ///
+    /// Compares MEGA fingerprints (CRC samples of the data plus the
+    /// modification time, as MEGA's apps compute them) and sizes, so
+    /// nothing is downloaded or hashed in full. The cloud drive and the
+    /// vault are searched; the rubbish bin and incoming shares aren't.
+    ///
+    /// Args:
+    ///     local_path: Path to the local file
+    ///
+    /// Returns:
+    ///     List of matching MegaNode, sorted by path
+    ///
+    /// Raises:
+    ///     PartialTreeError: After refresh(max_nodes=...)
+    fn find_by_fingerprint<'p>(
+        &self,
+        py: Python<'p>,
+        local_path: LocalPath,
+    ) -> PyResult<&'p PyAny> {
+        let local_path = local_path.0;
+        let inner = self.complete_tree("find_by_fingerprint()")?;
+        self.run(py, async move {
+            let (size, fingerprint) = fingerprint_file(local_path).await?;
+            let session = inner.lock().await;
+            let roots = session
+                .list("/", false)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+            let mut found = Vec::new();
+            for root in roots {
+                if root.node_type == NodeType::Trash {
+                    continue;
+                }
+                let Some(path) = root_path(root) else {
+                    continue;
+                };
+                let path = expand_virtual_path(&path);
+                let tree = session.list(&path, true).map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                })?;
+                let nodes: Vec<&Node> = tree
+                    .iter()
+                    .copied()
+                    .filter(|node| has_fingerprint(node, size, &fingerprint))
+                    .collect();
+                found.extend(nodes_below(&session, &path, &tree, &nodes));
+            }
+            found.sort_by_cached_key(|n| (n.full_path(), n.handle.clone()));
+            nodes_into_py(found)
+        })
+    }
+
+    /// Get a custom attribute of a node.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of the node
+    ///     key: Attribute name, as given to set_node_attr()
+    ///
+    /// Returns:
+    ///     The stored string, or None if the attribute isn't set
+    fn get_node_attr<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+        key: String,
+    ) -> PyResult<&'p PyAny> {
+        let key = custom_attr_key(&key)?;
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            Ok(resolve_node(&session, &path_or_handle)?
+                .attrs
+                .get(&key)
+                .map(|value| match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                }))
+        })
+    }
+
+    /// Set or remove a custom attribute of a node.
+    ///
+    /// Custom attributes are stored in the node's encrypted attributes
+    /// under the name prefixed with "_", like the official SDK's, so they
+    /// never clash with the name or other attributes MEGA uses. They are
+    /// kept across refresh() and visible to other clients.
+    ///
+    /// Args:
+    ///     path_or_handle: Path or handle of the node
+    ///     key: Attribute name
+    ///     value: String to store, or None to remove the attribute
+    ///
+    /// Raises:
+    ///     ValueError: If key is empty
+    fn set_node_attr<'p>(
+        &self,
+        py: Python<'p>,
+        path_or_handle: String,
+        key: String,
+        value: Option<String>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let key = custom_attr_key(&key)?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            update_node_attrs(&mut session, &path_or_handle, |attrs| match value {
+                Some(value) => {
+                    attrs.insert(key, value.into());
+                }
+                None => {
+                    attrs.remove(&key);
+                }
+            })
+            .await
+        })
+    }
+
+    /// Upload a file to MEGA.
+    ///
+    /// Args:
+    ///     local_path: Path to local file
+    ///     remote_path: Destination folder on MEGA
+    ///     precheck_quota: If True, raise OverQuotaError before transferring
+    ///         anything when the file doesn't fit in the remaining storage
+    ///     atomic: If True, upload under a hidden temporary name
+    ///         (".megatmp-<uuid>") and rename the node to the file's name only
+    ///         once it is complete, so other clients never see a partial file.
+    ///         The temporary node is removed if anything fails
+    ///     trash_existing: With atomic=True, move a file that already has the
+    ///         same name to the rubbish bin right before the rename
+    ///     progress: Called as progress(bytes_done, bytes_total) a few times
+    ///         per second while the file uploads. If it raises, the upload is
+    ///         cancelled and the exception propagates.
+    ///     skip_duplicates: If True and `remote_path` already holds a file
+    ///         with the same fingerprint and size (see find_by_fingerprint()),
+    ///         upload nothing and return that file with skipped=True. Copies
+    ///         elsewhere in the account don't count, since megalib can't copy
+    ///         them into the folder server-side.
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     MegaUploadResult with the new node and its size
+    #[pyo3(signature = (
+        local_path,
+        remote_path,
+        precheck_quota = false,
+        atomic = false,
+        trash_existing = false,
+        progress = None,
+        *,
+        skip_duplicates = false,
+        timeout = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn upload<'p>(
+        &self,
+        _py: Python<'p>,
+        local_path: LocalPath,
+        remote_path: String,
+        precheck_quota: bool,
+        atomic: bool,
+        trash_existing: bool,
+        progress: Option<PyObject>,
+        skip_duplicates: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let local_path = local_path.0;
+        if trash_existing && !atomic {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "trash_existing requires atomic=True",
+            ));
+        }
+        let inner = self.tree()?;
+        let temp_dir = self.temp_dir();
+        let throttle = self.throttle.clone();
+        self.run_mut_within(_py, timeout, async move {
+            let fingerprint = if skip_duplicates {
+                Some(fingerprint_file(local_path.clone()).await?)
+            } else {
+                None
+            };
+            let mut session = inner.lock().await;
+            ensure_access(&session, &remote_path, ACCESS_READ_WRITE)?;
+            if let Some((size, fingerprint)) = &fingerprint {
+                let existing = session
+                    .list(&expand_virtual_path(&remote_path), false)
+                    .ok()
+                    .and_then(|nodes| {
+                        nodes
+                            .into_iter()
+                            .find(|node| has_fingerprint(node, *size, fingerprint))
+                    });
+                if let Some(node) = existing {
+                    return Ok(MegaUploadResult {
+                        node: Some(MegaNode::with_path(&session, node)),
+                        bytes: *size,
+                        skipped: true,
+                    });
+                }
+            }
+            if precheck_quota {
+                ensure_quota(&mut session, &local_path).await?;
+            }
+            let total = file_len(&local_path);
+            let done = watch_transfer(&mut session, &throttle);
+            let sample = || done.load(Ordering::Relaxed);
+            if atomic {
+                let upload = upload_atomic(
+                    &mut session,
+                    &local_path,
+                    &remote_path,
+                    trash_existing,
+                    &temp_dir,
+                );
+                with_progress(upload, progress.as_ref(), total, sample).await??;
+            } else {
+                let upload = api(session.upload(&local_path, &remote_path));
+                with_progress(upload, progress.as_ref(), total, sample)
+                    .await?
+                    .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            }
+            let result = MegaUploadResult::new(&session, &local_path, &remote_path)?;
+            count_uploaded(result.bytes);
+            Ok(result)
+        })
+    }
+
+    /// Download a file from MEGA.
+    ///
+    /// Args:
+    ///     remote_path: Path to file on MEGA
+    ///     local_path: Destination path on local disk
+    ///     digest: Optional hash to compute while downloading
+    ///         ("sha256", "sha1" or "blake3")
+    ///     ignore_space_check: Skip checking the free disk space against the
+    ///         file size before starting, for filesystems that misreport it
+    ///     progress: Called as progress(bytes_done, bytes_total) a few times
+    ///         per second while the file downloads. If it raises, the
+    ///         download is cancelled and the exception propagates.
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Cancelling the awaiting task (or a raising progress callback) stops
+    /// the download, releases the session and removes the partial file.
+    ///
+    /// Returns:
+    ///     MegaDownloadResult, including the hex digest if `digest` was given
+    ///
+    /// Raises:
+    ///     DiskFullError: If the file doesn't fit on the local disk, or the
+    ///         disk filled up during the download (the partial file is kept)
+    #[pyo3(signature = (
+        remote_path,
+        local_path,
+        digest = None,
+        ignore_space_check = false,
+        progress = None,
+        *,
+        timeout = None
+    ))]
+    fn download<'p>(
+        &self,
+        _py: Python<'p>,
+        remote_path: String,
+        local_path: LocalPath,
+        digest: Option<String>,
+        ignore_space_check: bool,
+        progress: Option<PyObject>,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        let local_path = local_path.0;
+        let inner = self.tree()?;
+        self.run_mut_within(_py, timeout, async move {
+            let mut session = inner.lock().await;
+            let node = session.stat(&expand_virtual_path(&remote_path)).cloned();
+
+            if let Some(node) = node {
+                ensure_available(&node)?;
+                if !ignore_space_check {
+                    ensure_disk_space(&local_path, node.size)?;
+                }
+                let mut writer = download_writer(&local_path, digest.as_deref())?;
+
+                let outcome = with_progress(
+                    api(session.download(&node, &mut writer)),
+                    progress.as_ref(),
+                    node.size,
+                    || file_len(&local_path),
+                )
+                .await?;
+                writer.finish(outcome, local_path)
+            } else {
+                Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                    "File not found on Mega",
+                ))
+            }
+        })
+    }
+
+    /// Download a file into memory.
+    ///
+    /// Meant for small files such as configuration blobs; nothing is
+    /// written to disk.
+    ///
+    /// With `offset` or `length` only that range of the file is returned.
+    /// megalib always fetches a file from its start, so the download stops
+    /// once the range is complete but the data before `offset` is still
+    /// transferred. Ranged reads that stop before the end of the file
+    /// aren't checked against the file MAC.
+    ///
+    /// Args:
+    ///     remote_path: Path to file on MEGA
+    ///     max_size: Largest number of bytes to accept, None for no
+    ///         limit (64 MiB by default)
+    ///     offset: Position of the first byte to return
+    ///     length: Number of bytes to return, None for up to the end
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     The decrypted file contents as bytes
+    ///
+    /// Raises:
+    ///     FileNotFoundError: If there is no file at remote_path
+    ///     ValueError: If the requested data is larger than max_size, or
+    ///         the range reaches past the end of the file
+    #[pyo3(signature = (remote_path, max_size = Some(DOWNLOAD_BYTES_MAX), *, offset = 0, length = None, timeout = None))]
+    fn download_bytes<'p>(
+        &self,
+        py: Python<'p>,
+        remote_path: String,
+        max_size: Option<u64>,
+        offset: u64,
+        length: Option<u64>,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            let node = session
+                .stat(&expand_virtual_path(&remote_path))
+                .filter(|node| node.node_type == NodeType::File)
+                .cloned()
+                .ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>("File not found on Mega")
+                })?;
+            ensure_available(&node)?;
+            let length = match length {
+                Some(length) if offset.checked_add(length).is_none_or(|end| end > node.size) => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Range of {} bytes at offset {} is outside {} ({} bytes)",
+                        length, offset, remote_path, node.size
+                    )));
+                }
+                Some(length) => length,
+                None if offset > node.size => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "Offset {} is past the end of {} ({} bytes)",
+                        offset, remote_path, node.size
+                    )));
+                }
+                None => node.size - offset,
+            };
+            if let Some(max_size) = max_size {
+                if length > max_size {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "{} bytes of {} requested, more than max_size ({} bytes)",
+                        length, remote_path, max_size
+                    )));
+                }
+            }
+            let mut writer = RangeWriter::new(offset, length);
+            let outcome = if length == 0 {
+                Ok(())
+            } else {
+                api(session.download(&node, &mut writer)).await.map(|_| ())
+            };
+            count_downloaded(writer.received);
+            // The writer aborts a download that goes on past the range.
+            let aborted = writer.is_complete() && offset + length < node.size;
+            if !aborted {
+                outcome.map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            }
+            Ok(Python::with_gil(|py| {
+                PyObject::from(pyo3::types::PyBytes::new(py, &writer.data))
+            }))
+        })
+    }
+
+    /// Open a remote file for streaming its decrypted contents.
+    ///
+    /// The file is downloaded in the background as the stream is read,
+    /// without touching the disk. The stream downloads through its own copy
+    /// of the login, without the file tree, so other calls on this session
+    /// aren't blocked while it's open. set_bandwidth_limit() applies to it.
+    ///
+    /// Args:
+    ///     remote_path: Path of the file on MEGA
+    ///     chunk_size: Size of the chunks yielded, the last one may be
+    ///         shorter (default 1 MiB)
+    ///
+    /// Returns:
+    ///     MegaReadStream to read with `async for chunk in stream:`
+    #[pyo3(signature = (remote_path, chunk_size = READ_CHUNK_SIZE))]
+    fn open_read<'p>(
+        &self,
+        py: Python<'p>,
+        remote_path: String,
+        chunk_size: usize,
+    ) -> PyResult<&'p PyAny> {
+        if chunk_size == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "chunk_size must be at least 1",
+            ));
+        }
+        let inner = self.tree()?;
+        let temp_dir = self.temp_dir();
+        let throttle = self.throttle.clone();
+        self.run(py, async move {
+            let session = inner.lock().await;
+            let node = session
+                .stat(&expand_virtual_path(&remote_path))
+                .filter(|node| node.node_type == NodeType::File)
+                .cloned()
+                .ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>("File not found on Mega")
+                })?;
+            ensure_available(&node)?;
+            let session = detached_session(session, &temp_dir).await?;
+            MegaReadStream::start(session, throttle, node, chunk_size)
+        })
+    }
+
+    /// Get the user's email address.
+    ///
+    /// Returns:
+    ///     User's email address as a string
+    fn get_email<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.run(py, async move {
+            let session = inner.lock().await;
+            Ok(session.email.clone())
+        })
+    }
+
+    /// Get the user's display name.
+    ///
+    /// Returns:
+    ///     User's display name as a string, or None if it isn't set
+    fn get_name<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.run(py, async move {
+            let session = inner.lock().await;
+            Ok(session.name.clone())
+        })
+    }
+
+    /// Get the user's MEGA handle (unique ID).
+    ///
+    /// Returns:
+    ///     User's MEGA handle as a string
+    fn get_handle<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.run(py, async move {
+            let session = inner.lock().await;
+            Ok(session.user_handle.clone())
+        })
+    }
+
+    /// Set number of parallel transfer workers.
+    ///
+    /// Higher values speed up large file transfers.
+    ///
+    /// Args:
+    ///     workers: Number of parallel transfer workers
+    fn set_workers<'p>(&self, py: Python<'p>, workers: usize) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            session.set_workers(workers);
+            Ok(())
+        })
+    }
+
+    /// Set a default time limit for calls on this session.
+    ///
+    /// Calls taking longer are abandoned with TimeoutError: the operation
+    /// stops and the session is released, as if the awaiting task had been
+    /// cancelled. The limit covers the whole call, including transfers and
+    /// waiting for other calls on the session, so allow for large files.
+    /// Methods with a timeout argument use that instead when it is given.
+    /// Read-only views share the setting.
+    ///
+    /// Args:
+    ///     seconds: Time limit in seconds, or None for no limit
+    fn set_timeout<'p>(&self, py: Python<'p>, seconds: Option<f64>) -> PyResult<&'p PyAny> {
+        let limit = time_limit(seconds)?;
+        let timeout = self.timeout.clone();
+        self.run(py, async move {
+            *timeout.lock().unwrap() = limit;
+            Ok(())
+        })
+    }
+
+    /// Limit the transfer rate of this session.
+    ///
+    /// The limit is shared by uploads and downloads and by all transfer
+    /// workers (see set_workers()), and applies to read-only views and
+    /// open_read() streams too. It is enforced after each chunk, so short
+    /// bursts of about a chunk can exceed it. Changing it takes effect on
+    /// transfers that are already running.
+    ///
+    /// Args:
+    ///     bytes_per_second: Maximum rate, or 0 or None for no limit
+    fn set_bandwidth_limit<'p>(
+        &self,
+        py: Python<'p>,
+        bytes_per_second: Option<u64>,
+    ) -> PyResult<&'p PyAny> {
+        let throttle = self.throttle.clone();
+        self.run(py, async move {
+            throttle.set_rate(bytes_per_second.unwrap_or(0));
+            Ok(())
+        })
+    }
+
+    /// Set where scratch files are created.
+    ///
+    /// Streaming and atomic uploads stage data in a private temporary
+    /// directory that is removed when the call finishes, fails or is
+    /// cancelled. Point this at a large disk when the system temp directory
+    /// is a small tmpfs.
+    ///
+    /// Args:
+    ///     path: Existing directory, or None for the system temp directory
+    fn set_temp_dir<'p>(&self, py: Python<'p>, path: Option<LocalPath>) -> PyResult<&'p PyAny> {
+        let path = path.map(|path| path.0);
+        let temp_dir = self.temp_dir.clone();
+        self.run(py, async move {
+            if let Some(path) = &path {
+                if !path.is_dir() {
+                    return Err(PyErr::new::<pyo3::exceptions::PyNotADirectoryError, _>(
+                        format!("{} is not a directory", path.display()),
+                    ));
+                }
+            }
+            *temp_dir.lock().unwrap() = path;
+            Ok(())
+        })
+    }
+
+    /// Set where the persistent transfer queue is journaled.
+    ///
+    /// Transfers added with queue_download() and queue_upload() are
+    /// appended to this file and flushed before the call returns, and
+    /// run_queue() records the outcome of each one there. After a crash or
+    /// restart, point a new session at the same file and call run_queue()
+    /// again to carry on with the remaining entries; with set_resume(True)
+    /// partial downloads continue where they stopped. Only one process may
+    /// use a journal at a time.
+    ///
+    /// Args:
+    ///     path: Journal file, created on first use, or None to detach
+    ///
+    /// Raises:
+    ///     QueueCorruptError: If the file exists but is damaged
+    fn set_queue_path<'p>(&self, py: Python<'p>, path: Option<LocalPath>) -> PyResult<&'p PyAny> {
+        let path = path.map(|path| path.0);
+        let queue = self.queue.clone();
+        self.run(py, async move {
+            let mut queue = queue.lock().await;
+            if let Some(path) = &path {
+                read_queue(path)?;
+            }
+            *queue = path;
+            Ok(())
+        })
+    }
+
+    /// Add a download to the persistent queue (see set_queue_path()).
+    ///
+    /// Nothing is transferred until run_queue() is called.
+    ///
+    /// Args:
+    ///     remote_path: Path to file on MEGA
+    ///     local_path: Destination path on local disk (must be valid UTF-8)
+    ///
+    /// Returns:
+    ///     The new MegaQueueEntry
+    fn queue_download<'p>(
+        &self,
+        py: Python<'p>,
+        remote_path: String,
+        local_path: LocalPath,
+    ) -> PyResult<&'p PyAny> {
+        let local_path = local_path.0;
+        let queue = self.queue.clone();
+        self.run(py, async move {
+            let queue = queue.lock().await;
+            enqueue(queue_path(&queue)?, "download", remote_path, &local_path)
+        })
+    }
+
+    /// Add an upload to the persistent queue (see set_queue_path()).
+    ///
+    /// Nothing is transferred until run_queue() is called.
+    ///
+    /// Args:
+    ///     local_path: Path to local file (must be valid UTF-8)
+    ///     remote_path: Destination folder on MEGA
+    ///
+    /// Returns:
+    ///     The new MegaQueueEntry
+    fn queue_upload<'p>(
+        &self,
+        py: Python<'p>,
+        local_path: LocalPath,
+        remote_path: String,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let local_path = local_path.0;
+        let queue = self.queue.clone();
+        self.run(py, async move {
+            let queue = queue.lock().await;
+            enqueue(queue_path(&queue)?, "upload", remote_path, &local_path)
+        })
+    }
+
+    /// Entries of the persistent queue, in the order they were added.
+    ///
+    /// Returns:
+    ///     List of MegaQueueEntry; filter on state for pending, done or
+    ///     failed transfers
+    ///
+    /// Raises:
+    ///     QueueCorruptError: If the journal is damaged
+    fn queue_status<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let queue = self.queue.clone();
+        self.run(py, async move {
+            let queue = queue.lock().await;
+            read_queue(queue_path(&queue)?)
+        })
+    }
+
+    /// Work through the persistent queue (see set_queue_path()).
+    ///
+    /// Pending entries run one after another, since transfers on a session
+    /// are serialized by its lock; set_workers() controls the parallelism
+    /// within each transfer. Entries queued while this runs are picked up
+    /// before it returns. A failed entry is recorded with its error and
+    /// doesn't stop the others. When the call is cancelled, the entry in
+    /// flight is aborted and stays pending, and the CancelledError carries
+    /// a MegaTransferSummary of the entries run before in its
+    /// partial_summary attribute.
+    ///
+    /// Args:
+    ///     retry_failed: Also run entries whose last attempt failed
+    ///
+    /// Returns:
+    ///     MegaTransferSummary with one item per entry run; path is the
+    ///     remote path, detail the local path, and action is "downloaded",
+    ///     "uploaded" or "failed"
+    ///
+    /// Raises:
+    ///     QueueCorruptError: If the journal is damaged
+    #[pyo3(signature = (retry_failed = false))]
+    fn run_queue<'p>(&self, py: Python<'p>, retry_failed: bool) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        let queue = self.queue.clone();
+        let running = self.queue_running.clone();
+        let resume = self.resume.clone();
+        let read_only = self.read_only;
+        let summary = PartialSummary::default();
+        let items = summary.clone();
+        let awaitable = self.run_mut(py, async move {
+            if running.swap(true, Ordering::SeqCst) {
+                return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                    "run_queue() is already running",
+                ));
+            }
+            struct Running(Arc<AtomicBool>);
+            impl Drop for Running {
+                fn drop(&mut self) {
+                    self.0.store(false, Ordering::SeqCst);
+                }
+            }
+            let _running = Running(running);
+
+            let mut last_id = 0;
+            loop {
+                let batch: Vec<MegaQueueEntry> = {
+                    let queue = queue.lock().await;
+                    read_queue(queue_path(&queue)?)?
+                        .into_iter()
+                        .filter(|entry| entry.id > last_id)
+                        .filter(|entry| {
+                            entry.state == QUEUE_PENDING
+                                || (retry_failed && entry.state == QUEUE_FAILED)
+                        })
+                        .collect()
+                };
+                if batch.is_empty() {
+                    break;
+                }
+                for entry in batch {
+                    last_id = entry.id;
+                    let started = std::time::Instant::now();
+                    let outcome = if entry.kind == "upload" && read_only {
+                        Err(ReadOnlyError::new_err(
+                            "Queued uploads can't run on a read-only session",
+                        ))
+                    } else {
+                        let mut session = inner.lock().await;
+                        run_queued(&mut session, &entry, resume.load(Ordering::Relaxed)).await
+                    };
+                    let (record, item) = match outcome {
+                        Ok((handle, bytes)) => (
+                            serde_json::json!({"op": QUEUE_DONE, "id": entry.id}),
+                            MegaTransferItem {
+                                path: entry.remote_path,
+                                handle,
+                                action: if entry.kind == "upload" {
+                                    "uploaded"
+                                } else {
+                                    "downloaded"
+                                }
+                                .to_string(),
+                                bytes,
+                                detail: Some(entry.local_path),
+                                error: None,
+                                error_type: None,
+                                duration: started.elapsed().as_secs_f64(),
+                            },
+                        ),
+                        Err(e) => (
+                            serde_json::json!({
+                                "op": QUEUE_FAILED,
+                                "id": entry.id,
+                                "error": e.to_string(),
+                            }),
+                            MegaTransferItem {
+                                path: entry.remote_path,
+                                handle: None,
+                                action: "failed".to_string(),
+                                bytes: 0,
+                                detail: Some(entry.local_path),
+                                error: Some(e.to_string()),
+                                error_type: exception_name(&e),
+                                duration: started.elapsed().as_secs_f64(),
+                            },
+                        ),
+                    };
+                    let queue = queue.lock().await;
+                    append_queue(queue_path(&queue)?, &record)?;
+                    items.push(item);
+                }
+            }
+            Ok(items.finish())
+        })?;
+        with_partial_summary(py, awaitable, summary)
+    }
+
+    /// Enable/disable resume for interrupted downloads.
+    ///
+    /// Args:
+    ///     enabled: True to enable, False to disable
+    fn set_resume<'p>(&self, py: Python<'p>, enabled: bool) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        self.resume.store(enabled, Ordering::Relaxed);
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            session.set_resume(enabled);
+            Ok(())
+        })
+    }
+
+    /// Enable/disable thumbnail generation on upload.
+    ///
+    /// Args:
+    ///     enabled: True to enable, False to disable
+    fn enable_previews<'p>(&self, py: Python<'p>, enabled: bool) -> PyResult<&'p PyAny> {
         let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            session.enable_previews(enabled);
+            Ok(())
+        })
+    }
+
+    /// Share a folder with another user.
+    ///
+    /// Args:
+    ///     path: Path to folder to share
+    ///     email: Email of user to share with
+    ///     access_level: 0=read, 1=write, 2=full
+    ///
+    /// Returns:
+    ///     MegaShareResult telling whether the share is active, pending
+    ///     contact acceptance, or an invitation to a new MEGA user
+    fn share_folder<'p>(
+        &self,
+        py: Python<'p>,
+        path: String,
+        email: String,
+        access_level: i32,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        self.run_mut(py, async move {
             let mut session = inner.lock().await;
+            ensure_access(&session, &path, ACCESS_READ_WRITE)?;
+            let contact = session
+                .list_contacts()
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(&email))
+                .map(|c| c.handle.clone());
+            let (status, user_handle) = match contact {
+                Some(handle) => (SHARE_ACTIVE, Some(handle)),
+                None => match api(session.lookup_user(&email))
+                    .await
+                    .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?
+                {
+                    Some(handle) => (SHARE_PENDING, Some(handle)),
+                    None => (SHARE_INVITED, None),
+                },
+            };
+
+            api(session.share_folder(&path, &email, access_level))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            Ok(MegaShareResult {
+                status: status.to_string(),
+                email,
+                user_handle,
+            })
+        })
+    }
+
+    /// List all contacts (users you've shared with).
+    ///
+    /// Returns:
+    ///     List of MegaNode objects representing contacts
+    fn list_contacts<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let py_contacts: Vec<MegaNode> = {
+                let session = inner.lock().await;
+                let contacts = session.list_contacts();
+                contacts.iter().map(|n| MegaNode::from(*n)).collect()
+            };
+            nodes_into_py(py_contacts)
+        })
+    }
+
+    /// Look up the user behind a user handle, e.g. MegaNode.owner_handle.
+    ///
+    /// Only the account itself and its contacts are known; no request is
+    /// sent for other users.
+    ///
+    /// Args:
+    ///     handle: User handle
+    ///
+    /// Returns:
+    ///     MegaContact, or None if the user isn't a contact
+    fn resolve_user<'p>(&self, py: Python<'p>, handle: String) -> PyResult<&'p PyAny> {
+        let inner = self.tree()?;
+        self.run(py, async move {
+            let session = inner.lock().await;
+            if handle == session.user_handle {
+                return Ok(Some(MegaContact {
+                    handle,
+                    email: session.email.clone(),
+                    name: session.name.clone(),
+                }));
+            }
+            Ok(session
+                .list_contacts()
+                .into_iter()
+                .find(|contact| contact.handle == handle)
+                .map(|contact| MegaContact {
+                    handle: contact.handle.clone(),
+                    email: contact.name.clone(),
+                    name: None,
+                }))
+        })
+    }
+
+    /// Save session to file for later restoration.
+    ///
+    /// The saved file contains encrypted credentials - keep it secure!
+    ///
+    /// Args:
+    ///     path: Path to save session file
+    fn save<'p>(&self, py: Python<'p>, path: LocalPath) -> PyResult<&'p PyAny> {
+        let path = path.0;
+        let inner = self.inner.clone();
+        self.run(py, async move {
+            let session = inner.lock().await;
             session
-                .mv(&source, &dest)
+                .save(&path)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            Ok(())
+        })
+    }
+
+    /// Serialize the session to a string, for keeping it somewhere other
+    /// than a file (a secrets manager, a database).
+    ///
+    /// The string holds the same data as save() writes, so it is as
+    /// sensitive as the password. It starts with a version tag
+    /// ("megasession1:"); load_from_string() accepts strings from any
+    /// version that reads that tag.
+    ///
+    /// Returns:
+    ///     The serialized session
+    fn save_to_string<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let inner = self.inner.clone();
+        let temp_dir = self.temp_dir();
+        self.run(py, async move {
+            let spill = SpillDir::create(&temp_dir)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            let path = spill.path.join("session");
+            inner
+                .lock()
+                .await
+                .save(&path)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            let data = std::fs::read(&path)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            Ok(encode_session(&data))
+        })
+    }
+
+    /// Change the user's password.
+    ///
+    /// Args:
+    ///     new_password: New password for the account
+    fn change_password<'p>(&self, py: Python<'p>, new_password: String) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.inner.clone();
+        self.run_mut(py, async move {
+            let mut session = inner.lock().await;
+            api(session.change_password(&new_password))
+                .await
+                .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            Ok(())
+        })
+    }
+
+    /// Download a file to a specific file path.
+    ///
+    /// With set_resume(True), an existing partial file at local_path is
+    /// continued instead of being downloaded again.
+    ///
+    /// With resume_verify=True the partial file isn't trusted blindly: it is
+    /// first cut back to the last MAC chunk boundary, dropping a tail torn
+    /// by a killed process, and the finished file is checked against the
+    /// file MAC. MEGA only stores the MAC of the whole file, so if the check
+    /// fails the file is downloaded again from scratch.
+    ///
+    /// Args:
+    ///     remote_path: Path to file on MEGA
+    ///     local_path: Destination path on local disk
+    ///     ignore_space_check: Skip checking the free disk space against the
+    ///         file size before starting, for filesystems that misreport it
+    ///     resume_verify: Verify a resumed partial file as described above
+    ///     verify_progress: Called as verify_progress(done, total) while the
+    ///         file is checked, which can take a while for large files
+    ///     progress: Called as progress(bytes_done, bytes_total) a few times
+    ///         per second while the file downloads; bytes_done starts at the
+    ///         resumed offset. If it raises, the download is cancelled like
+    ///         below and the exception propagates.
+    ///     digest: Optional hash of the whole file ("sha256", "sha1" or
+    ///         "blake3"). A resumed file is hashed from disk after the
+    ///         transfer, covering the kept prefix and the new tail.
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Cancelling the awaiting task stops the download and releases the
+    /// session. With resume enabled the partial file is kept to continue
+    /// later, otherwise it is removed.
+    ///
+    /// Returns:
+    ///     MegaDownloadResult telling how much of the file was reused,
+    ///     including the hex digest if `digest` was given
+    ///
+    /// Raises:
+    ///     DiskFullError: If the file doesn't fit on the local disk, or the
+    ///         disk filled up during the download (the partial file is kept
+    ///         for resuming)
+    #[pyo3(signature = (
+        remote_path,
+        local_path,
+        ignore_space_check = false,
+        resume_verify = false,
+        verify_progress = None,
+        progress = None,
+        *,
+        digest = None,
+        timeout = None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn download_to_file<'p>(
+        &self,
+        py: Python<'p>,
+        remote_path: String,
+        local_path: LocalPath,
+        ignore_space_check: bool,
+        resume_verify: bool,
+        verify_progress: Option<PyObject>,
+        progress: Option<PyObject>,
+        digest: Option<String>,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        let local_path = local_path.0;
+        let hasher = digest.as_deref().map(Hasher::new).transpose()?;
+        let inner = self.tree()?;
+        let resume = self.resume.clone();
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            let node = session.stat(&expand_virtual_path(&remote_path)).cloned();
+
+            if let Some(node) = node {
+                ensure_available(&node)?;
+                let resume = resume.load(Ordering::Relaxed);
+                let mut reused = if resume {
+                    partial_download_len(&local_path, node.size)
+                } else {
+                    0
+                };
+                let mut redownloaded = 0;
+                if resume_verify && reused > 0 {
+                    let boundary = mac_chunk_floor(reused);
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .open(&local_path)
+                        .and_then(|file| file.set_len(boundary))
+                        .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+                    redownloaded = reused - boundary;
+                    reused = boundary;
+                }
+                if !ignore_space_check {
+                    ensure_disk_space(&local_path, node.size)?;
+                }
+                let download_err = |e: ::megalib::MegaError| match &e {
+                    ::megalib::MegaError::Io(io) if is_disk_full(io) => {
+                        disk_full_mid_download(&local_path)
+                    }
+                    _ => mega_err::<pyo3::exceptions::PyRuntimeError>(e),
+                };
+                // Without resume a cancelled download's file is useless.
+                let mut partial = PartialFile((!resume).then(|| local_path.clone()));
+                let outcome = with_progress(
+                    api(session.download_to_file(&node, &local_path)),
+                    progress.as_ref(),
+                    node.size,
+                    || file_len(&local_path),
+                )
+                .await;
+                if outcome.is_ok() {
+                    partial.keep();
+                }
+                outcome?.map_err(download_err)?;
+                let mut transferred = node.size - reused;
+                count_downloaded(transferred);
+
+                let mut reverified = 0;
+                let mut verified = false;
+                if resume_verify && reused > 0 {
+                    let path = local_path.clone();
+                    let key = node.key.clone();
+                    let matches = tokio::task::spawn_blocking(move || {
+                        file_mac_matches(&path, &key, verify_progress.as_ref())
+                    })
+                    .await
+                    .map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                    })??;
+                    reverified = reused;
+                    verified = matches;
+                    if !matches {
+                        std::fs::File::create(&local_path).map_err(|e| {
+                            PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string())
+                        })?;
+                        let mut partial = PartialFile((!resume).then(|| local_path.clone()));
+                        let outcome = with_progress(
+                            api(session.download_to_file(&node, &local_path)),
+                            progress.as_ref(),
+                            node.size,
+                            || file_len(&local_path),
+                        )
+                        .await;
+                        if outcome.is_ok() {
+                            partial.keep();
+                        }
+                        outcome?.map_err(download_err)?;
+                        transferred += node.size;
+                        count_downloaded(node.size);
+                        redownloaded += reused;
+                        reused = 0;
+                    }
+                }
+                let digest = match hasher {
+                    Some(hasher) => {
+                        let path = local_path.clone();
+                        let digest = tokio::task::spawn_blocking(move || hasher.hash_file(&path))
+                            .await
+                            .map_err(|e| {
+                                PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+                            })?
+                            .map_err(|e| {
+                                PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string())
+                            })?;
+                        Some(digest)
+                    }
+                    None => None,
+                };
+                Ok(MegaDownloadResult {
+                    local_path,
+                    bytes_transferred: transferred,
+                    bytes_reused_from_resume: reused,
+                    bytes_reverified: reverified,
+                    bytes_redownloaded: redownloaded,
+                    verified,
+                    digest,
+                    attempts: 1,
+                })
+            } else {
+                Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                    "File not found on Mega",
+                ))
+            }
+        })
+    }
+
+    /// Download a folder and everything below it.
+    ///
+    /// The folder structure is recreated under `local_dir`, including empty
+    /// folders, and files are downloaded one after another like
+    /// download_to_file(), so set_workers() and set_resume() apply. The
+    /// session is locked for one file at a time, so other calls on it can
+    /// run in between. A file that fails is reported and doesn't stop the
+    /// others, except when the disk is full (DiskFullError): then no
+    /// further files are started. Nodes whose names
+    /// can't be used as a local file name without leaving their folder
+    /// (e.g. ".." or names containing a slash) are skipped. When the call
+    /// is cancelled, the file in flight is aborted and the CancelledError
+    /// carries a MegaTransferSummary of the items so far in its
+    /// partial_summary attribute.
+    ///
+    /// Args:
+    ///     remote_path: Folder on MEGA
+    ///     local_dir: Local directory to download into, created if missing
+    ///     progress: Called as progress(bytes_done, bytes_total, path,
+    ///         files_done, files_total) a few times per second while a file
+    ///         downloads and once after each file, with path the remote path
+    ///         of that file. The totals cover every file to download; failed
+    ///         files count as done. If it raises, the download stops and the
+    ///         exception propagates.
+    ///
+    /// Returns:
+    ///     MegaTransferSummary with one item per file, plus failed and
+    ///     skipped folders; path is the remote path, detail the local path
+    ///     (the reason for skipped items), and action is "downloaded",
+    ///     "skipped" or "failed"
+    ///
+    /// Raises:
+    ///     NotFoundError: If remote_path isn't a folder
+    ///     PartialTreeError: If refresh() didn't load the whole tree
+    #[pyo3(signature = (remote_path, local_dir, progress = None))]
+    fn download_folder<'p>(
+        &self,
+        py: Python<'p>,
+        remote_path: String,
+        local_dir: LocalPath,
+        progress: Option<PyObject>,
+    ) -> PyResult<&'p PyAny> {
+        let local_dir = local_dir.0;
+        let inner = self.complete_tree("download_folder()")?;
+        let resume = self.resume.clone();
+        let summary = PartialSummary::default();
+        let items = summary.clone();
+        let awaitable = self.run_mut(py, async move {
+            let root = expand_virtual_path(&remote_path).into_owned();
+            // Plan from the tree first; the session is then only locked
+            // for one file at a time, so other calls get in between.
+            let (steps, skipped) = {
+                let session = inner.lock().await;
+                if !session
+                    .stat(&root)
+                    .is_some_and(|node| node.node_type.is_container())
+                {
+                    return Err(NotFoundError::new_err(format!(
+                        "{} is not a folder",
+                        remote_path
+                    )));
+                }
+                plan_folder_download(&session, &root, &local_dir)?
+            };
+            tokio::fs::create_dir_all(&local_dir)
+                .await
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            items.extend(skipped);
+            let resume = resume.load(Ordering::Relaxed);
+            let mut progress = FolderProgress::new(
+                progress,
+                steps.iter().filter_map(|step| match step {
+                    FolderStep::File { node, .. } => Some(node.size),
+                    FolderStep::Dir { .. } => None,
+                }),
+            );
+
+            for step in steps {
+                let started = std::time::Instant::now();
+                let (path, handle, local_path, outcome) = match step {
+                    FolderStep::Dir {
+                        path,
+                        handle,
+                        local_path,
+                    } => match tokio::fs::create_dir_all(&local_path).await {
+                        Ok(()) => continue,
+                        Err(e) => {
+                            let e = PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+                            (path, handle, local_path, Err(e))
+                        }
+                    },
+                    FolderStep::File {
+                        path,
+                        node,
+                        local_path,
+                    } => {
+                        let outcome = {
+                            let mut session = inner.lock().await;
+                            let download = download_node(&mut session, &node, &local_path, resume);
+                            progress
+                                .file(&path, node.size, download, || file_len(&local_path))
+                                .await?
+                        };
+                        (path, node.handle, local_path, outcome)
+                    }
+                };
+                let disk_full = outcome
+                    .as_ref()
+                    .is_err_and(|e| Python::with_gil(|py| e.is_instance_of::<DiskFullError>(py)));
+                let (action, bytes, error, error_type) = match outcome {
+                    Ok(bytes) => ("downloaded", bytes, None, None),
+                    Err(e) => ("failed", 0, Some(e.to_string()), exception_name(&e)),
+                };
+                items.push(MegaTransferItem {
+                    path,
+                    handle: Some(handle),
+                    action: action.to_string(),
+                    bytes,
+                    detail: Some(local_path.to_string_lossy().into_owned()),
+                    error,
+                    error_type,
+                    duration: started.elapsed().as_secs_f64(),
+                });
+                // The remaining files wouldn't fit either.
+                if disk_full {
+                    break;
+                }
+            }
+            Ok(items.finish())
+        })?;
+        with_partial_summary(py, awaitable, summary)
+    }
+
+    /// Upload a file resumable.
+    ///
+    /// Args:
+    ///     local_path: Path to local file
+    ///     remote_path: Destination folder on MEGA
+    ///     precheck_quota: If True, raise OverQuotaError before transferring
+    ///         anything when the file doesn't fit in the remaining storage
+    ///     progress: Called as progress(bytes_done, bytes_total) a few times
+    ///         per second while the file uploads; when an earlier upload is
+    ///         resumed, bytes_done starts with the chunks it already sent. If
+    ///         it raises, the upload is cancelled and the exception
+    ///         propagates.
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     MegaUploadResult with the new node and its size
+    #[pyo3(signature = (local_path, remote_path, precheck_quota = false, progress = None, *, timeout = None))]
+    fn upload_resumable<'p>(
+        &self,
+        py: Python<'p>,
+        local_path: LocalPath,
+        remote_path: String,
+        precheck_quota: bool,
+        progress: Option<PyObject>,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let local_path = local_path.0;
+        let inner = self.tree()?;
+        let throttle = self.throttle.clone();
+        self.run_mut_within(py, timeout, async move {
+            let mut session = inner.lock().await;
+            ensure_access(&session, &remote_path, ACCESS_READ_WRITE)?;
+            if precheck_quota {
+                ensure_quota(&mut session, &local_path).await?;
+            }
+            let total = file_len(&local_path);
+            let done = watch_transfer(&mut session, &throttle);
+            let upload = api(session.upload_resumable(&local_path, &remote_path));
+            with_progress(upload, progress.as_ref(), total, || {
+                done.load(Ordering::Relaxed)
+            })
+            .await?
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+            let result = MegaUploadResult::new(&session, &local_path, &remote_path)?;
+            count_uploaded(result.bytes);
+            Ok(result)
+        })
+    }
+
+    /// Upload data from a stream of unknown length.
+    ///
+    /// The source is read until it is exhausted and spooled to a temporary
+    /// file, so memory use stays bounded. Nothing is created on MEGA unless
+    /// the whole source was read successfully; errors raised by the source
+    /// propagate unchanged.
+    ///
+    /// When `size` is given, access to `remote_dir` and the remaining
+    /// storage are checked before the source is read, and a source that
+    /// ends up shorter or longer raises ValueError without uploading.
+    ///
+    /// Args:
+    ///     source: File-like object with read(n), or an async iterable of bytes
+    ///     remote_dir: Destination folder on MEGA
+    ///     name: File name for the uploaded node
+    ///     size: Expected number of bytes, if known
+    ///
+    /// Returns:
+    ///     MegaNode for the uploaded file
+    #[pyo3(signature = (source, remote_dir, name, size = None))]
+    fn upload_stream<'p>(
+        &self,
+        py: Python<'p>,
+        source: &PyAny,
+        remote_dir: String,
+        name: String,
+        size: Option<u64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        let source = StreamSource::new(source)?;
+        let temp_dir = self.temp_dir();
+        self.run_mut(py, async move {
+            if let Some(size) = size {
+                let mut session = inner.lock().await;
+                ensure_access(&session, &remote_dir, ACCESS_READ_WRITE)?;
+                ensure_fits(&mut session, size).await?;
+            }
+            let spill = SpillDir::create(&temp_dir)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            let spill_path = spill.path.join(&name);
+            let written = spool_stream(&source, &spill_path).await?;
+            if let Some(size) = size.filter(|&size| size != written) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Source gave {} bytes but size was {}",
+                    written, size
+                )));
+            }
+            let size = written;
+
+            let mut session = inner.lock().await;
+            upload_spilled(&mut session, &spill_path, &remote_dir, size).await
+        })
+    }
+
+    /// Upload a local directory and everything below it.
+    ///
+    /// The contents of `local_dir` are mirrored into `remote_dir`: missing
+    /// folders are created and existing ones reused, and files are uploaded
+    /// one after another like upload(). Hidden files are included. The
+    /// directory is walked first; the session is then locked for one
+    /// folder or file at a time. A file that fails is reported and doesn't
+    /// stop the others; nothing is uploaded below a folder that couldn't
+    /// be created. When the call is cancelled, the file in flight is
+    /// aborted and the CancelledError carries a MegaTransferSummary of the
+    /// items so far in its partial_summary attribute.
+    ///
+    /// Args:
+    ///     local_dir: Local directory to upload
+    ///     remote_dir: Folder on MEGA to upload into, created if missing
+    ///         (its parent must exist)
+    ///     follow_symlinks: Upload what symlinks point to instead of
+    ///         skipping them; symlinks leading back into a directory being
+    ///         uploaded are still skipped
+    ///     progress: Called as progress(bytes_done, bytes_total, path,
+    ///         files_done, files_total) a few times per second while a file
+    ///         uploads and once after each file, with path the local path of
+    ///         that file. The totals cover every file found in the walk;
+    ///         failed files count as done. If it raises, the upload stops
+    ///         and the exception propagates.
+    ///     use_ignore_files: Skip what the .megaignore files in local_dir
+    ///         and its subdirectories exclude. They use gitignore syntax and
+    ///         apply to their directory and everything below it, with later
+    ///         patterns and deeper files taking precedence. Skipped items
+    ///         name the file, line and pattern that excluded them.
+    ///
+    /// Returns:
+    ///     MegaTransferSummary with one item per file, plus failed and
+    ///     skipped entries; path is the local path, detail the remote
+    ///     folder (the reason for skipped items), and action is "uploaded",
+    ///     "skipped" or "failed"
+    ///
+    /// Raises:
+    ///     PartialTreeError: If refresh() didn't load the whole tree
+    ///     ValueError: If an ignore file has an invalid pattern; the message
+    ///         starts with "<file>:<line>:"
+    #[pyo3(signature = (local_dir, remote_dir, follow_symlinks = false, progress = None, use_ignore_files = false))]
+    fn upload_folder<'p>(
+        &self,
+        py: Python<'p>,
+        local_dir: LocalPath,
+        remote_dir: String,
+        follow_symlinks: bool,
+        progress: Option<PyObject>,
+        use_ignore_files: bool,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let local_dir = local_dir.0;
+        let inner = self.complete_tree("upload_folder()")?;
+        let throttle = self.throttle.clone();
+        let summary = PartialSummary::default();
+        let items = summary.clone();
+        let awaitable = self.run_mut(py, async move {
+            let remote_dir = expand_virtual_path(&remote_dir)
+                .trim_end_matches('/')
+                .to_string();
+            ensure_access(&*inner.lock().await, &remote_dir, ACCESS_READ_WRITE)?;
+            // Walk first, so a bad ignore file fails before anything changes.
+            let (steps, skipped) = {
+                let remote_dir = remote_dir.clone();
+                tokio::task::spawn_blocking(move || {
+                    plan_folder_upload(&local_dir, &remote_dir, follow_symlinks, use_ignore_files)
+                })
+                .await
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))??
+            };
+            ensure_folder(&mut *inner.lock().await, &remote_dir).await?;
+            items.extend(skipped);
+            let mut progress = FolderProgress::new(
+                progress,
+                steps.iter().filter_map(|step| match step {
+                    UploadStep::File { size, .. } => Some(*size),
+                    UploadStep::Dir { .. } => None,
+                }),
+            );
+
+            // Remote folders that couldn't be created; nothing is uploaded
+            // below them.
+            let mut missing = std::collections::HashSet::new();
+            for step in steps {
+                let started = std::time::Instant::now();
+                let (local_path, remote, outcome) = match step {
+                    UploadStep::Dir {
+                        local_path,
+                        remote_dir,
+                        name,
+                    } => {
+                        let remote_path = format!("{}/{}", remote_dir, name);
+                        if missing.contains(&remote_dir) {
+                            missing.insert(remote_path);
+                            continue;
+                        }
+                        let mut session = inner.lock().await;
+                        let created = ensure_folder(&mut session, &remote_path).await;
+                        let is_folder = session
+                            .stat(&remote_path)
+                            .is_some_and(|node| node.node_type.is_container());
+                        let e = match created {
+                            Ok(_) if is_folder => continue,
+                            Ok(_) => PyErr::new::<pyo3::exceptions::PyFileExistsError, _>(format!(
+                                "{} exists and isn't a folder",
+                                remote_path
+                            )),
+                            Err(e) => e,
+                        };
+                        missing.insert(remote_path);
+                        (local_path, remote_dir, Err(e))
+                    }
+                    UploadStep::File {
+                        local_path,
+                        remote_dir,
+                        size,
+                    } => {
+                        let path = local_path.to_string_lossy().into_owned();
+                        if missing.contains(&remote_dir) {
+                            progress.skip(&path, size)?;
+                            continue;
+                        }
+                        let mut session = inner.lock().await;
+                        let done = watch_transfer(&mut session, &throttle);
+                        let upload = api(session.upload(&local_path, &remote_dir));
+                        let outcome = match progress
+                            .file(&path, size, upload, || done.load(Ordering::Relaxed))
+                            .await?
+                        {
+                            Ok(_) => MegaUploadResult::new(&session, &local_path, &remote_dir),
+                            Err(e) => Err(mega_err::<pyo3::exceptions::PyRuntimeError>(e)),
+                        };
+                        (local_path, remote_dir, outcome)
+                    }
+                };
+                let (handle, action, bytes, error, error_type) = match outcome {
+                    Ok(result) => {
+                        count_uploaded(result.bytes);
+                        (
+                            result.node.map(|node| node.handle),
+                            "uploaded",
+                            result.bytes,
+                            None,
+                            None,
+                        )
+                    }
+                    Err(e) => (None, "failed", 0, Some(e.to_string()), exception_name(&e)),
+                };
+                items.push(MegaTransferItem {
+                    path: local_path.to_string_lossy().into_owned(),
+                    handle,
+                    action: action.to_string(),
+                    bytes,
+                    detail: Some(remote),
+                    error,
+                    error_type,
+                    duration: started.elapsed().as_secs_f64(),
+                });
+            }
+            Ok(items.finish())
+        })?;
+        with_partial_summary(py, awaitable, summary)
+    }
+
+    /// Upload a file from memory.
+    ///
+    /// The data is copied when the call is made, so the buffer may be
+    /// changed as soon as this returns. Empty data creates an empty file.
+    ///
+    /// Args:
+    ///     data: bytes, bytearray, memoryview or any other object
+    ///         supporting the buffer protocol
+    ///     remote_dir: Destination folder on MEGA
+    ///     filename: File name for the uploaded node
+    ///     timeout: Seconds before the call is abandoned with TimeoutError,
+    ///         None for the set_timeout() default
+    ///
+    /// Returns:
+    ///     MegaNode for the uploaded file
+    #[pyo3(signature = (data, remote_dir, filename, *, timeout = None))]
+    fn upload_bytes<'p>(
+        &self,
+        py: Python<'p>,
+        data: &PyAny,
+        remote_dir: String,
+        filename: String,
+        timeout: Option<f64>,
+    ) -> PyResult<&'p PyAny> {
+        self.writable()?;
+        let inner = self.tree()?;
+        let data = buffer_to_vec(data)?;
+        let temp_dir = self.temp_dir();
+        self.run_mut_within(py, timeout, async move {
+            let spill = SpillDir::create(&temp_dir)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            let spill_path = spill.path.join(&filename);
+            std::fs::write(&spill_path, &data)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+
+            let mut session = inner.lock().await;
+            upload_spilled(&mut session, &spill_path, &remote_dir, data.len() as u64).await
+        })
+    }
+
+    /// Load a saved session from a file.
+    ///
+    /// Args:
+    ///     path: Path to saved session file
+    ///     auto_refresh: Fetch the file tree as part of loading, so refresh()
+    ///         isn't needed before list(), stat(), etc.
+    ///
+    /// Returns:
+    ///     MegaSession if loaded, None if file not found
+    #[staticmethod]
+    #[pyo3(signature = (path, auto_refresh = true))]
+    fn load(py: Python<'_>, path: LocalPath, auto_refresh: bool) -> PyResult<&PyAny> {
+        let path = path.0;
+        future_into_py(py, async move {
+            match api(Session::load(&path))
+                .await
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?
+            {
+                Some(mut session) => {
+                    if auto_refresh {
+                        fetch_tree(&mut session, false).await?;
+                    }
+                    Ok(Some(MegaSession::new(session, auto_refresh)))
+                }
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// Restore a session serialized by save_to_string().
+    ///
+    /// Args:
+    ///     data: String returned by save_to_string()
+    ///     auto_refresh: Fetch the file tree as part of loading, so refresh()
+    ///         isn't needed before list(), stat(), etc.
+    ///
+    /// Returns:
+    ///     MegaSession
+    ///
+    /// Raises:
+    ///     ValueError: If data isn't a serialized session or is corrupted
+    #[staticmethod]
+    #[pyo3(signature = (data, auto_refresh = true))]
+    fn load_from_string(py: Python<'_>, data: String, auto_refresh: bool) -> PyResult<&PyAny> {
+        let data = decode_session(&data)?;
+        future_into_py(py, async move {
+            let spill = SpillDir::create(&std::env::temp_dir())
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            let path = spill.path.join("session");
+            std::fs::write(&path, &data)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
+            let invalid = |detail: String| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "invalid session data: {}",
+                    detail
+                ))
+            };
+            let mut session = api(Session::load(&path))
                 .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
+                .map_err(|e| invalid(e.to_string()))?
+                .ok_or_else(|| invalid("empty session".to_string()))?;
+            drop(spill);
+            if auto_refresh {
+                fetch_tree(&mut session, false).await?;
+            }
+            Ok(MegaSession::new(session, auto_refresh))
+        })
+    }
+
+    /// Create a session on a new ephemeral (account-less) MEGA account.
+    ///
+    /// Ephemeral accounts need no credentials and support upload() and
+    /// export(). MEGA may garbage-collect an ephemeral account, and with it
+    /// any links exported from it, after some time.
+    ///
+    /// Returns:
+    ///     MegaSession with the file tree already fetched
+    #[staticmethod]
+    fn create_ephemeral(py: Python<'_>) -> PyResult<&PyAny> {
+        future_into_py(py, async move {
+            let session = create_ephemeral_session().await?;
+            Ok(MegaSession::new(session, true))
         })
     }
+}
+
+/// Version tag in front of strings from save_to_string().
+const SESSION_STRING_TAG: &str = "megasession1:";
+
+/// Encode a saved session file as tag || base64url(contents).
+fn encode_session(data: &[u8]) -> String {
+    use base64::Engine;
+    format!(
+        "{}{}",
+        SESSION_STRING_TAG,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data)
+    )
+}
+
+/// Inverse of encode_session(). Raises ValueError on anything else.
+fn decode_session(s: &str) -> PyResult<Vec<u8>> {
+    use base64::Engine;
+    let invalid = |detail: &str| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("invalid session data: {}", detail))
+    };
+    let payload = s
+        .trim()
+        .strip_prefix(SESSION_STRING_TAG)
+        .ok_or_else(|| invalid("missing version tag, not a string from save_to_string()"))?;
+    let data = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| invalid("bad encoding"))?;
+    if data.is_empty() {
+        return Err(invalid("empty session"));
+    }
+    Ok(data)
+}
+
+/// Blocking facade over MegaSession, for code that doesn't run asyncio
+/// (scripts, Django management commands).
+///
+/// Every MegaSession method is available with the same arguments. Each
+/// call runs to completion on a fresh event loop, like asyncio.run(), and
+/// returns the result directly: the same MegaNode, MegaUploadResult, ...
+/// objects the async methods produce. Methods returning a session
+/// (read_only()) return a MegaSessionSync.
+///
+/// Calling a method while an event loop is running in the thread raises
+/// RuntimeError; await the MegaSession methods there instead.
+///
+/// Attributes:
+///     session: The wrapped MegaSession, for use from async code
+#[pyclass]
+struct MegaSessionSync {
+    #[pyo3(get)]
+    session: Py<MegaSession>,
+}
+
+#[pymethods]
+impl MegaSessionSync {
+    /// Wrap an existing MegaSession.
+    #[new]
+    fn new(session: Py<MegaSession>) -> Self {
+        MegaSessionSync { session }
+    }
+
+    /// Blocking MegaSession.login().
+    #[staticmethod]
+    #[pyo3(signature = (email, password, proxy = None, auto_refresh = true, *, timeout = None))]
+    fn login(
+        py: Python<'_>,
+        email: String,
+        password: String,
+        proxy: Option<String>,
+        auto_refresh: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<PyObject> {
+        let kwargs = pyo3::types::PyDict::new(py);
+        kwargs.set_item("email", email)?;
+        kwargs.set_item("password", password)?;
+        kwargs.set_item("proxy", proxy)?;
+        kwargs.set_item("auto_refresh", auto_refresh)?;
+        kwargs.set_item("timeout", timeout)?;
+        run_sync_static(py, "login", kwargs)
+    }
+
+    /// Blocking MegaSession.load().
+    #[staticmethod]
+    #[pyo3(signature = (path, auto_refresh = true))]
+    fn load(py: Python<'_>, path: &PyAny, auto_refresh: bool) -> PyResult<PyObject> {
+        let kwargs = pyo3::types::PyDict::new(py);
+        kwargs.set_item("path", path)?;
+        kwargs.set_item("auto_refresh", auto_refresh)?;
+        run_sync_static(py, "load", kwargs)
+    }
+
+    /// Blocking MegaSession.load_from_string().
+    #[staticmethod]
+    #[pyo3(signature = (data, auto_refresh = true))]
+    fn load_from_string(py: Python<'_>, data: String, auto_refresh: bool) -> PyResult<PyObject> {
+        let kwargs = pyo3::types::PyDict::new(py);
+        kwargs.set_item("data", data)?;
+        kwargs.set_item("auto_refresh", auto_refresh)?;
+        run_sync_static(py, "load_from_string", kwargs)
+    }
+
+    /// Blocking MegaSession.create_ephemeral().
+    #[staticmethod]
+    fn create_ephemeral(py: Python<'_>) -> PyResult<PyObject> {
+        run_sync_static(py, "create_ephemeral", pyo3::types::PyDict::new(py))
+    }
+
+    fn __getattr__(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        if name == "open_read" {
+            return Err(PyErr::new::<pyo3::exceptions::PyAttributeError, _>(
+                "open_read() returns an async stream; use download_bytes() with offset and length instead",
+            ));
+        }
+        let attr = self.session.as_ref(py).getattr(name)?;
+        if !attr.is_callable() {
+            return Ok(attr.into());
+        }
+        Ok(SyncMethod {
+            method: attr.into(),
+        }
+        .into_py(py))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!(
+            "MegaSessionSync({})",
+            self.session.as_ref(py).repr()?
+        ))
+    }
+}
+
+/// A bound MegaSession method, called through MegaSessionSync.
+#[pyclass]
+struct SyncMethod {
+    method: PyObject,
+}
+
+#[pymethods]
+impl SyncMethod {
+    #[pyo3(signature = (*args, **kwargs))]
+    fn __call__(
+        &self,
+        py: Python<'_>,
+        args: &pyo3::types::PyTuple,
+        kwargs: Option<&pyo3::types::PyDict>,
+    ) -> PyResult<PyObject> {
+        run_sync(py, self.method.as_ref(py), args, kwargs)
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!("<blocking {}>", self.method.as_ref(py).repr()?))
+    }
+}
+
+/// Runs a call to completion on a new event loop, awaiting the result if
+/// it is awaitable. The awaitable has to be created inside the loop, see
+/// future_into_py().
+const SYNC_RUNNER: &str = r#"
+import asyncio
+import inspect
+
+
+def run(method, args, kwargs):
+    async def call():
+        result = method(*args, **kwargs)
+        if inspect.isawaitable(result):
+            result = await result
+        return result
+
+    return asyncio.run(call())
+"#;
+
+static SYNC_RUN: pyo3::sync::GILOnceCell<PyObject> = pyo3::sync::GILOnceCell::new();
+
+/// Call `method` and wait for its result for MegaSessionSync, wrapping
+/// returned sessions.
+fn run_sync(
+    py: Python<'_>,
+    method: &PyAny,
+    args: &pyo3::types::PyTuple,
+    kwargs: Option<&pyo3::types::PyDict>,
+) -> PyResult<PyObject> {
+    if pyo3_asyncio::get_running_loop(py).is_ok() {
+        return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "MegaSessionSync can't be used while an asyncio event loop is running; await the MegaSession methods instead",
+        ));
+    }
+    let run = SYNC_RUN.get_or_try_init(py, || -> PyResult<PyObject> {
+        Ok(
+            PyModule::from_code(py, SYNC_RUNNER, "megalib_sync.py", "megalib_sync")?
+                .getattr("run")?
+                .into(),
+        )
+    })?;
+    let kwargs = match kwargs {
+        Some(kwargs) => kwargs,
+        None => pyo3::types::PyDict::new(py),
+    };
+    let result = run.call1(py, (method, args, kwargs))?;
+    match result.extract::<Py<MegaSession>>(py) {
+        Ok(session) => Ok(MegaSessionSync { session }.into_py(py)),
+        Err(_) => Ok(result),
+    }
+}
+
+/// run_sync() for a MegaSession static method.
+fn run_sync_static(py: Python<'_>, name: &str, kwargs: &pyo3::types::PyDict) -> PyResult<PyObject> {
+    let method = py.get_type::<MegaSession>().getattr(name)?;
+    run_sync(py, method, pyo3::types::PyTuple::empty(py), Some(kwargs))
+}
+
+async fn create_ephemeral_session() -> PyResult<Session> {
+    let mut session = api(Session::create_ephemeral())
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+    fetch_tree(&mut session, false).await?;
+    Ok(session)
+}
+
+/// Upload a file without an account and return a public link to it.
+///
+/// Creates an ephemeral session, uploads the file to its cloud drive and
+/// exports it. MEGA may garbage-collect the ephemeral account, and with it
+/// the link, after some time.
+///
+/// Args:
+///     local_path: Path to local file
+///
+/// Returns:
+///     Public URL string
+#[pyfunction]
+fn upload_anonymous(py: Python<'_>, local_path: LocalPath) -> PyResult<&PyAny> {
+    let local_path = local_path.0;
+    future_into_py(py, async move {
+        let name = local_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>("local_path has no file name")
+            })?
+            .to_string();
+
+        let mut session = create_ephemeral_session().await?;
+        api(session.upload(&local_path, "/Root"))
+            .await
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        let url = api(session.export(&format!("/Root/{}", name)))
+            .await
+            .map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+        Ok(url)
+    })
+}
+
+/// Start the registration process for a new MEGA account.
+///
+/// Args:
+///     email: Email address for the new account
+///     password: Password for the new account
+///     name: Display name for the account
+///
+/// Returns:
+///     MegaRegistrationState to save and use with verify_registration()
+#[pyfunction]
+fn register(py: Python<'_>, email: String, password: String, name: String) -> PyResult<&PyAny> {
+    future_into_py(py, async move {
+        let state = api(::megalib::register(&email, &password, &name))
+            .await
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        Ok(MegaRegistrationState { inner: state })
+    })
+}
+
+/// Complete registration using the signup key from email.
+///
+/// Args:
+///     state: MegaRegistrationState from register()
+///     signup_key: The confirmation key from the verification email
+#[pyfunction]
+fn verify_registration<'p>(
+    py: Python<'p>,
+    state: &MegaRegistrationState,
+    signup_key: String,
+) -> PyResult<&'p PyAny> {
+    let state_inner = state.inner.clone();
+    future_into_py(py, async move {
+        api(::megalib::verify_registration(&state_inner, &signup_key))
+            .await
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        Ok(())
+    })
+}
+
+/// Call the user's `fetch_key()` and await the result if it's awaitable.
+///
+/// The call itself runs on a blocking thread, since a sync callable may do
+/// network I/O. None and "" mean the key hasn't arrived yet.
+async fn fetch_signup_key(fetch_key: &PyObject) -> PyResult<Option<String>> {
+    let fetch_key = Python::with_gil(|py| fetch_key.clone_ref(py));
+    let result = tokio::task::spawn_blocking(move || Python::with_gil(|py| fetch_key.call0(py)))
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))??;
+    let awaitable = Python::with_gil(|py| {
+        let result = result.as_ref(py);
+        if result.hasattr("__await__")? {
+            pyo3_asyncio::tokio::into_future(result).map(Some)
+        } else {
+            Ok(None)
+        }
+    })?;
+    let result = match awaitable {
+        Some(future) => future.await?,
+        None => result,
+    };
+    Python::with_gil(|py| {
+        let key: Option<String> = result.extract(py).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyTypeError, _>("fetch_key must return a str or None")
+        })?;
+        Ok(key.filter(|key| !key.is_empty()))
+    })
+}
+
+/// Wait for a registration's signup key, then confirm it and log in.
+///
+/// Calls fetch_key() every poll_interval_secs until it returns the key
+/// from the confirmation email, completes the registration with it and
+/// logs in to the new account.
+///
+/// Args:
+///     state: MegaRegistrationState from register()
+///     fetch_key: Callable taking no arguments, sync or async, returning
+///         the signup key or None while the email hasn't arrived
+///     email: Email address the account was registered with
+///     password: Password the account was registered with
+///     poll_interval_secs: Seconds between calls to fetch_key
+///     timeout_secs: Seconds to wait for the key in total
+///
+/// Returns:
+///     MegaSession for the new account, with its file tree fetched
+///
+/// Raises:
+///     TimeoutError: If no key arrived within timeout_secs
+///     ValueError: If MEGA rejects the key or the login
+///
+/// Exceptions raised by fetch_key propagate unchanged and end the wait.
+#[pyfunction]
+#[pyo3(signature = (
+    state,
+    fetch_key,
+    *,
+    email,
+    password,
+    poll_interval_secs = 10.0,
+    timeout_secs = 600.0
+))]
+fn wait_for_confirmation<'p>(
+    py: Python<'p>,
+    state: &MegaRegistrationState,
+    fetch_key: PyObject,
+    email: String,
+    password: String,
+    poll_interval_secs: f64,
+    timeout_secs: f64,
+) -> PyResult<&'p PyAny> {
+    if !(poll_interval_secs > 0.0 && poll_interval_secs.is_finite()) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "poll_interval_secs must be a positive number",
+        ));
+    }
+    if !(timeout_secs >= 0.0 && timeout_secs.is_finite()) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "timeout_secs must be a non-negative number",
+        ));
+    }
+    let state_inner = state.inner.clone();
+    future_into_py(py, async move {
+        let timed_out = || {
+            PyErr::new::<pyo3::exceptions::PyTimeoutError, _>(format!(
+                "No signup key within {} seconds",
+                timeout_secs
+            ))
+        };
+        let deadline =
+            tokio::time::Instant::now() + std::time::Duration::from_secs_f64(timeout_secs);
+        let interval = std::time::Duration::from_secs_f64(poll_interval_secs);
+        let signup_key = loop {
+            let key = tokio::time::timeout_at(deadline, fetch_signup_key(&fetch_key))
+                .await
+                .map_err(|_| timed_out())??;
+            if let Some(key) = key {
+                break key;
+            }
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return Err(timed_out());
+            }
+            tokio::time::sleep_until((now + interval).min(deadline)).await;
+        };
+
+        api(::megalib::verify_registration(&state_inner, &signup_key))
+            .await
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        let mut session = api(Session::login(&email, &password))
+            .await
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        fetch_tree(&mut session, false).await?;
+        Ok(MegaSession::new(session, true))
+    })
+}
+
+/// Get info about a public file without downloading.
+///
+/// Args:
+///     url: MEGA public link (e.g., "https://mega.nz/file/...")
+///     key: Decryption key, for links that were shared without one
+///     password: Password of a password-protected ("#P!") link
+///
+/// Returns:
+///     MegaPublicFile with name, size, and handle
+///
+/// Raises:
+///     MissingKeyError: If the link has no key and `key` wasn't given, or is
+///         password-protected and `password` wasn't given
+#[pyfunction]
+#[pyo3(signature = (url, key = None, password = None))]
+fn get_public_file_info(
+    py: Python<'_>,
+    url: String,
+    key: Option<String>,
+    password: Option<String>,
+) -> PyResult<&PyAny> {
+    future_into_py(py, async move {
+        let url = complete_link_async(url, key, password).await?;
+        public_file_info(&url).await
+    })
+}
+
+/// Get info about a public file from its handle and key.
+///
+/// Same as get_public_file_info() for applications that store handles and
+/// keys instead of links.
+///
+/// Args:
+///     handle: Public handle of the file
+///     key: Base64url-encoded file key (32 bytes)
+///
+/// Returns:
+///     MegaPublicFile with name, size, and handle
+///
+/// Raises:
+///     ValueError: If the key doesn't decode to 32 bytes; raised before any
+///         request is sent
+#[pyfunction]
+fn get_public_file_info_by_handle(py: Python<'_>, handle: String, key: String) -> PyResult<&PyAny> {
+    let url = link_from_handle(LinkKind::File, &handle, &key)?;
+    future_into_py(py, async move { public_file_info(&url).await })
+}
+
+async fn public_file_info(url: &str) -> PyResult<MegaPublicFile> {
+    let info = api(::megalib::get_public_file_info(url))
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+    Ok(MegaPublicFile {
+        name: info.name,
+        size: info.size,
+        handle: info.handle,
+    })
+}
+
+/// Download a file from a public MEGA link.
+///
+/// Besides file links this accepts links to a file inside a public folder
+/// (".../folder/HANDLE#KEY/file/NODE").
+///
+/// Args:
+///     url: MEGA public link
+///     local_path: Destination path on local disk
+///     digest: Optional hash to compute while downloading
+///         ("sha256", "sha1" or "blake3")
+///     key: Decryption key, for links that were shared without one
+///     password: Password of a password-protected ("#P!") link
+///
+/// Returns:
+///     MegaDownloadResult, including the hex digest if `digest` was given
+///
+/// Raises:
+///     MissingKeyError: If the link has no key and `key` wasn't given, or is
+///         password-protected and `password` wasn't given
+///     DiskFullError: If the file doesn't fit on the local disk, or the disk
+///         filled up during the download
+#[pyfunction]
+#[pyo3(signature = (
+    url,
+    local_path,
+    digest = None,
+    key = None,
+    password = None,
+    ignore_space_check = false
+))]
+fn download_public_file(
+    py: Python<'_>,
+    url: String,
+    local_path: LocalPath,
+    digest: Option<String>,
+    key: Option<String>,
+    password: Option<String>,
+    ignore_space_check: bool,
+) -> PyResult<&PyAny> {
+    let local_path = local_path.0;
+    future_into_py(py, async move {
+        let url = complete_link_async(url, key, password).await?;
+        download_public(&url, local_path, digest, ignore_space_check).await
+    })
+}
+
+/// Download a public file from its handle and key.
+///
+/// Same as download_public_file() for applications that store handles and
+/// keys instead of links.
+///
+/// Args:
+///     handle: Public handle of the file
+///     key: Base64url-encoded file key (32 bytes)
+///     local_path: Destination path on local disk
+///     digest: Optional hash to compute while downloading
+///         ("sha256", "sha1" or "blake3")
+///     ignore_space_check: Skip checking the free disk space first
+///
+/// Returns:
+///     MegaDownloadResult, including the hex digest if `digest` was given
+///
+/// Raises:
+///     ValueError: If the key doesn't decode to 32 bytes; raised before any
+///         request is sent
+///     DiskFullError: If the file doesn't fit on the local disk, or the disk
+///         filled up during the download
+#[pyfunction]
+#[pyo3(signature = (handle, key, local_path, digest = None, ignore_space_check = false))]
+fn download_public_file_by_handle(
+    py: Python<'_>,
+    handle: String,
+    key: String,
+    local_path: LocalPath,
+    digest: Option<String>,
+    ignore_space_check: bool,
+) -> PyResult<&PyAny> {
+    let local_path = local_path.0;
+    let url = link_from_handle(LinkKind::File, &handle, &key)?;
+    future_into_py(py, async move {
+        download_public(&url, local_path, digest, ignore_space_check).await
+    })
+}
+
+/// Download the file a complete public link points at.
+async fn download_public(
+    url: &str,
+    local_path: std::path::PathBuf,
+    digest: Option<String>,
+    ignore_space_check: bool,
+) -> PyResult<MegaDownloadResult> {
+    match FolderLink::parse(url).and_then(|link| link.node) {
+        Some(node_handle) => {
+            let folder = open_public_folder(url).await?;
+            let node = resolve_public_node(&folder.inner, &node_handle)
+                .cloned()
+                .ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                        "File not found in public folder",
+                    )
+                })?;
+            ensure_available(&node)?;
+            if !ignore_space_check {
+                ensure_disk_space(&local_path, node.size)?;
+            }
+            let mut writer = download_writer(&local_path, digest.as_deref())?;
+            let outcome = api(folder.inner.download(&node, &mut writer)).await;
+            writer.finish(outcome, local_path)
+        }
+        None => {
+            if !ignore_space_check {
+                let info = api(::megalib::get_public_file_info(url))
+                    .await
+                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+                ensure_disk_space(&local_path, info.size)?;
+            }
+            let mut writer = download_writer(&local_path, digest.as_deref())?;
+            let outcome = api(::megalib::download_public_file(url, &mut writer)).await;
+            writer.finish(outcome, local_path)
+        }
+    }
+}
+
+/// Download a public file into memory.
+///
+/// Accepts the same links as download_public_file(), including legacy
+/// ("#!") links and files inside a public folder. The data is checked
+/// against the file MAC before it is returned.
+///
+/// Args:
+///     url: MEGA public link
+///     max_size: Largest file to download in bytes, None for no limit
+///         (64 MiB by default)
+///     key: Decryption key, for links that were shared without one
+///     password: Password of a password-protected ("#P!") link
+///
+/// Returns:
+///     The decrypted file contents as bytes
+///
+/// Raises:
+///     ValueError: If the file is larger than max_size; nothing is
+///         downloaded
+///     MissingKeyError: If the link has no key and `key` wasn't given, or is
+///         password-protected and `password` wasn't given
+///     RuntimeError: If the data doesn't match the file MAC
+#[pyfunction]
+#[pyo3(signature = (url, max_size = Some(DOWNLOAD_BYTES_MAX), *, key = None, password = None))]
+fn download_public_file_bytes(
+    py: Python<'_>,
+    url: String,
+    max_size: Option<u64>,
+    key: Option<String>,
+    password: Option<String>,
+) -> PyResult<&PyAny> {
+    future_into_py(py, async move {
+        let url = complete_link_async(url, key, password).await?;
+        let data = download_public_bytes(&url, max_size).await?;
+        Ok(Python::with_gil(|py| {
+            PyObject::from(pyo3::types::PyBytes::new(py, &data))
+        }))
+    })
+}
+
+/// Download the file a complete public link points at into memory and
+/// verify it.
+async fn download_public_bytes(url: &str, max_size: Option<u64>) -> PyResult<Vec<u8>> {
+    let check_size = |size: u64| match max_size {
+        Some(max_size) if size > max_size => {
+            Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "File is {} bytes, more than max_size ({} bytes)",
+                size, max_size
+            )))
+        }
+        _ => Ok(()),
+    };
+    let (data, key, size, outcome) = match FolderLink::parse(url).and_then(|link| link.node) {
+        Some(node_handle) => {
+            let folder = open_public_folder(url).await?;
+            let node = resolve_public_node(&folder.inner, &node_handle)
+                .cloned()
+                .ok_or_else(|| {
+                    PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                        "File not found in public folder",
+                    )
+                })?;
+            ensure_available(&node)?;
+            check_size(node.size)?;
+            let mut data = Vec::with_capacity(node.size as usize);
+            let outcome = api(folder.inner.download(&node, &mut data))
+                .await
+                .map(|_| ());
+            (data, node.key, node.size, outcome)
+        }
+        None => {
+            let key = file_link_key(url).ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>("Invalid file key in link")
+            })?;
+            let info = api(::megalib::get_public_file_info(url))
+                .await
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+            check_size(info.size)?;
+            let mut data = Vec::with_capacity(info.size as usize);
+            let outcome = api(::megalib::download_public_file(url, &mut data))
+                .await
+                .map(|_| ());
+            (data, key, info.size, outcome)
+        }
+    };
+    count_downloaded(data.len() as u64);
+    outcome.map_err(mega_err::<pyo3::exceptions::PyRuntimeError>)?;
+    if data.len() as u64 != size {
+        return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+            "Download truncated after {} of {} bytes",
+            data.len(),
+            size
+        )));
+    }
+    // Hashing a large file takes a while; keep it off the runtime's workers.
+    tokio::task::spawn_blocking(move || {
+        let mut mac = StreamMac::new(&key)?;
+        mac.update(&data);
+        if mac.matches() {
+            Ok(data)
+        } else {
+            Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "Downloaded data doesn't match the file MAC",
+            ))
+        }
+    })
+    .await
+    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+}
+
+/// Longest pause between fetch_public_file() attempts.
+const FETCH_MAX_BACKOFF: f64 = 60.0;
+
+/// Pause before retrying after `err`, or None if retrying can't help.
+///
+/// Congestion, rate limiting and network errors are retried with
+/// exponential backoff. Taken-down links, wrong keys, missing files, quota
+/// errors and a full local disk are final.
+fn fetch_retry_delay(err: &::megalib::MegaError, attempt: u32) -> Option<std::time::Duration> {
+    let retryable = match err {
+        ::megalib::MegaError::ApiError {
+            code: EAGAIN | ERATELIMIT,
+            ..
+        } => true,
+        ::megalib::MegaError::Io(io) => !is_disk_full(io),
+        _ => false,
+    };
+    retryable.then(|| {
+        std::time::Duration::from_secs_f64(2f64.powi(attempt as i32).min(FETCH_MAX_BACKOFF))
+    })
+}
+
+/// The file key of a complete public file link.
+fn file_link_key(url: &str) -> Option<Vec<u8>> {
+    use base64::Engine;
+
+    let key = match url.split_once("#!") {
+        Some((_, legacy)) => legacy.split('!').nth(1)?,
+        None => url.split_once("/file/")?.1.split_once('#')?.1,
+    };
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(key.trim_end_matches('='))
+        .ok()
+        .filter(|key| key.len() == 32)
+}
+
+/// Download a public file link with retries, then check it against its MAC.
+async fn fetch_public(
+    url: &str,
+    local_path: std::path::PathBuf,
+    retries: u32,
+    verify: bool,
+    ignore_space_check: bool,
+) -> PyResult<MegaDownloadResult> {
+    let key = file_link_key(url).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "fetch_public_file() needs a file link with its key; use \
+             download_public_file() for files inside folder links",
+        )
+    })?;
+
+    let mut attempt = 0;
+    let info = loop {
+        match api(::megalib::get_public_file_info(url)).await {
+            Ok(info) => break info,
+            Err(e) => match fetch_retry_delay(&e, attempt).filter(|_| attempt < retries) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(mega_err::<pyo3::exceptions::PyValueError>(e)),
+            },
+        }
+        attempt += 1;
+    };
+    if !ignore_space_check {
+        ensure_disk_space(&local_path, info.size)?;
+    }
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut writer = download_writer(&local_path, None)?;
+        let outcome = api(::megalib::download_public_file(url, &mut writer)).await;
+        let outcome = match outcome {
+            // A full disk is reported by finish() below.
+            Err(e) if !writer.disk_full => {
+                match fetch_retry_delay(&e, attempts - 1).filter(|_| attempts <= retries) {
+                    Some(delay) => {
+                        tokio::time::sleep(delay).await;
+                        continue;
+                    }
+                    None => {
+                        writer.partial.keep();
+                        return Err(mega_err::<pyo3::exceptions::PyRuntimeError>(e));
+                    }
+                }
+            }
+            outcome => outcome,
+        };
+        let mut result = writer.finish(outcome, local_path.clone())?;
+        result.attempts = attempts;
+        if !verify {
+            return Ok(result);
+        }
+
+        let path = local_path.clone();
+        let key = key.clone();
+        let matches = tokio::task::spawn_blocking(move || file_mac_matches(&path, &key, None))
+            .await
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))??;
+        if matches {
+            result.verified = true;
+            return Ok(result);
+        }
+        if attempts > retries {
+            return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                "{} doesn't match the file MAC after {} attempts",
+                local_path.display(),
+                attempts
+            )));
+        }
+    }
+}
 
-    /// Delete a file or folder.
-    ///
-    /// Args:
-    ///     path: Path to the item to delete
-    fn rm<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .rm(&path)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
-        })
+/// Download a public file link, retrying what can be retried.
+///
+/// Runs the whole pipeline of get_public_file_info(), download and a check
+/// of the written file against its MAC. Congestion (-3), rate limiting (-4)
+/// and network errors are retried with exponential backoff of up to a
+/// minute; taken-down links, wrong keys, missing files, quota errors and a
+/// full disk fail immediately. A file that doesn't match its MAC is
+/// downloaded again. Safe to run concurrently for different URLs.
+///
+/// Args:
+///     url: MEGA public file link
+///     local_path: Destination path on local disk
+///     key: Decryption key, for links that were shared without one
+///     password: Password of a password-protected ("#P!") link
+///     retries: How many times each stage may be retried
+///     verify: Check the written file against the MAC in the file key
+///     ignore_space_check: Skip checking the free disk space first
+///
+/// Returns:
+///     MegaDownloadResult; attempts tells how many transfers were started
+///     and verified whether the MAC check passed
+///
+/// Raises:
+///     LinkTakenDownError, NotFoundError, MissingKeyError, DiskFullError:
+///         Without retrying
+#[pyfunction]
+#[pyo3(signature = (
+    url,
+    local_path,
+    *,
+    key = None,
+    password = None,
+    retries = 5,
+    verify = true,
+    ignore_space_check = false
+))]
+#[allow(clippy::too_many_arguments)]
+fn fetch_public_file(
+    py: Python<'_>,
+    url: String,
+    local_path: LocalPath,
+    key: Option<String>,
+    password: Option<String>,
+    retries: u32,
+    verify: bool,
+    ignore_space_check: bool,
+) -> PyResult<&PyAny> {
+    let local_path = local_path.0;
+    future_into_py(py, async move {
+        let url = complete_link_async(url, key, password).await?;
+        fetch_public(&url, local_path, retries, verify, ignore_space_check).await
+    })
+}
+
+/// Kind of node a public handle refers to, for link_from_handle().
+#[derive(Clone, Copy)]
+enum LinkKind {
+    File,
+    Folder,
+}
+
+/// Build a public link from a raw handle and key.
+///
+/// The key is checked to decode to the right length (32 bytes for files,
+/// 16 for folders) so a bad key fails here instead of at the server.
+fn link_from_handle(kind: LinkKind, handle: &str, key: &str) -> PyResult<String> {
+    use base64::Engine;
+
+    let (path, key_len) = match kind {
+        LinkKind::File => ("file", 32),
+        LinkKind::Folder => ("folder", 16),
+    };
+    let invalid = |msg: String| PyErr::new::<pyo3::exceptions::PyValueError, _>(msg);
+    if handle.is_empty()
+        || !handle
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_".contains(c))
+    {
+        return Err(invalid(format!("Invalid public handle {:?}", handle)));
     }
+    let key = key.trim_end_matches('=');
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(key)
+        .map_err(|e| invalid(format!("Invalid {} key: {}", path, e)))?;
+    if decoded.len() != key_len {
+        return Err(invalid(format!(
+            "A {} key must be {} bytes, got {}",
+            path,
+            key_len,
+            decoded.len()
+        )));
+    }
+    Ok(format!("https://mega.nz/{}/{}#{}", path, handle, key))
+}
 
-    /// Create a public download link for a file.
-    ///
-    /// Args:
-    ///     path: Path to the file to export
-    ///
-    /// Returns:
-    ///     Public URL string
-    fn export<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            let url = session
-                .export(&path)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(url)
-        })
+/// Parsed MEGA folder link, optionally pointing at a node inside the folder.
+struct FolderLink {
+    handle: String,
+    key: String,
+    node: Option<String>,
+}
+
+impl FolderLink {
+    /// Parse "https://mega.nz/folder/H#K[/file/N]" or the legacy
+    /// "https://mega.nz/#F!H!K[!N]" form. Returns None for other links.
+    fn parse(url: &str) -> Option<Self> {
+        if let Some((_, rest)) = url.split_once("/folder/") {
+            let (handle, fragment) = rest.split_once('#')?;
+            let (key, node) = match fragment.split_once("/file/") {
+                Some((key, node)) => (key, Some(node)),
+                None => match fragment.split_once("/folder/") {
+                    Some((key, node)) => (key, Some(node)),
+                    None => (fragment, None),
+                },
+            };
+            return FolderLink::new(handle, key, node);
+        }
+        let (_, legacy) = url.split_once("#F!")?;
+        let mut parts = legacy.split(['!', '?']);
+        let handle = parts.next()?;
+        let key = parts.next()?;
+        FolderLink::new(handle, key, parts.next())
     }
 
-    /// Upload a file to MEGA.
-    ///
-    /// Args:
-    ///     local_path: Path to local file
-    ///     remote_path: Destination folder on MEGA
-    fn upload<'p>(
-        &self,
-        _py: Python<'p>,
-        local_path: String,
-        remote_path: String,
-    ) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(_py, async move {
-            let mut session = inner.lock().await;
-            session
-                .upload(local_path, &remote_path)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok("Upload complete")
+    fn new(handle: &str, key: &str, node: Option<&str>) -> Option<Self> {
+        if handle.is_empty() || key.is_empty() {
+            return None;
+        }
+        Some(FolderLink {
+            handle: handle.to_string(),
+            key: key.to_string(),
+            node: node.filter(|n| !n.is_empty()).map(str::to_string),
         })
     }
 
-    /// Download a file from MEGA.
-    ///
-    /// Args:
-    ///     remote_path: Path to file on MEGA
-    ///     local_path: Destination path on local disk
-    fn download<'p>(
-        &self,
-        _py: Python<'p>,
-        remote_path: String,
-        local_path: String,
-    ) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(_py, async move {
-            let mut session = inner.lock().await;
-            let node = session.stat(&remote_path).cloned();
+    /// Link to the folder itself, in the current format.
+    fn folder_url(&self) -> String {
+        format!("https://mega.nz/folder/{}#{}", self.handle, self.key)
+    }
 
-            if let Some(node) = node {
-                let file = std::fs::File::create(&local_path)
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
-                let mut writer = std::io::BufWriter::new(file);
+    /// Link to a single file inside the folder.
+    fn file_url(&self, node_handle: &str) -> String {
+        format!("{}/file/{}", self.folder_url(), node_handle)
+    }
+}
 
-                session.download(&node, &mut writer).await.map_err(|e| {
-                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
-                })?;
-                Ok("Download complete")
-            } else {
-                Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
-                    "File not found on Mega",
-                ))
-            }
-        })
+/// Fill in the decryption key of a public link.
+///
+/// Password-protected ("#P!") links are decrypted with `password`; links
+/// without a key fragment get `key` appended. Links that already carry a key
+/// are returned unchanged and `key` is ignored.
+fn complete_link(url: &str, key: Option<&str>, password: Option<&str>) -> PyResult<String> {
+    if let Some((_, data)) = url.split_once("#P!") {
+        let password = password.ok_or_else(|| {
+            MissingKeyError::new_err("the link is password-protected; pass password=")
+        })?;
+        return decrypt_protected_link(data, password);
     }
+    let missing = || {
+        MissingKeyError::new_err(
+            "the link has no decryption key; pass key= with the key shared alongside it",
+        )
+    };
 
-    /// Get the user's email address.
-    ///
-    /// Returns:
-    ///     User's email address as a string
-    fn get_email<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            Ok(session.email.clone())
-        })
+    // Legacy "#!HANDLE!KEY" and "#F!HANDLE!KEY[!NODE]" links.
+    for marker in ["#F!", "#!"] {
+        if let Some((prefix, rest)) = url.split_once(marker) {
+            let mut parts = rest.splitn(3, '!');
+            let handle = parts.next().unwrap_or_default();
+            return match parts.next().filter(|k| !k.is_empty()) {
+                Some(_) => Ok(url.to_string()),
+                None => {
+                    let key = key.ok_or_else(missing)?;
+                    let node = parts.next().map(|n| format!("!{}", n)).unwrap_or_default();
+                    Ok(format!("{}{}{}!{}{}", prefix, marker, handle, key, node))
+                }
+            };
+        }
     }
 
-    /// Get the user's display name.
-    ///
-    /// Returns:
-    ///     User's display name as a string
-    fn get_name<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            Ok(session.name.clone())
-        })
+    // "https://mega.nz/{file,folder}/HANDLE[#KEY][/file/NODE]" links.
+    match url.split_once('#') {
+        Some((_, fragment)) if !fragment.is_empty() && !fragment.starts_with('/') => {
+            Ok(url.to_string())
+        }
+        _ => {
+            let key = key.ok_or_else(missing)?;
+            let url = url.trim_end_matches('#');
+            let kind = ["/folder/", "/file/"]
+                .into_iter()
+                .find_map(|kind| url.split_once(kind).map(|parts| (kind, parts)));
+            let (kind, (prefix, rest)) = kind.ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>("Invalid MEGA link")
+            })?;
+            let rest = rest.replacen('#', "", 1);
+            let (handle, node) = match rest.split_once('/') {
+                Some((handle, node)) => (handle, format!("/{}", node)),
+                None => (rest.as_str(), String::new()),
+            };
+            Ok(format!("{}{}{}#{}{}", prefix, kind, handle, key, node))
+        }
     }
+}
 
-    /// Get the user's MEGA handle (unique ID).
-    ///
-    /// Returns:
-    ///     User's MEGA handle as a string
-    fn get_handle<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            Ok(session.user_handle.clone())
-        })
+/// complete_link() on a blocking thread, since decrypting a "#P!" link
+/// takes 100,000 rounds of PBKDF2.
+async fn complete_link_async(
+    url: String,
+    key: Option<String>,
+    password: Option<String>,
+) -> PyResult<String> {
+    tokio::task::spawn_blocking(move || complete_link(&url, key.as_deref(), password.as_deref()))
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+}
+
+/// Decrypt the payload of a "#P!" link into a regular link.
+///
+/// The payload is base64url(algorithm || type || handle || salt || key ||
+/// mac). The password is stretched with PBKDF2-HMAC-SHA512; the first half
+/// of the result XORs the key and the second half keys the HMAC-SHA256 over
+/// everything before the mac. Algorithm 1 passed the HMAC arguments the
+/// other way round.
+fn decrypt_protected_link(data: &str, password: &str) -> PyResult<String> {
+    use base64::Engine;
+    use hmac::Mac;
+
+    let invalid = || PyErr::new::<pyo3::exceptions::PyValueError, _>("Invalid protected link");
+    let b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let raw = b64
+        .decode(data.trim_end_matches('='))
+        .map_err(|_| invalid())?;
+
+    let (algorithm, kind) = match raw.as_slice() {
+        [algorithm @ (1 | 2), kind @ (0 | 1), ..] => (*algorithm, *kind),
+        _ => return Err(invalid()),
+    };
+    // Folder links carry a 16 byte key, file links a 32 byte one.
+    let key_len = if kind == 0 { 16 } else { 32 };
+    if raw.len() != 2 + 6 + 32 + key_len + 32 {
+        return Err(invalid());
     }
+    let (signed, mac) = raw.split_at(raw.len() - 32);
+    let handle = &signed[2..8];
+    let salt = &signed[8..40];
+    let encrypted_key = &signed[40..];
 
-    /// Set number of parallel transfer workers.
-    ///
-    /// Higher values speed up large file transfers.
-    ///
-    /// Args:
-    ///     workers: Number of parallel transfer workers
-    fn set_workers<'p>(&self, py: Python<'p>, workers: usize) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session.set_workers(workers);
-            Ok(())
-        })
+    let derived = protected_link_keys(password, salt);
+    let (xor_key, mac_key) = derived.split_at(32);
+
+    let (mac_key, message) = match algorithm {
+        1 => (signed, mac_key),
+        _ => (mac_key, signed),
+    };
+    let mut hmac = hmac::Hmac::<sha2::Sha256>::new_from_slice(mac_key).map_err(|_| invalid())?;
+    hmac.update(message);
+    hmac.verify_slice(mac).map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>("Wrong password for protected link")
+    })?;
+
+    let key: Vec<u8> = encrypted_key
+        .iter()
+        .zip(xor_key)
+        .map(|(k, x)| k ^ x)
+        .collect();
+    let kind = if kind == 0 { "folder" } else { "file" };
+    Ok(format!(
+        "https://mega.nz/{}/{}#{}",
+        kind,
+        b64.encode(handle),
+        b64.encode(key)
+    ))
+}
+
+/// Stretch the password of a "#P!" link into its XOR and HMAC keys.
+fn protected_link_keys(password: &str, salt: &[u8]) -> [u8; 64] {
+    let mut derived = [0u8; 64];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha512>(password.as_bytes(), salt, 100_000, &mut derived);
+    derived
+}
+
+/// Turn a file link into a password-protected ("#P!") link.
+///
+/// The inverse of decrypt_protected_link(), always with algorithm 2 and a
+/// fresh random salt, as the official clients create them.
+fn protect_file_link(url: &str, password: &str) -> PyResult<String> {
+    use base64::Engine;
+    use hmac::Mac;
+
+    let invalid = || PyErr::new::<pyo3::exceptions::PyValueError, _>("Invalid file link");
+    let b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let handle = match url.split_once("#!") {
+        Some((_, legacy)) => legacy.split('!').next(),
+        None => url
+            .split_once("/file/")
+            .and_then(|(_, rest)| rest.split_once('#'))
+            .map(|(handle, _)| handle),
     }
+    .and_then(|handle| b64.decode(handle).ok())
+    .filter(|handle| handle.len() == 6)
+    .ok_or_else(invalid)?;
+    let key = file_link_key(url).ok_or_else(invalid)?;
 
-    /// Enable/disable resume for interrupted downloads.
-    ///
-    /// Args:
-    ///     enabled: True to enable, False to disable
-    fn set_resume<'p>(&self, py: Python<'p>, enabled: bool) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session.set_resume(enabled);
-            Ok(())
-        })
+    let mut salt = [0u8; 32];
+    getrandom::getrandom(&mut salt)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+    let derived = protected_link_keys(password, &salt);
+    let (xor_key, mac_key) = derived.split_at(32);
+
+    let mut payload = vec![2, 1];
+    payload.extend_from_slice(&handle);
+    payload.extend_from_slice(&salt);
+    payload.extend(key.iter().zip(xor_key).map(|(k, x)| k ^ x));
+    let mut hmac =
+        hmac::Hmac::<sha2::Sha256>::new_from_slice(mac_key).expect("HMAC takes keys of any length");
+    hmac.update(&payload);
+    payload.extend_from_slice(&hmac.finalize().into_bytes());
+    Ok(format!("https://mega.nz/#P!{}", b64.encode(payload)))
+}
+
+/// Look up a node in a public folder by path (relative to the folder root) or handle.
+fn resolve_public_node<'a>(
+    folder: &'a ::megalib::public::PublicFolder,
+    path_or_handle: &str,
+) -> Option<&'a Node> {
+    if path_or_handle.starts_with('/') {
+        folder.stat(path_or_handle)
+    } else {
+        folder
+            .list("/", true)
+            .into_iter()
+            .find(|n| n.handle == path_or_handle)
     }
+}
 
-    /// Enable/disable thumbnail generation on upload.
-    ///
-    /// Args:
-    ///     enabled: True to enable, False to disable
-    fn enable_previews<'p>(&self, py: Python<'p>, enabled: bool) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session.enable_previews(enabled);
-            Ok(())
-        })
+/// Version of the manifest format written by write_manifest().
+const MANIFEST_VERSION: u32 = 1;
+
+/// Write a JSON manifest of the tree below `root`, one node at a time.
+///
+/// The format is {"version": 1, "nodes": [...]} with one object per node
+/// holding path, handle, type ("file" or "folder"), size, timestamp and
+/// modified. Nodes are in depth-first order with siblings sorted by name
+/// and handle, so the same tree always gives the same bytes.
+fn write_manifest<'a, W: std::io::Write>(
+    out: &mut W,
+    root: &str,
+    list: impl Fn(&str) -> Vec<&'a Node>,
+) -> std::io::Result<()> {
+    let children = |path: &str| {
+        let mut nodes: Vec<_> = list(path)
+            .into_iter()
+            .map(|node| {
+                (
+                    format!("{}/{}", path.trim_end_matches('/'), node.name),
+                    node,
+                )
+            })
+            .collect();
+        nodes.sort_by(|(_, a), (_, b)| (&b.name, &b.handle).cmp(&(&a.name, &a.handle)));
+        nodes
+    };
+
+    write!(out, "{{\"version\":{},\"nodes\":[", MANIFEST_VERSION)?;
+    let mut stack = children(root);
+    let mut first = true;
+    while let Some((path, node)) = stack.pop() {
+        if !first {
+            out.write_all(b",")?;
+        }
+        first = false;
+        let node_type = if node.node_type.is_container() {
+            "folder"
+        } else {
+            "file"
+        };
+        let mega_node = MegaNode::from(node);
+        let entry = serde_json::json!({
+            "path": path,
+            "handle": node.handle,
+            "type": node_type,
+            "size": node.size,
+            "timestamp": node.timestamp,
+            "modified": mega_node.modified_ts,
+        });
+        serde_json::to_writer(&mut *out, &entry)?;
+        if node.node_type.is_container() {
+            stack.extend(children(&path));
+        }
     }
+    out.write_all(b"]}")
+}
 
-    /// Share a folder with another user.
+/// Build the manifest of a public folder, into `local_path` or a string.
+///
+/// Runs on a blocking thread since large folders take a while to serialize.
+async fn public_manifest(
+    folder: Arc<::megalib::public::PublicFolder>,
+    local_path: Option<std::path::PathBuf>,
+) -> PyResult<Option<String>> {
+    tokio::task::spawn_blocking(move || {
+        let list = |path: &str| folder.list(path, false);
+        match local_path {
+            Some(local_path) => {
+                let mut out = std::io::BufWriter::new(std::fs::File::create(local_path)?);
+                write_manifest(&mut out, "/", list)?;
+                std::io::Write::flush(&mut out)?;
+                Ok(None)
+            }
+            None => {
+                let mut out = Vec::new();
+                write_manifest(&mut out, "/", list)?;
+                Ok(Some(
+                    String::from_utf8(out).expect("serde_json writes valid UTF-8"),
+                ))
+            }
+        }
+    })
+    .await
+    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?
+    .map_err(|e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))
+}
+
+/// A public folder for browsing shared folders without login.
+///
+/// Created via open_folder(). Use list() to browse, download() to get files.
+#[pyclass]
+struct MegaPublicFolder {
+    inner: Arc<::megalib::public::PublicFolder>,
+    link: FolderLink,
+}
+
+#[pymethods]
+impl MegaPublicFolder {
+    /// Build a direct link to a single file inside this folder.
+    ///
+    /// The link has the form "https://mega.nz/folder/HANDLE#KEY/file/NODE"
+    /// and works with open_folder() and download_public_file().
     ///
     /// Args:
-    ///     path: Path to folder to share
-    ///     email: Email of user to share with
-    ///     access_level: 0=read, 1=write, 2=full
-    fn share_folder<'p>(
-        &self,
-        py: Python<'p>,
-        path: String,
-        email: String,
-        access_level: i32,
-    ) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .share_folder(&path, &email, access_level)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
-        })
+    ///     path_or_handle: Path within the folder or handle of the node
+    ///
+    /// Returns:
+    ///     Public URL string
+    fn link_for(&self, path_or_handle: String) -> PyResult<String> {
+        match resolve_public_node(&self.inner, &path_or_handle) {
+            Some(node) => Ok(self.link.file_url(&node.handle)),
+            None => Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                "File not found in public folder",
+            )),
+        }
     }
 
-    /// List all contacts (users you've shared with).
+    /// Get information about a file or folder in the public folder.
+    ///
+    /// Args:
+    ///     path: Path relative to the folder root (e.g., "/Photos/a.jpg";
+    ///         "/" is the shared folder itself)
     ///
     /// Returns:
-    ///     List of MegaNode objects representing contacts
-    fn list_contacts<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+    ///     MegaNode if found, None otherwise
+    fn stat<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
         let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            let contacts = session.list_contacts();
-            let py_contacts: Vec<MegaNode> = contacts.iter().map(|n| MegaNode::from(*n)).collect();
-            Ok(py_contacts)
+        future_into_py(py, async move {
+            Ok(inner.stat(&path).map(|n| {
+                let mut node = MegaNode::from(n);
+                let trimmed = path.trim_end_matches('/');
+                node.location =
+                    NodeLocation::At(if trimmed.is_empty() { "/" } else { trimmed }.into());
+                node
+            }))
         })
     }
 
-    /// Save session to file for later restoration.
-    ///
-    /// The saved file contains encrypted credentials - keep it secure!
+    /// List files in a path within the public folder.
     ///
     /// Args:
-    ///     path: Path to save session file
-    fn save<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
+    ///     path: Folder path relative to the folder root ("/" for the root)
+    ///     recursive: If True, list all descendants, not just the children
+    #[pyo3(signature = (path, recursive = false))]
+    fn list<'p>(&self, py: Python<'p>, path: String, recursive: bool) -> PyResult<&'p PyAny> {
         let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let session = inner.lock().await;
-            session
-                .save(&path)
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
-            Ok(())
+        future_into_py(py, async move {
+            let nodes = inner.list(&path, recursive);
+            let locations = if recursive {
+                let dir = inner.stat(&path).map(|n| n.handle.as_str());
+                descendant_paths(&path, dir, &nodes, &nodes)
+            } else {
+                let dir: Arc<str> = Arc::from(path.as_str());
+                vec![NodeLocation::In(dir); nodes.len()]
+            };
+            let mut strings = Interner::default();
+            let py_nodes: Vec<MegaNode> = nodes
+                .iter()
+                .zip(locations)
+                .map(|(n, location)| MegaNode::snapshot(n, location, &mut strings))
+                .collect();
+            nodes_into_py(py_nodes)
         })
     }
 
-    /// Change the user's password.
+    /// List only the direct child folders of a path within the public folder.
     ///
-    /// Args:
-    ///     new_password: New password for the account
-    fn change_password<'p>(&self, py: Python<'p>, new_password: String) -> PyResult<&'p PyAny> {
+    /// Works like MegaSession.list_folders().
+    ///
+    /// Returns:
+    ///     List of (MegaNode, child_count) tuples sorted by name
+    fn list_folders<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
         let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .change_password(&new_password)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok(())
+        future_into_py(py, async move {
+            let folders = child_folders(&path, inner.list(&path, false), |child| {
+                inner.list(child, false).len()
+            });
+            folders_into_py(folders)
         })
     }
 
-    /// Download a file to a specific file path.
+    /// Export the metadata of every node in the folder as JSON.
+    ///
+    /// Nothing is downloaded. The manifest lists path, handle, type, size,
+    /// timestamp and modified time of each node in a deterministic order.
     ///
     /// Args:
-    ///     remote_path: Path to file on MEGA
-    ///     local_path: Destination path on local disk
-    fn download_to_file<'p>(
+    ///     local_path: File to stream the manifest into, recommended for
+    ///         large folders. If None, the manifest is returned as a string.
+    ///
+    /// Returns:
+    ///     The manifest string, or None if it was written to local_path
+    #[pyo3(signature = (local_path = None))]
+    fn export_tree_json<'p>(
         &self,
         py: Python<'p>,
-        remote_path: String,
-        local_path: String,
+        local_path: Option<LocalPath>,
     ) -> PyResult<&'p PyAny> {
+        let local_path = local_path.map(|path| path.0);
         let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            let node = session.stat(&remote_path).cloned();
-
-            if let Some(node) = node {
-                session
-                    .download_to_file(&node, &local_path)
-                    .await
-                    .map_err(|e| {
-                        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
-                    })?;
-                Ok("Download complete")
-            } else {
-                Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
-                    "File not found on Mega",
-                ))
-            }
-        })
+        future_into_py(py, async move { public_manifest(inner, local_path).await })
     }
 
-    /// Upload a file resumable.
+    /// Download a file from the public folder.
     ///
-    /// Args:
-    ///     local_path: Path to local file
-    ///     remote_path: Destination folder on MEGA
-    fn upload_resumable<'p>(
+    /// `digest`, `ignore_space_check` and `progress` work as in
+    /// MegaSession.download().
+    #[pyo3(signature = (
+        remote_path,
+        local_path,
+        digest = None,
+        ignore_space_check = false,
+        progress = None
+    ))]
+    fn download<'p>(
         &self,
-        py: Python<'p>,
-        local_path: String,
+        _py: Python<'p>,
         remote_path: String,
+        local_path: LocalPath,
+        digest: Option<String>,
+        ignore_space_check: bool,
+        progress: Option<PyObject>,
     ) -> PyResult<&'p PyAny> {
+        let local_path = local_path.0;
         let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut session = inner.lock().await;
-            session
-                .upload_resumable(&local_path, &remote_path)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-            Ok("Upload complete")
-        })
-    }
+        future_into_py(_py, async move {
+            let node = inner.stat(&remote_path).cloned();
 
-    /// Load a saved session from a file.
-    ///
-    /// Args:
-    ///     path: Path to saved session file
-    ///
-    /// Returns:
-    ///     MegaSession if loaded, None if file not found
-    #[staticmethod]
-    fn load(py: Python<'_>, path: String) -> PyResult<&PyAny> {
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            match Session::load(&path)
-                .await
-                .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?
-            {
-                Some(session) => Ok(Some(MegaSession {
-                    inner: Arc::new(Mutex::new(session)),
-                })),
-                None => Ok(None),
+            if let Some(node) = node {
+                ensure_available(&node)?;
+                if !ignore_space_check {
+                    ensure_disk_space(&local_path, node.size)?;
+                }
+                let mut writer = download_writer(&local_path, digest.as_deref())?;
+
+                let outcome = with_progress(
+                    api(inner.download(&node, &mut writer)),
+                    progress.as_ref(),
+                    node.size,
+                    || file_len(&local_path),
+                )
+                .await?;
+                writer.finish(outcome, local_path)
+            } else {
+                Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                    "File not found in public folder",
+                ))
             }
         })
     }
 }
 
-/// Start the registration process for a new MEGA account.
+/// Open a public folder from a MEGA folder link.
+///
+/// Links to a file inside a folder (".../folder/HANDLE#KEY/file/NODE") open
+/// the containing folder.
 ///
 /// Args:
-///     email: Email address for the new account
-///     password: Password for the new account
-///     name: Display name for the account
+///     url: MEGA folder link (e.g., "https://mega.nz/folder/...")
+///     key: Decryption key, for links that were shared without one
+///     password: Password of a password-protected ("#P!") link
 ///
 /// Returns:
-///     MegaRegistrationState to save and use with verify_registration()
+///     MegaPublicFolder for browsing and downloading
+///
+/// Raises:
+///     MissingKeyError: If the link has no key and `key` wasn't given, or is
+///         password-protected and `password` wasn't given
 #[pyfunction]
-fn register(py: Python<'_>, email: String, password: String, name: String) -> PyResult<&PyAny> {
-    pyo3_asyncio::tokio::future_into_py(py, async move {
-        let state = ::megalib::register(&email, &password, &name)
-            .await
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
-        Ok(MegaRegistrationState { inner: state })
+#[pyo3(signature = (url, key = None, password = None))]
+fn open_folder(
+    py: Python<'_>,
+    url: String,
+    key: Option<String>,
+    password: Option<String>,
+) -> PyResult<&PyAny> {
+    future_into_py(py, async move {
+        let url = complete_link_async(url, key, password).await?;
+        open_public_folder(&url).await
     })
 }
 
-/// Complete registration using the signup key from email.
+/// Open a public folder from its handle and key.
+///
+/// Same as open_folder() for applications that store handles and keys
+/// instead of links.
 ///
 /// Args:
-///     state: MegaRegistrationState from register()
-///     signup_key: The confirmation key from the verification email
+///     handle: Public handle of the folder
+///     key: Base64url-encoded folder key (16 bytes)
+///
+/// Returns:
+///     MegaPublicFolder for browsing and downloading
+///
+/// Raises:
+///     ValueError: If the key doesn't decode to 16 bytes; raised before any
+///         request is sent
 #[pyfunction]
-fn verify_registration<'p>(
-    py: Python<'p>,
-    state: &MegaRegistrationState,
-    signup_key: String,
-) -> PyResult<&'p PyAny> {
-    let state_inner = state.inner.clone();
-    pyo3_asyncio::tokio::future_into_py(py, async move {
-        ::megalib::verify_registration(&state_inner, &signup_key)
-            .await
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
-        Ok(())
+fn open_folder_by_handle(py: Python<'_>, handle: String, key: String) -> PyResult<&PyAny> {
+    let url = link_from_handle(LinkKind::Folder, &handle, &key)?;
+    future_into_py(py, async move { open_public_folder(&url).await })
+}
+
+async fn open_public_folder(url: &str) -> PyResult<MegaPublicFolder> {
+    let link = FolderLink::parse(url).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>("Invalid MEGA folder link")
+    })?;
+    let folder = api(::megalib::public::open_folder(&link.folder_url()))
+        .await
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+    Ok(MegaPublicFolder {
+        inner: Arc::new(folder),
+        link,
     })
 }
 
-/// Get info about a public file without downloading.
+/// Build the JSON manifest of a public folder link in one call.
+///
+/// Same as open_folder(url).export_tree_json(local_path), without keeping
+/// the folder around.
 ///
 /// Args:
-///     url: MEGA public link (e.g., "https://mega.nz/file/...")
+///     url: MEGA folder link
+///     local_path: File to stream the manifest into; None returns it
+///     key: Decryption key, for links that were shared without one
+///     password: Password of a password-protected ("#P!") link
 ///
 /// Returns:
-///     MegaPublicFile with name, size, and handle
+///     The manifest string, or None if it was written to local_path
 #[pyfunction]
-fn get_public_file_info(py: Python<'_>, url: String) -> PyResult<&PyAny> {
-    pyo3_asyncio::tokio::future_into_py(py, async move {
-        let info = ::megalib::get_public_file_info(&url)
-            .await
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
-        Ok(MegaPublicFile {
-            name: info.name,
-            size: info.size,
-            handle: info.handle,
-        })
+#[pyo3(signature = (url, local_path = None, key = None, password = None))]
+fn public_folder_manifest(
+    py: Python<'_>,
+    url: String,
+    local_path: Option<LocalPath>,
+    key: Option<String>,
+    password: Option<String>,
+) -> PyResult<&PyAny> {
+    let local_path = local_path.map(|path| path.0);
+    future_into_py(py, async move {
+        let url = complete_link_async(url, key, password).await?;
+        let folder = open_public_folder(&url).await?;
+        public_manifest(folder.inner, local_path).await
     })
 }
 
-/// Download a file from a public MEGA link.
+/// Remove scratch directories left behind by crashed processes.
+///
+/// Only directories with megalib's prefix that haven't been modified for
+/// `max_age` seconds are removed, so transfers running in other processes
+/// are left alone.
 ///
 /// Args:
-///     url: MEGA public link
-///     local_path: Destination path on local disk
+///     path: Directory to sweep, None for the system temp directory (use
+///         the path given to set_temp_dir() if one was set)
+///     max_age: Minimum age in seconds of a directory to remove
+///
+/// Returns:
+///     Number of directories removed
 #[pyfunction]
-fn download_public_file(py: Python<'_>, url: String, local_path: String) -> PyResult<&PyAny> {
-    pyo3_asyncio::tokio::future_into_py(py, async move {
-        let file = std::fs::File::create(&local_path)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
-        let mut writer = std::io::BufWriter::new(file);
+#[pyo3(signature = (path = None, max_age = 86400.0))]
+fn cleanup_temp(path: Option<LocalPath>, max_age: f64) -> PyResult<usize> {
+    let path = path.map(|path| path.0);
+    let io_err = |e: std::io::Error| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string());
+    let max_age = std::time::Duration::try_from_secs_f64(max_age).map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>("max_age must be non-negative")
+    })?;
+    let base = path.unwrap_or_else(std::env::temp_dir);
 
-        ::megalib::download_public_file(&url, &mut writer)
-            .await
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-        Ok("Download complete")
-    })
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&base).map_err(io_err)? {
+        let entry = entry.map_err(io_err)?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(SPILL_PREFIX)
+        {
+            continue;
+        }
+        let stale = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age >= max_age);
+        if stale && std::fs::remove_dir_all(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
 }
 
-/// A public folder for browsing shared folders without login.
+/// Configure the thread pools of the runtime that runs all operations.
 ///
-/// Created via open_folder(). Use list() to browse, download() to get files.
-#[pyclass]
-struct MegaPublicFolder {
-    inner: Arc<::megalib::public::PublicFolder>,
+/// Must be called before the first async operation; the runtime is created
+/// then and can't be changed afterwards. Arguments left as None keep
+/// tokio's defaults (one worker per CPU core, up to 512 blocking threads).
+///
+/// Args:
+///     worker_threads: Number of threads running async tasks, e.g. 1 in
+///         prefork servers with many worker processes
+///     max_blocking_threads: Limit of threads for blocking file I/O
+///
+/// Raises:
+///     RuntimeError: If an async operation has already been started
+#[pyfunction]
+#[pyo3(signature = (worker_threads = None, max_blocking_threads = None))]
+fn configure_runtime(
+    worker_threads: Option<usize>,
+    max_blocking_threads: Option<usize>,
+) -> PyResult<()> {
+    if worker_threads == Some(0) || max_blocking_threads == Some(0) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "thread counts must be positive",
+        ));
+    }
+    let started = RUNTIME_STARTED.lock().unwrap();
+    if *started {
+        return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "configure_runtime() must be called before the first async operation",
+        ));
+    }
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(n) = worker_threads {
+        builder.worker_threads(n);
+    }
+    if let Some(n) = max_blocking_threads {
+        builder.max_blocking_threads(n);
+    }
+    pyo3_asyncio::tokio::init(builder);
+    Ok(())
 }
 
-#[pymethods]
-impl MegaPublicFolder {
-    /// List files in a path within the public folder.
-    fn list<'p>(&self, py: Python<'p>, path: String) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let nodes = inner.list(&path, false);
-            let py_nodes: Vec<MegaNode> = nodes.iter().map(|n| MegaNode::from(*n)).collect();
-            Ok(py_nodes)
-        })
+/// Set how many threads check downloaded files against their MAC.
+///
+/// Used by download_to_file(resume_verify=True) and fetch_public_file().
+/// Chunks are hashed in parallel, which matters for files of many GB on
+/// fast disks; on spinning disks a single thread may be faster.
+///
+/// Args:
+///     threads: Number of threads, or None for one per CPU core
+#[pyfunction]
+#[pyo3(signature = (threads = None))]
+fn set_verify_threads(threads: Option<usize>) -> PyResult<()> {
+    if threads == Some(0) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "threads must be at least 1",
+        ));
     }
+    VERIFY_THREADS.store(threads.unwrap_or(0), Ordering::Relaxed);
+    Ok(())
+}
 
-    /// Download a file from the public folder.
-    fn download<'p>(
-        &self,
-        _py: Python<'p>,
-        remote_path: String,
-        local_path: String,
-    ) -> PyResult<&'p PyAny> {
-        let inner = self.inner.clone();
-        pyo3_asyncio::tokio::future_into_py(_py, async move {
-            let node = inner.stat(&remote_path).cloned();
+/// Limit the rate of MEGA API requests across all sessions in this process.
+///
+/// Applies to API commands (login, refresh, mkdir, ...), not to the data
+/// transferred to and from storage servers.
+///
+/// Args:
+///     requests_per_second: Sustained request rate, or None to remove the limit
+///     burst: Number of requests that may be sent back-to-back
+#[pyfunction]
+#[pyo3(signature = (requests_per_second, burst = 1))]
+fn set_api_rate_limit(requests_per_second: Option<f64>, burst: u32) -> PyResult<()> {
+    let bucket = match requests_per_second {
+        None => None,
+        Some(rate) if rate > 0.0 && burst > 0 => Some(Bucket {
+            rate,
+            burst: f64::from(burst),
+            tokens: f64::from(burst),
+            last: std::time::Instant::now(),
+        }),
+        Some(_) => {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "requests_per_second and burst must be positive",
+            ))
+        }
+    };
+    *API_LIMIT.lock().unwrap() = bucket;
+    Ok(())
+}
 
-            if let Some(node) = node {
-                let file = std::fs::File::create(&local_path)
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(e.to_string()))?;
-                let mut writer = std::io::BufWriter::new(file);
+/// Get counters of MEGA API requests made by this process.
+///
+/// Returns:
+///     Dict with "requests" (API commands sent), "throttled" (commands
+///     delayed by the rate limit) and "eagain" (commands that failed with
+///     MEGA's -3 "try again" error)
+#[pyfunction]
+fn api_stats() -> std::collections::HashMap<&'static str, u64> {
+    std::collections::HashMap::from([
+        ("requests", API_REQUESTS.load(Ordering::Relaxed)),
+        ("throttled", API_THROTTLED.load(Ordering::Relaxed)),
+        ("eagain", API_EAGAIN.load(Ordering::Relaxed)),
+    ])
+}
 
-                inner.download(&node, &mut writer).await.map_err(|e| {
-                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
-                })?;
-                Ok("Download complete")
-            } else {
-                Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
-                    "File not found in public folder",
-                ))
-            }
-        })
-    }
+/// Capabilities reported by features(), one per optional part of the API.
+///
+/// Generated by build.rs from [package.metadata.megalib.features] in
+/// Cargo.toml, which also lists the symbols check_stubs.py expects for
+/// each; add new capabilities there.
+const FEATURES: &[&str] = include!(concat!(env!("OUT_DIR"), "/features.rs"));
+
+/// Get the version of the megalib crate these bindings were built against.
+///
+/// Returns:
+///     Version string (e.g., "0.3.1"), "unknown" if it couldn't be determined
+///     at build time
+#[pyfunction]
+fn core_version() -> &'static str {
+    env!("MEGALIB_CORE_VERSION")
 }
 
-/// Open a public folder from a MEGA folder link.
+/// Get the optional capabilities compiled into these bindings.
 ///
-/// Args:
-///     url: MEGA folder link (e.g., "https://mega.nz/folder/...")
+/// Lets applications that support several versions of the bindings check
+/// for a capability instead of catching AttributeError or TypeError.
 ///
 /// Returns:
-///     MegaPublicFolder for browsing and downloading
+///     frozenset of feature names (e.g., "atomic-upload", "link-passwords")
 #[pyfunction]
-fn open_folder(py: Python<'_>, url: String) -> PyResult<&PyAny> {
-    pyo3_asyncio::tokio::future_into_py(py, async move {
-        let folder = ::megalib::public::open_folder(&url)
-            .await
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
-        Ok(MegaPublicFolder {
-            inner: Arc::new(folder),
-        })
-    })
+fn features(py: Python<'_>) -> PyResult<&pyo3::types::PyFrozenSet> {
+    pyo3::types::PyFrozenSet::new(py, FEATURES)
 }
 
 #[pymodule]
 #[pyo3(name = "megalib")]
-fn megalib_backend(_py: Python, m: &PyModule) -> PyResult<()> {
+fn megalib_backend(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("MegaError", py.get_type::<MegaError>())?;
+    m.add("AccessDeniedError", py.get_type::<AccessDeniedError>())?;
+    m.add("OverQuotaError", py.get_type::<OverQuotaError>())?;
+    m.add("LinkTakenDownError", py.get_type::<LinkTakenDownError>())?;
+    m.add("NotRefreshedError", py.get_type::<NotRefreshedError>())?;
+    m.add("MissingKeyError", py.get_type::<MissingKeyError>())?;
+    m.add("BusinessPolicyError", py.get_type::<BusinessPolicyError>())?;
+    m.add("DiskFullError", py.get_type::<DiskFullError>())?;
+    m.add("NotFoundError", py.get_type::<NotFoundError>())?;
+    m.add("ReadOnlyError", py.get_type::<ReadOnlyError>())?;
+    m.add("PartialTreeError", py.get_type::<PartialTreeError>())?;
+    m.add("QueueCorruptError", py.get_type::<QueueCorruptError>())?;
     m.add_class::<MegaSession>()?;
     m.add_class::<MegaNode>()?;
     m.add_class::<MegaRegistrationState>()?;
     m.add_class::<MegaPublicFile>()?;
+    m.add_class::<MegaStorageUsage>()?;
+    m.add_class::<MegaShareResult>()?;
+    m.add_class::<MegaContact>()?;
+    m.add_class::<MegaUndecryptableNode>()?;
+    m.add_class::<MegaDownloadResult>()?;
+    m.add_class::<MegaUploadResult>()?;
+    m.add_class::<MegaQuotaInfo>()?;
+    m.add_class::<MegaTransferItem>()?;
+    m.add_class::<MegaTransferSummary>()?;
+    m.add_class::<MegaReadStream>()?;
+    m.add_class::<MegaSessionSync>()?;
+    m.add_class::<MegaExposure>()?;
+    m.add_class::<MegaExposureReport>()?;
+    m.add_class::<MegaQueueEntry>()?;
     m.add_class::<MegaPublicFolder>()?;
     m.add_function(wrap_pyfunction!(register, m)?)?;
     m.add_function(wrap_pyfunction!(verify_registration, m)?)?;
+    m.add_function(wrap_pyfunction!(wait_for_confirmation, m)?)?;
+    m.add_function(wrap_pyfunction!(upload_anonymous, m)?)?;
     m.add_function(wrap_pyfunction!(get_public_file_info, m)?)?;
     m.add_function(wrap_pyfunction!(download_public_file, m)?)?;
+    m.add_function(wrap_pyfunction!(download_public_file_bytes, m)?)?;
     m.add_function(wrap_pyfunction!(open_folder, m)?)?;
+    m.add_function(wrap_pyfunction!(fetch_public_file, m)?)?;
+    m.add_function(wrap_pyfunction!(get_public_file_info_by_handle, m)?)?;
+    m.add_function(wrap_pyfunction!(download_public_file_by_handle, m)?)?;
+    m.add_function(wrap_pyfunction!(open_folder_by_handle, m)?)?;
+    m.add_function(wrap_pyfunction!(public_folder_manifest, m)?)?;
+    m.add_function(wrap_pyfunction!(set_api_rate_limit, m)?)?;
+    m.add_function(wrap_pyfunction!(set_verify_threads, m)?)?;
+    m.add_function(wrap_pyfunction!(api_stats, m)?)?;
+    m.add_function(wrap_pyfunction!(cleanup_temp, m)?)?;
+    m.add_function(wrap_pyfunction!(configure_runtime, m)?)?;
+    m.add_function(wrap_pyfunction!(core_version, m)?)?;
+    m.add_function(wrap_pyfunction!(features, m)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic test data.
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 7 + 3) % 251) as u8).collect()
+    }
+
+    fn crc_hex(len: usize) -> String {
+        let data = sample(len);
+        let crc = fingerprint_crc(&mut std::io::Cursor::new(data), len as u64).unwrap();
+        crc.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Expected values follow the MEGA SDK's FileFingerprint::genfingerprint,
+    // computed with zlib's CRC32.
+    #[test]
+    fn fingerprint_crc_known_values() {
+        assert_eq!(crc_hex(0), "00000000000000000000000000000000");
+        assert_eq!(crc_hex(16), "030a11181f262d343b424950575e656c");
+        assert_eq!(crc_hex(17), "7ed34e6214600cdd3fa05caa299b91b1");
+        assert_eq!(crc_hex(8192), "9608673ec37f45cb57c6cf5dc2534121");
+        assert_eq!(crc_hex(8193), "9608673ec37f45cb57c6cf5d3656b7ec");
+        assert_eq!(crc_hex(1 << 20), "82baf69f452fe62c4b7fc0807a84eb8f");
+    }
+
+    #[test]
+    fn fingerprint_mtime_round_trip() {
+        use base64::Engine;
+
+        for mtime in [0u64, 1, 255, 256, 1_700_000_000, i64::MAX as u64] {
+            let raw = serialize_fingerprint(&[0; 16], mtime);
+            let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&raw);
+            assert_eq!(fingerprint_mtime(&encoded), Some(mtime as i64));
+        }
+        let raw = serialize_fingerprint(&[0; 16], 1_700_000_000);
+        assert_eq!(
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw),
+            "AAAAAAAAAAAAAAAAAAAAAAQA8VNl"
+        );
+    }
+
+    fn ignore_file(dir: &str, text: &str) -> Arc<IgnoreFile> {
+        Arc::new(IgnoreFile {
+            dir: std::path::PathBuf::from(dir),
+            rules: IgnoreRule::parse_all(&format!("{}/.megaignore", dir), text).unwrap(),
+        })
+    }
+
+    fn ignored(files: &[Arc<IgnoreFile>], path: &str, is_dir: bool) -> Option<String> {
+        ignored_by(files, std::path::Path::new(path), is_dir).map(|rule| rule.source.clone())
+    }
+
+    #[test]
+    fn ignore_rules_follow_gitignore() {
+        let files = [ignore_file(
+            "/data",
+            "# comment\n\n*.tmp\nbuild/\n/top.txt\ndocs/**\n!docs/keep.md\n\\#hash\n",
+        )];
+        assert_eq!(
+            ignored(&files, "/data/a.tmp", false).as_deref(),
+            Some("/data/.megaignore:3")
+        );
+        assert_eq!(
+            ignored(&files, "/data/x/y/a.tmp", false).as_deref(),
+            Some("/data/.megaignore:3")
+        );
+        assert_eq!(
+            ignored(&files, "/data/x/build", true).as_deref(),
+            Some("/data/.megaignore:4")
+        );
+        assert_eq!(ignored(&files, "/data/x/build", false), None);
+        assert_eq!(
+            ignored(&files, "/data/top.txt", false).as_deref(),
+            Some("/data/.megaignore:5")
+        );
+        assert_eq!(ignored(&files, "/data/x/top.txt", false), None);
+        assert_eq!(ignored(&files, "/data/docs", true), None);
+        assert_eq!(
+            ignored(&files, "/data/docs/a.md", false).as_deref(),
+            Some("/data/.megaignore:6")
+        );
+        assert_eq!(ignored(&files, "/data/docs/keep.md", false), None);
+        assert_eq!(
+            ignored(&files, "/data/#hash", false).as_deref(),
+            Some("/data/.megaignore:8")
+        );
+    }
+
+    #[test]
+    fn nested_ignore_files_take_precedence() {
+        let files = [
+            ignore_file("/data", "*.log\n"),
+            ignore_file("/data/sub", "!keep.log\n"),
+        ];
+        assert!(ignored(&files, "/data/a.log", false).is_some());
+        assert!(ignored(&files, "/data/sub/a.log", false).is_some());
+        assert_eq!(ignored(&files, "/data/sub/keep.log", false), None);
+        // The nested file doesn't apply outside its directory.
+        assert!(ignored(&files, "/data/keep.log", false).is_some());
+    }
+
+    #[test]
+    fn ignore_pattern_errors_name_file_and_line() {
+        pyo3::prepare_freethreaded_python();
+        for (text, line) in [("ok\nbad\\\n", 2), ("a\nb\n!\n", 3), ("/\n", 1)] {
+            let Err(e) = IgnoreRule::parse_all("/data/.megaignore", text) else {
+                panic!("{:?} parsed", text);
+            };
+            Python::with_gil(|py| {
+                assert!(e.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+                let message = e.value(py).to_string();
+                let prefix = format!("/data/.megaignore:{}: ", line);
+                assert!(message.starts_with(&prefix), "{}", message);
+            });
+        }
+    }
+
+    fn fixture_node(name: &str, handle: &str, size: u64, timestamp: i64) -> MegaNode {
+        MegaNode {
+            name: name.to_string(),
+            handle: handle.to_string(),
+            location: NodeLocation::In(Arc::from("/Root")),
+            parent_handle: None,
+            size,
+            timestamp,
+            created_ts: timestamp,
+            modified_ts: timestamp,
+            is_file: true,
+            is_folder: false,
+            is_takendown: false,
+            owner_handle: None,
+            fav: false,
+            label: None,
+            is_shared: false,
+            share_count: 0,
+            is_exported: false,
+            mime_type: None,
+        }
+    }
+
+    /// A listing with duplicate names, sizes and timestamps, so every tie
+    /// breaker is exercised.
+    fn fixture_listing() -> Vec<MegaNode> {
+        vec![
+            fixture_node("b.txt", "H3", 10, 300),
+            fixture_node("a.txt", "H9", 30, 100),
+            fixture_node("B.txt", "H1", 20, 200),
+            fixture_node("b.txt", "H2", 10, 300),
+            fixture_node("c.txt", "H5", 30, 100),
+            fixture_node("a.txt", "H4", 20, 400),
+        ]
+    }
+
+    fn sorted(sort: &str, mut nodes: Vec<MegaNode>) -> Vec<String> {
+        NodeSort::parse(sort).unwrap().apply(&mut nodes);
+        nodes
+            .iter()
+            .map(|n| format!("{}:{}", n.name, n.handle))
+            .collect()
+    }
+
+    #[test]
+    fn list_order_golden() {
+        assert_eq!(
+            sorted("name", fixture_listing()),
+            ["B.txt:H1", "a.txt:H4", "a.txt:H9", "b.txt:H2", "b.txt:H3", "c.txt:H5"]
+        );
+        assert_eq!(
+            sorted("size", fixture_listing()),
+            ["b.txt:H2", "b.txt:H3", "B.txt:H1", "a.txt:H4", "a.txt:H9", "c.txt:H5"]
+        );
+        assert_eq!(
+            sorted("timestamp", fixture_listing()),
+            ["a.txt:H9", "c.txt:H5", "B.txt:H1", "b.txt:H2", "b.txt:H3", "a.txt:H4"]
+        );
+    }
+
+    #[test]
+    fn list_order_ignores_fetch_order() {
+        for sort in ["name", "size", "timestamp"] {
+            let expected = sorted(sort, fixture_listing());
+            for shift in 0..6 {
+                let mut nodes = fixture_listing();
+                nodes.rotate_left(shift);
+                assert_eq!(sorted(sort, nodes.clone()), expected);
+                nodes.reverse();
+                assert_eq!(sorted(sort, nodes), expected);
+            }
+        }
+    }
+
+    /// A tree with a read-only, a read-write and a full-access incoming
+    /// share, as share_access() of check_access() sees it.
+    fn share_tree(path: &str) -> Option<Option<i32>> {
+        match path {
+            "/Root" | "/Root/docs" => Some(None),
+            "/alice@example.com" => Some(None),
+            "/alice@example.com/ro" => Some(Some(0)),
+            "/alice@example.com/rw" => Some(Some(ACCESS_READ_WRITE)),
+            "/alice@example.com/full" => Some(Some(ACCESS_FULL)),
+            "/alice@example.com/ro/sub" | "/alice@example.com/rw/sub" => Some(None),
+            _ => None,
+        }
+    }
+
+    fn access_denied(path: &str, required: i32) -> bool {
+        pyo3::prepare_freethreaded_python();
+        match check_access(path, required, share_tree) {
+            Ok(()) => false,
+            Err(e) => Python::with_gil(|py| {
+                assert!(e.is_instance_of::<AccessDeniedError>(py));
+                true
+            }),
+        }
+    }
+
+    #[test]
+    fn read_only_share_is_rejected_locally() {
+        for path in [
+            "/alice@example.com/ro",
+            "/alice@example.com/ro/sub",
+            "/alice@example.com/ro/sub/new.txt",
+        ] {
+            assert!(access_denied(path, ACCESS_READ_WRITE), "{}", path);
+        }
+        assert!(!access_denied(
+            "/alice@example.com/rw/sub/new.txt",
+            ACCESS_READ_WRITE
+        ));
+        assert!(!access_denied("/Root/docs/new.txt", ACCESS_FULL));
+        for path in [
+            "/Vault",
+            "/Vault/",
+            "/Vault/backup",
+            "/Vault/backup/photo.jpg",
+        ] {
+            assert!(access_denied(path, ACCESS_READ_WRITE), "{}", path);
+        }
+        assert!(!access_denied("/Vaults/backup", ACCESS_READ_WRITE));
+    }
+
+    #[test]
+    fn rm_in_share_depends_on_owner() {
+        let me = "MEHANDLE1";
+        let own = rm_access(Some(me), me);
+        let theirs = rm_access(Some("ALICEHND"), me);
+        assert_eq!(own, ACCESS_READ_WRITE);
+        assert_eq!(theirs, ACCESS_FULL);
+        assert_eq!(rm_access(None, me), ACCESS_FULL);
+
+        // Your own node can go from a read-write share, the sharer's only
+        // from a full-access one.
+        assert!(!access_denied("/alice@example.com/rw/sub", own));
+        assert!(access_denied("/alice@example.com/rw/sub", theirs));
+        assert!(!access_denied("/alice@example.com/full/x", theirs));
+        assert!(access_denied("/alice@example.com/ro/sub", own));
+    }
+
+    /// Run `check` with `result` bound to `value` in a Python namespace,
+    /// DeprecationWarnings silenced.
+    fn check_shim(value: impl IntoPy<PyObject>, check: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = pyo3::types::PyDict::new(py);
+            locals.set_item("result", value.into_py(py)).unwrap();
+            py.run(
+                &format!(
+                    "import warnings\nwith warnings.catch_warnings():\n    \
+                     warnings.simplefilter('ignore', DeprecationWarning)\n    {}",
+                    check
+                ),
+                None,
+                Some(locals),
+            )
+            .unwrap();
+        });
+    }
+
+    const HASH_CHECK: &str = "assert result == legacy and hash(result) == hash(legacy) \
+         and legacy in {result} and result in {legacy}";
+
+    fn download_result(digest: Option<&str>) -> MegaDownloadResult {
+        MegaDownloadResult {
+            local_path: "a.bin".into(),
+            bytes_transferred: 1,
+            bytes_reused_from_resume: 0,
+            bytes_reverified: 0,
+            bytes_redownloaded: 0,
+            verified: true,
+            digest: digest.map(str::to_string),
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn legacy_results_hash_like_legacy_values() {
+        check_shim(
+            download_result(None),
+            &format!("legacy = 'Download complete'; {}", HASH_CHECK),
+        );
+        check_shim(
+            download_result(Some("abc123")),
+            &format!("legacy = 'abc123'; {}", HASH_CHECK),
+        );
+        check_shim(
+            MegaUploadResult {
+                node: None,
+                bytes: 1,
+                skipped: false,
+            },
+            &format!("legacy = 'Upload complete'; {}", HASH_CHECK),
+        );
+        check_shim(
+            MegaQuotaInfo {
+                total: 100,
+                used: 40,
+            },
+            &format!("legacy = (100, 40); {}", HASH_CHECK),
+        );
+    }
+
+    #[test]
+    fn legacy_results_stay_distinct_objects() {
+        check_shim(
+            MegaQuotaInfo {
+                total: 100,
+                used: 40,
+            },
+            "assert result != (100, 41) and result == result and len({result, result}) == 1",
+        );
+    }
+
+    #[test]
+    fn protected_link_round_trip() {
+        use base64::Engine;
+
+        pyo3::prepare_freethreaded_python();
+        let b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let (handle, key) = (b64.encode(sample(6)), b64.encode(sample(32)));
+        let url = format!("https://mega.nz/file/{}#{}", handle, key);
+        let legacy = format!("https://mega.nz/#!{}!{}", handle, key);
+        assert_eq!(file_link_key(&legacy), file_link_key(&url));
+
+        for link in [&url, &legacy] {
+            let protected = protect_file_link(link, "hunter2").unwrap();
+            let data = protected.strip_prefix("https://mega.nz/#P!").unwrap();
+            let raw = b64.decode(data).unwrap();
+            assert_eq!(raw.len(), 2 + 6 + 32 + 32 + 32);
+            assert_eq!(&raw[..2], &[2, 1]);
+
+            assert_eq!(
+                complete_link(&protected, None, Some("hunter2")).unwrap(),
+                url
+            );
+            let Err(e) = decrypt_protected_link(data, "hunter3") else {
+                panic!("wrong password accepted");
+            };
+            Python::with_gil(|py| {
+                assert!(e.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            });
+        }
+    }
+
+    fn transfer_item(path: &str, error_type: Option<&str>) -> MegaTransferItem {
+        let failed = error_type.is_some();
+        MegaTransferItem {
+            path: path.to_string(),
+            handle: (!failed).then(|| "H1".to_string()),
+            action: if failed { "failed" } else { "uploaded" }.to_string(),
+            bytes: if failed { 0 } else { 1234 },
+            detail: None,
+            error: error_type.map(|name| format!("{}: quota exceeded", name)),
+            error_type: error_type.map(str::to_string),
+            duration: 0.25,
+        }
+    }
+
+    #[test]
+    fn transfer_summary_round_trips_through_json() {
+        check_shim(
+            MegaTransferSummary {
+                items: vec![
+                    transfer_item("/data/a.bin", None),
+                    transfer_item("/data/b.bin", Some("OverQuotaError")),
+                ],
+            },
+            "import json; data = result.to_dict(); \
+             copy = type(result).from_dict(json.loads(json.dumps(data))); \
+             assert copy.to_dict() == data, copy.to_dict(); \
+             assert [item.error_type for item in copy.failed()] == ['OverQuotaError']",
+        );
+    }
+
+    #[test]
+    fn local_path_accepts_path_like_objects() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = pyo3::types::PyDict::new(py);
+            py.run(
+                "import pathlib\n\
+                 class PathLike:\n    def __fspath__(self):\n        return 'dir/p.bin'\n\
+                 path = pathlib.Path('x')\n\
+                 path_like = PathLike()\n\
+                 raw = b'\\xff.bin'\n",
+                None,
+                Some(locals),
+            )
+            .unwrap();
+            let extract = |name: &str| -> std::path::PathBuf {
+                let value = locals.get_item(name).unwrap().unwrap();
+                value.extract::<LocalPath>().unwrap().0
+            };
+            assert_eq!(extract("path"), std::path::PathBuf::from("x"));
+            assert_eq!(extract("path_like"), std::path::PathBuf::from("dir/p.bin"));
+            #[cfg(unix)]
+            assert_eq!(
+                extract("raw"),
+                std::path::PathBuf::from(
+                    <std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(b"\xff.bin")
+                )
+            );
+        });
+    }
+
+    #[test]
+    fn nodes_compare_and_hash_by_handle() {
+        check_shim(
+            (
+                fixture_node("a.txt", "H1", 10, 100),
+                fixture_node("renamed.txt", "H1", 20, 200),
+                fixture_node("a.txt", "H2", 10, 100),
+            ),
+            "a, renamed, other = result; \
+             assert a == renamed and hash(a) == hash(renamed) and a != other; \
+             assert len({a, renamed, other}) == 2 and {a: 1}[renamed] == 1; \
+             assert a.__eq__('H1') is NotImplemented and a.__lt__(renamed) is NotImplemented; \
+             assert a != 'H1' and a not in ['H1']",
+        );
+    }
+
+    #[test]
+    fn paced_transfer_waits_without_blocking_the_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let throttle = Arc::<Throttle>::default();
+            throttle.set_rate(10_000);
+            // A full bucket plus 2,000 bytes of debt: a 0.2 s pause.
+            let transfer = {
+                let throttle = throttle.clone();
+                async move {
+                    throttle.consume(12_000);
+                    tokio::task::yield_now().await;
+                }
+            };
+            let ticks = Arc::new(AtomicU64::new(0));
+            let ticker = {
+                let ticks = ticks.clone();
+                tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                        ticks.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            };
+            let start = std::time::Instant::now();
+            Paced::new(throttle.clone(), transfer).await;
+            let elapsed = start.elapsed();
+            ticker.abort();
+            assert!(
+                elapsed >= std::time::Duration::from_millis(150),
+                "{:?}",
+                elapsed
+            );
+            assert!(ticks.load(Ordering::Relaxed) >= 5);
+
+            // A call that transfers nothing doesn't wait for the debt.
+            throttle.consume(5_000);
+            let start = std::time::Instant::now();
+            Paced::new(throttle, tokio::task::yield_now()).await;
+            assert!(start.elapsed() < std::time::Duration::from_millis(100));
+        });
+    }
+}
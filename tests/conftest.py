@@ -0,0 +1,56 @@
+"""
+Fixtures for the tests that run against a MEGA account.
+
+Build the extension first (`maturin develop`), set credentials and run:
+    MEGA_EMAIL=user@example.com
+    MEGA_PASSWORD=yourpassword
+    pytest tests
+
+Without credentials these tests are skipped. Each test that changes the
+account works in a scratch folder /Root/megalib-test-<id>, which is deleted
+permanently afterwards.
+"""
+
+import asyncio
+import os
+import uuid
+
+import pytest
+
+import megalib
+
+
+def credentials():
+    email = os.environ.get("MEGA_EMAIL")
+    password = os.environ.get("MEGA_PASSWORD")
+    if not email or not password:
+        pytest.skip("set MEGA_EMAIL and MEGA_PASSWORD to run tests against an account")
+    return email, password
+
+
+@pytest.fixture(scope="session")
+def session():
+    email, password = credentials()
+    return asyncio.run(megalib.MegaSession.login(email, password))
+
+
+@pytest.fixture
+def scratch(session):
+    folder = f"/Root/megalib-test-{uuid.uuid4().hex[:12]}"
+    asyncio.run(session.mkdir(folder))
+    yield folder
+    asyncio.run(session.rm(folder, permanent=True))
+
+
+@pytest.fixture
+def local_file(tmp_path):
+    path = tmp_path / "sample.txt"
+    path.write_bytes(b"megalib test data\n")
+    return path
+
+
+@pytest.fixture(scope="session")
+def other_session():
+    """A second login to the same account, acting as another client."""
+    email, password = credentials()
+    return asyncio.run(megalib.MegaSession.login(email, password))
@@ -0,0 +1,69 @@
+import asyncio
+import os
+
+import pytest
+
+
+@pytest.fixture
+def big_file(tmp_path):
+    path = tmp_path / "big.bin"
+    path.write_bytes(os.urandom(8 * 1024 * 1024))
+    return path
+
+
+async def cancel_on_first_progress(start):
+    """Run start(progress) as a task, cancel it once data moves, and make
+    sure the session answers right after."""
+    loop = asyncio.get_running_loop()
+    moving = asyncio.Event()
+
+    def progress(done, total):
+        # Runs on a runtime thread.
+        if done:
+            loop.call_soon_threadsafe(moving.set)
+
+    task = asyncio.ensure_future(start(progress))
+    await asyncio.wait_for(moving.wait(), 60)
+    task.cancel()
+    with pytest.raises(asyncio.CancelledError):
+        await task
+
+
+def test_list_after_cancelled_download(session, scratch, big_file, tmp_path):
+    asyncio.run(session.upload(big_file, scratch))
+    remote = f"{scratch}/big.bin"
+    target = tmp_path / "download.bin"
+
+    async def run():
+        await cancel_on_first_progress(
+            lambda progress: session.download(remote, target, progress=progress)
+        )
+        # A transfer still holding the session would make this hang.
+        await asyncio.wait_for(session.list("/"), 10)
+
+    asyncio.run(run())
+    # Without resume the partial file is removed.
+    assert not target.exists()
+
+
+def test_list_after_cancelled_upload(session, scratch, big_file):
+    async def run():
+        await cancel_on_first_progress(
+            lambda progress: session.upload(big_file, scratch, progress=progress)
+        )
+        await asyncio.wait_for(session.list("/"), 10)
+
+    asyncio.run(run())
+
+
+def test_list_after_wait_for_timeout(session, scratch, big_file, tmp_path):
+    asyncio.run(session.upload(big_file, scratch))
+
+    async def run():
+        with pytest.raises(asyncio.TimeoutError):
+            await asyncio.wait_for(
+                session.download(f"{scratch}/big.bin", tmp_path / "download.bin"), 0.05
+            )
+        await asyncio.wait_for(session.list("/"), 10)
+
+    asyncio.run(run())
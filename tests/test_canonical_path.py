@@ -0,0 +1,18 @@
+import asyncio
+
+
+def test_canonical_path_is_a_fixed_point(session, scratch):
+    async def run():
+        folder = await session.mkdir(f"{scratch}/a")
+        paths = ["/", f"{scratch}//a/", folder.handle]
+        for root in await session.roots():
+            paths.append(root)
+            paths += [node.path for node in (await session.list(root))[:3] if node.path]
+
+        for path in paths:
+            canonical = await session.canonical_path(path)
+            assert await session.canonical_path(canonical) == canonical, path
+        assert await session.canonical_path(f"{scratch}//a/") == f"{scratch}/a"
+        assert await session.canonical_path(folder.handle) == f"{scratch}/a"
+
+    asyncio.run(run())
@@ -0,0 +1,47 @@
+import asyncio
+
+import pytest
+
+import megalib
+
+
+def test_concurrent_mkdir_returns_same_folder(session, other_session, scratch):
+    async def run():
+        await other_session.refresh()
+        path = f"{scratch}/shared"
+        return await asyncio.gather(
+            session.mkdir(path, exist_ok=True),
+            other_session.mkdir(path, exist_ok=True),
+        )
+
+    first, second = asyncio.run(run())
+    assert first.handle == second.handle
+
+
+def test_mv_already_at_target(session, other_session, scratch):
+    async def run():
+        node = await session.mkdir(f"{scratch}/item")
+        await session.mkdir(f"{scratch}/dest")
+        await other_session.refresh()
+        await other_session.mv(f"{scratch}/item", f"{scratch}/dest")
+
+        # This session's tree still has the folder at its old path.
+        await session.mv(f"{scratch}/item", f"{scratch}/dest")
+        await session.refresh()
+        moved = await session.stat(f"{scratch}/dest/item")
+        assert moved is not None and moved.handle == node.handle
+
+    asyncio.run(run())
+
+
+def test_mv_of_deleted_node(session, other_session, scratch):
+    async def run():
+        await session.mkdir(f"{scratch}/item")
+        await session.mkdir(f"{scratch}/dest")
+        await other_session.refresh()
+        await other_session.rm(f"{scratch}/item", permanent=True)
+
+        with pytest.raises(megalib.NotFoundError):
+            await session.mv(f"{scratch}/item", f"{scratch}/dest")
+
+    asyncio.run(run())
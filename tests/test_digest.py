@@ -0,0 +1,29 @@
+import asyncio
+import hashlib
+
+
+def test_download_to_file_digest_covers_resumed_prefix(session, scratch, tmp_path):
+    data = bytes(range(256)) * 4096
+    source = tmp_path / "source.bin"
+    source.write_bytes(data)
+    expected = hashlib.sha256(data).hexdigest()
+
+    async def run():
+        await session.upload(source, scratch)
+        target = tmp_path / "target.bin"
+        result = await session.download_to_file(f"{scratch}/source.bin", target, digest="sha256")
+        assert result.digest == expected
+
+        # A partial file from an earlier run is kept and still hashed.
+        target.write_bytes(data[: len(data) // 2])
+        await session.set_resume(True)
+        try:
+            result = await session.download_to_file(
+                f"{scratch}/source.bin", target, digest="sha256"
+            )
+        finally:
+            await session.set_resume(False)
+        assert result.bytes_reused_from_resume > 0
+        assert result.digest == expected
+
+    asyncio.run(run())
@@ -0,0 +1,47 @@
+import asyncio
+import threading
+
+import pytest
+
+import megalib
+
+
+def test_sequential_event_loops(session):
+    """Each asyncio.run() creates and closes its own loop; calls bind to
+    whichever loop is running."""
+    roots = []
+    for _ in range(3):
+        roots.append(asyncio.run(session.stat("/Root")).handle)
+    loop = asyncio.new_event_loop()
+    try:
+        roots.append(loop.run_until_complete(session.stat("/Root")).handle)
+    finally:
+        loop.close()
+    assert len(set(roots)) == 1
+
+
+def test_event_loop_in_new_thread(session):
+    result = []
+    thread = threading.Thread(target=lambda: result.append(asyncio.run(session.list("/"))))
+    thread.start()
+    thread.join(60)
+    assert result and isinstance(result[0], list)
+
+
+def test_uvloop(session):
+    uvloop = pytest.importorskip("uvloop")
+    data = asyncio.run(session.save_to_string())
+
+    async def run():
+        assert "uvloop" in type(asyncio.get_running_loop()).__module__
+        # The first await of a new session runs on this loop.
+        fresh = await megalib.MegaSession.load_from_string(data)
+        return await fresh.stat("/Root"), await session.stat("/Root")
+
+    for _ in range(2):
+        loop = uvloop.new_event_loop()
+        try:
+            fresh_root, root = loop.run_until_complete(run())
+        finally:
+            loop.close()
+        assert fresh_root.handle == root.handle
@@ -0,0 +1,17 @@
+"""Needs the built extension but no account."""
+
+import sys
+from pathlib import Path
+
+sys.path.insert(0, str(Path(__file__).resolve().parent.parent))
+
+import check_stubs  # noqa: E402
+import megalib  # noqa: E402
+
+
+def test_features_come_from_cargo_metadata():
+    assert megalib.features() == frozenset(check_stubs.FEATURE_SYMBOLS)
+
+
+def test_features_match_exported_symbols():
+    assert check_stubs.check_features() == []
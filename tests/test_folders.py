@@ -0,0 +1,93 @@
+import asyncio
+
+import pytest
+
+
+def make_tree(root):
+    (root / "sub").mkdir(parents=True)
+    (root / "a.bin").write_bytes(b"a" * 1000)
+    (root / "sub" / "b.bin").write_bytes(b"b" * 2500)
+    (root / "empty").mkdir()
+
+
+def check_progress(calls, total, files):
+    assert calls
+    assert all(call[1] == total and call[4] == files for call in calls)
+    done = [(call[0], call[3]) for call in calls]
+    assert done == sorted(done)
+    assert calls[-1][0] == total and calls[-1][3] == files
+
+
+def test_folder_progress(session, scratch, tmp_path):
+    """Both folder transfers report (bytes_done, bytes_total, path,
+    files_done, files_total) and end on the totals."""
+    source = tmp_path / "source"
+    make_tree(source)
+
+    uploads = []
+    summary = asyncio.run(
+        session.upload_folder(source, scratch, progress=lambda *args: uploads.append(args))
+    )
+    assert sorted(item.action for item in summary.items) == ["uploaded", "uploaded"]
+    check_progress(uploads, 3500, 2)
+    # Paths are below the canonical directory.
+    source = source.resolve()
+    assert {call[2] for call in uploads} <= {str(source / "a.bin"), str(source / "sub" / "b.bin")}
+
+    downloads = []
+    target = tmp_path / "target"
+    summary = asyncio.run(
+        session.download_folder(scratch, target, progress=lambda *args: downloads.append(args))
+    )
+    assert sorted(item.action for item in summary.items) == ["downloaded", "downloaded"]
+    check_progress(downloads, 3500, 2)
+    assert {call[2] for call in downloads} <= {f"{scratch}/a.bin", f"{scratch}/sub/b.bin"}
+    assert (target / "sub" / "b.bin").read_bytes() == b"b" * 2500
+    assert (target / "empty").is_dir()
+
+
+def test_upload_folder_ignore_files(session, scratch, tmp_path):
+    source = tmp_path / "source"
+    make_tree(source)
+    (source / ".megaignore").write_text("*.bin\n")
+    (source / "sub" / ".megaignore").write_text("!b.bin\n")
+
+    summary = asyncio.run(session.upload_folder(source, scratch, use_ignore_files=True))
+    actions = {item.path.rsplit("/", 1)[-1]: item for item in summary.items}
+    assert actions["a.bin"].action == "skipped"
+    assert actions["a.bin"].detail.endswith(".megaignore:1: *.bin")
+    assert actions["b.bin"].action == "uploaded"
+
+
+def test_upload_folder_bad_ignore_file(session, scratch, tmp_path):
+    source = tmp_path / "source"
+    make_tree(source)
+    (source / ".megaignore").write_text("# fine\nbad\\\n")
+
+    with pytest.raises(ValueError, match=r"\.megaignore:2: "):
+        asyncio.run(session.upload_folder(source, f"{scratch}/target", use_ignore_files=True))
+    assert asyncio.run(session.stat(f"{scratch}/target")) is None
+
+
+def test_cancel_upload_folder_keeps_partial_summary(session, scratch, tmp_path):
+    source = tmp_path / "source"
+    source.mkdir()
+    for i in range(5):
+        (source / f"{i}.bin").write_bytes(bytes([i]) * 100_000)
+
+    async def run():
+        loop = asyncio.get_running_loop()
+
+        def progress(bytes_done, bytes_total, path, files_done, files_total):
+            # Runs on a runtime thread.
+            if files_done == 2:
+                loop.call_soon_threadsafe(task.cancel)
+
+        task = asyncio.ensure_future(session.upload_folder(source, scratch, progress=progress))
+        with pytest.raises(asyncio.CancelledError) as info:
+            await task
+        return info.value.partial_summary
+
+    summary = asyncio.run(run())
+    assert 2 <= len(summary.ok()) < 5
+    assert all(item.action == "uploaded" for item in summary.items)
@@ -0,0 +1,16 @@
+import asyncio
+
+
+def test_root_lists_cloud_drive(session):
+    root = asyncio.run(session.list("/"))
+    drive = asyncio.run(session.list("/Root"))
+    assert [node.handle for node in root] == [node.handle for node in drive]
+    assert all(node.path.startswith("/Root/") for node in root)
+
+
+def test_list_is_sorted_by_name(session, scratch, tmp_path):
+    for name in ["b.txt", "A.txt", "a.txt"]:
+        (tmp_path / name).write_bytes(name.encode())
+        asyncio.run(session.upload(tmp_path / name, scratch))
+    names = [node.name for node in asyncio.run(session.list(scratch))]
+    assert names == ["A.txt", "a.txt", "b.txt"]
@@ -0,0 +1,82 @@
+"""
+Every mutating MegaSession method must raise ReadOnlyError on a read_only()
+view before doing anything. New methods have to be added to one of the
+tables below, so a mutating one can't slip through unguarded.
+"""
+
+import asyncio
+import inspect
+import io
+
+import pytest
+
+import megalib
+
+PATH = "/Root/megalib-read-only-check"
+HANDLE = "AAAAAAAA"
+
+MUTATING = {
+    "apply_node_update": ("{}",),
+    "change_password": ("new password",),
+    "create_path": (PATH + "/a/b",),
+    "empty_trash": (),
+    "export": (PATH,),
+    "export_protected": (PATH, "secret"),
+    "forget_node": (HANDLE,),
+    "mkdir": (PATH,),
+    "mv": (PATH, "/Root/megalib-read-only-other"),
+    "queue_upload": ("sample.txt", PATH),
+    "rename": (PATH, "renamed"),
+    "restore": (HANDLE,),
+    "revoke_all": (None, False),
+    "rm": (PATH,),
+    "set_favorite": (PATH, True),
+    "set_label": (PATH, 1),
+    "set_node_attr": (PATH, "key", "value"),
+    "set_raw_attribute": (PATH, "key", 1),
+    "share_folder": (PATH, "someone@example.com", 0),
+    "unexport": (PATH,),
+    "upload": ("sample.txt", PATH),
+    "upload_bytes": (b"data", PATH, "sample.txt"),
+    "upload_folder": (".", PATH),
+    "upload_resumable": ("sample.txt", PATH),
+    "upload_stream": (io.BytesIO(b"data"), PATH, "sample.txt"),
+}
+
+# Methods that only read, or change settings local to this object.
+NOT_MUTATING = {
+    "audit_exposure", "canonical_path", "create_ephemeral", "download", "download_bytes",
+    "download_folder", "download_to_file", "enable_previews", "find_by_fingerprint",
+    "get_email", "get_handle", "get_link", "get_name", "get_node_attr",
+    "get_raw_attributes", "glob", "is_partial_tree", "is_read_only", "list",
+    "list_contacts", "list_folders", "list_trash", "load", "load_from_string", "login",
+    "open_read", "queue_download", "queue_status", "quota", "read_only", "refresh",
+    "refresh_subtree", "remaining_storage", "reset_usage_stats", "resolve_user", "roots",
+    "run_queue", "save", "save_to_string", "search", "set_bandwidth_limit",
+    "set_queue_path", "set_resume", "set_temp_dir", "set_timeout", "set_workers", "stat",
+    "stat_many", "stat_many_by_handle", "storage_breakdown", "undecryptable_nodes",
+    "usage_stats",
+}
+
+
+def test_every_method_is_classified():
+    methods = {name for name in dir(megalib.MegaSession) if not name.startswith("_")}
+    assert not MUTATING.keys() & NOT_MUTATING
+    assert methods - MUTATING.keys() - NOT_MUTATING == set(), "classify new methods above"
+    assert MUTATING.keys() - methods == set()
+
+
+@pytest.mark.parametrize("name", sorted(MUTATING))
+def test_mutating_method_raises(session, name):
+    view = session.read_only()
+    with pytest.raises(megalib.ReadOnlyError):
+        result = getattr(view, name)(*MUTATING[name])
+        if inspect.isawaitable(result):
+            asyncio.run(result)
+
+
+def test_read_only_is_preserved(session):
+    view = session.read_only()
+    assert view.is_read_only
+    assert view.read_only().is_read_only
+    assert not session.is_read_only
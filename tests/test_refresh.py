@@ -0,0 +1,65 @@
+import asyncio
+import time
+
+import pytest
+
+import megalib
+
+
+def test_list_during_refresh(session):
+    """list() answers from the previous tree while refresh() runs."""
+
+    async def run():
+        await session.list("/Root")
+        refresh = asyncio.ensure_future(session.refresh())
+        await asyncio.sleep(0)
+
+        latencies = []
+        while not refresh.done():
+            start = time.perf_counter()
+            await session.list("/Root")
+            latencies.append(time.perf_counter() - start)
+            await asyncio.sleep(0.01)
+        await refresh
+        return latencies
+
+    latencies = asyncio.run(run())
+    if len(latencies) < 3:
+        pytest.skip("refresh() finished too quickly to overlap with list()")
+    # The last call may have waited for the swap at the end of the refresh.
+    assert max(latencies[:-1]) < 0.5
+
+
+def reload(session, **kwargs):
+    data = asyncio.run(session.save_to_string())
+    return asyncio.run(megalib.MegaSession.load_from_string(data, **kwargs))
+
+
+@pytest.mark.parametrize(
+    "call",
+    [
+        lambda s, tmp: s.list("/"),
+        lambda s, tmp: s.stat("/Root"),
+        lambda s, tmp: s.download("/Root/missing.bin", tmp / "missing.bin"),
+        lambda s, tmp: s.upload(tmp / "sample.txt", "/Root"),
+        lambda s, tmp: s.mkdir("/Root/megalib-never-created"),
+    ],
+    ids=["list", "stat", "download", "upload", "mkdir"],
+)
+def test_not_refreshed(session, local_file, call):
+    fresh = reload(session, auto_refresh=False)
+
+    async def run():
+        await call(fresh, local_file.parent)
+
+    with pytest.raises(megalib.NotRefreshedError, match=r"refresh\(\)"):
+        asyncio.run(run())
+
+    asyncio.run(fresh.refresh())
+    asyncio.run(fresh.list("/"))
+
+
+def test_auto_refresh(session):
+    fresh = reload(session)
+    names = [node.name for node in asyncio.run(fresh.list("/"))]
+    assert names == [node.name for node in asyncio.run(session.list("/"))]
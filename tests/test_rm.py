@@ -0,0 +1,56 @@
+import asyncio
+
+import pytest
+
+
+async def trash_path(session):
+    roots = await session.roots()
+    return next(path for path in roots if path not in ("/Root", "/Vault"))
+
+
+def test_rm_moves_to_rubbish_bin(session, scratch, local_file):
+    async def run():
+        result = await session.upload(local_file, scratch)
+        handle = result.node.handle
+        await session.rm(f"{scratch}/{local_file.name}")
+
+        await session.refresh()
+        trash = await trash_path(session)
+        assert await session.stat(f"{scratch}/{local_file.name}") is None
+        assert (await session.canonical_path(handle)).startswith(trash + "/")
+
+        restored = await session.restore(handle)
+        assert restored.path == f"{scratch}/{local_file.name}"
+
+    asyncio.run(run())
+
+
+def test_rm_in_rubbish_bin_is_noop(session, scratch, local_file):
+    async def run():
+        result = await session.upload(local_file, scratch)
+        handle = result.node.handle
+        await session.rm(f"{scratch}/{local_file.name}")
+        in_trash = await session.canonical_path(handle)
+
+        await session.rm(in_trash)
+        await session.refresh()
+        assert await session.canonical_path(handle) == in_trash
+        await session.rm(in_trash, permanent=True)
+
+    asyncio.run(run())
+
+
+def test_rm_permanent(session, scratch, local_file):
+    async def run():
+        result = await session.upload(local_file, scratch)
+        await session.rm(f"{scratch}/{local_file.name}", permanent=True)
+
+        await session.refresh()
+        assert await session.stat_many_by_handle([result.node.handle]) == [None]
+
+    asyncio.run(run())
+
+
+def test_rm_missing(session, scratch):
+    with pytest.raises(FileNotFoundError):
+        asyncio.run(session.rm(f"{scratch}/missing"))